@@ -0,0 +1,423 @@
+use crate::ir::{
+    Block, Comparison, Function, Module, Operation, Statement, Terminator, Type, Value,
+};
+
+use super::{elementwise, global_symbol, index, linear_algebra, math, reduction, symbol, RUNTIME};
+
+const BOILER_INCLUDES: &str = "#include <stdint.h>\n";
+
+/// Lowers a module to portable c, mostly useful where qbe isn't available.
+pub fn emit(module: &Module) -> String {
+    let globals = module
+        .globals
+        .iter()
+        .map(|global| {
+            format!(
+                "static {} {};\n",
+                c_type(&global.ty),
+                global_symbol(&global.name)
+            )
+        })
+        .collect::<String>();
+    let prototypes = module
+        .functions
+        .iter()
+        .map(|function| format!("{};\n", signature(function)))
+        .collect::<String>();
+    let functions = module
+        .functions
+        .iter()
+        .map(emit_function)
+        .collect::<Vec<String>>()
+        .join("\n");
+    return format!("{BOILER_INCLUDES}{RUNTIME}\n{globals}{prototypes}\n{functions}");
+}
+
+fn signature(function: &Function) -> String {
+    // c fixes the types of main's parameters
+    if function.name == "main" {
+        return "int main(int argc, char **argv)".to_string();
+    }
+    let params = if function.params.is_empty() {
+        "void".to_string()
+    } else {
+        function
+            .params
+            .iter()
+            .map(|param| format!("{} {}", c_type(&param.ty), param.name))
+            .collect::<Vec<String>>()
+            .join(", ")
+    };
+    let linkage = if function.export { "" } else { "static " };
+    format!(
+        "{linkage}{} {}({params})",
+        c_type(&function.return_type),
+        symbol(&function.name)
+    )
+}
+
+fn emit_function(function: &Function) -> String {
+    // c needs every local declared up front so jumps between blocks stay valid
+    let locals = function
+        .blocks
+        .iter()
+        .flat_map(|block| &block.statements)
+        .filter_map(|statement| statement.dest.as_ref())
+        .map(|dest| format!("\t{} {};\n", c_type(&dest.ty), dest.name))
+        .collect::<String>();
+    let blocks = function
+        .blocks
+        .iter()
+        .map(|block| emit_block(block, function))
+        .collect::<String>();
+    format!("{} {{\n{locals}{blocks}}}\n", signature(function))
+}
+
+fn emit_block(block: &Block, function: &Function) -> String {
+    let mut lines = vec![format!("L_{}:;", block.label)];
+    lines.extend(
+        block
+            .statements
+            .iter()
+            .filter(|statement| !matches!(statement.operation, Operation::Phi(_)))
+            .map(emit_statement),
+    );
+    // c has no phis, each predecessor assigns the value it passes on before jumping
+    for successor in &function.blocks {
+        for statement in &successor.statements {
+            let (Some(dest), Operation::Phi(incoming)) = (&statement.dest, &statement.operation)
+            else {
+                continue;
+            };
+            for (label, value) in incoming {
+                if *label == block.label {
+                    lines.push(format!("\t{} = {};", dest.name, emit_value(value)));
+                }
+            }
+        }
+    }
+    lines.push(match &block.terminator {
+        Terminator::Return(Some(value)) => format!("\treturn {};", emit_value(value)),
+        Terminator::Return(None) => "\treturn;".to_string(),
+        Terminator::Jump(label) => format!("\tgoto L_{label};"),
+        Terminator::Branch(cond, then, otherwise) => format!(
+            "\tif ({}) goto L_{then}; else goto L_{otherwise};",
+            emit_value(cond)
+        ),
+    });
+    lines.join("\n") + "\n"
+}
+
+fn emit_statement(statement: &Statement) -> String {
+    // a closure is filled in after it is allocated
+    if let (Some(dest), Operation::Closure(function, captures)) =
+        (&statement.dest, &statement.operation)
+    {
+        let mut lines = vec![
+            format!(
+                "\t{} = nm_closure_new(INT64_C({}));",
+                dest.name,
+                captures.len()
+            ),
+            format!(
+                "\t{}->function = (void (*)(void)){};",
+                dest.name,
+                symbol(function)
+            ),
+        ];
+        for (index, capture) in captures.iter().enumerate() {
+            lines.push(format!(
+                "\t{}->captures[{index}].{} = {};",
+                dest.name,
+                slot(&capture.ty()),
+                emit_value(capture)
+            ));
+        }
+        return lines.join("\n");
+    }
+    let expression = match &statement.operation {
+        Operation::Add(x, y)
+        | Operation::Sub(x, y)
+        | Operation::Mul(x, y)
+        | Operation::Div(x, y)
+        | Operation::Rem(x, y)
+        | Operation::Pow(x, y)
+            if x.ty() == Type::Vector || y.ty() == Type::Vector =>
+        {
+            let (function, code) = elementwise(&statement.operation, x, y);
+            format!("{function}({code}, {}, {})", emit_value(x), emit_value(y))
+        }
+        Operation::Copy(x) => emit_value(x),
+        // the runtime stops the program when an int overflows
+        Operation::Neg(x) if x.ty() == Type::Long => format!("nm_ineg({})", emit_value(x)),
+        Operation::Add(x, y) if x.ty() == Type::Long => checked("nm_iadd", x, y),
+        Operation::Sub(x, y) if x.ty() == Type::Long => checked("nm_isub", x, y),
+        Operation::Mul(x, y) if x.ty() == Type::Long => checked("nm_imul", x, y),
+        Operation::Div(x, y) if x.ty() == Type::Long => {
+            format!("nm_idiv({}, {})", emit_value(x), emit_value(y))
+        }
+        Operation::Rem(x, y) if x.ty() == Type::Long => {
+            format!("nm_irem({}, {})", emit_value(x), emit_value(y))
+        }
+        Operation::Pow(x, y) if x.ty() == Type::Long => {
+            format!("nm_ipow({}, {})", emit_value(x), emit_value(y))
+        }
+        Operation::Neg(x) => format!("-({})", emit_value(x)),
+        Operation::Add(x, y) => format!("{} + {}", emit_value(x), emit_value(y)),
+        Operation::Sub(x, y) => format!("{} - {}", emit_value(x), emit_value(y)),
+        Operation::Mul(x, y) => format!("{} * {}", emit_value(x), emit_value(y)),
+        Operation::Div(x, y) => format!("{} / {}", emit_value(x), emit_value(y)),
+        Operation::Rem(x, y) => format!("fmod({}, {})", emit_value(x), emit_value(y)),
+        Operation::Pow(x, y) => format!("pow({}, {})", emit_value(x), emit_value(y)),
+        Operation::IntToFloat(x) => format!("(double){}", emit_value(x)),
+        Operation::FloatToInt(x) => format!("nm_ftoi({})", emit_value(x)),
+        Operation::Compare(comparison, x, y) => {
+            let operator = match comparison {
+                Comparison::Equal => "==",
+                Comparison::NotEqual => "!=",
+                Comparison::Less => "<",
+                Comparison::LessEqual => "<=",
+                Comparison::Greater => ">",
+                Comparison::GreaterEqual => ">=",
+            };
+            format!("{} {operator} {}", emit_value(x), emit_value(y))
+        }
+        Operation::Not(x) => format!("!{}", emit_value(x)),
+        Operation::Phi(_) => unreachable!("phis are assigned by their predecessors"),
+        Operation::Vector(elements) => {
+            let args = elements
+                .iter()
+                .map(|element| format!(", {}", emit_value(element)))
+                .collect::<String>();
+            format!("nm_vector_of(INT64_C({}){args})", elements.len())
+        }
+        Operation::Matrix(rows) => {
+            let args = rows
+                .iter()
+                .map(|row| format!(", {}", emit_value(row)))
+                .collect::<String>();
+            format!("nm_matrix_of(INT64_C({}){args})", rows.len())
+        }
+        Operation::Index(x, i) => {
+            format!("{}({}, {})", index(x), emit_value(x), emit_value(i))
+        }
+        Operation::MatMul(..)
+        | Operation::Transpose(_)
+        | Operation::Det(_)
+        | Operation::Inverse(_)
+        | Operation::Solve(..) => {
+            let (function, args) = linear_algebra(&statement.operation);
+            let args = args
+                .into_iter()
+                .map(emit_value)
+                .collect::<Vec<String>>()
+                .join(", ");
+            format!("{function}({args})")
+        }
+        Operation::Length(x) => format!("nm_len({})", emit_value(x)),
+        Operation::Reduce(r, x) => format!("{}({})", reduction(*r), emit_value(x)),
+        Operation::Math(function, x) => format!("{}({})", math(*function), emit_value(x)),
+        Operation::Call(name, args) => {
+            let args = args
+                .iter()
+                .map(emit_value)
+                .collect::<Vec<String>>()
+                .join(", ");
+            format!("{}({args})", symbol(name))
+        }
+        Operation::Closure(..) => unreachable!("closures are filled in above"),
+        Operation::Capture(env, index) => {
+            let ty = statement
+                .dest
+                .as_ref()
+                .map_or(Type::Double, |d| d.ty.clone());
+            format!(
+                "({}){}->captures[{index}].{}",
+                c_type(&ty),
+                emit_value(env),
+                slot(&ty)
+            )
+        }
+        Operation::CallClosure(closure, args) => {
+            let Type::Function(params, result) = closure.ty() else {
+                unreachable!("{} is not a closure", closure)
+            };
+            let params = std::iter::once("nm_closure *")
+                .chain(params.iter().map(c_type))
+                .collect::<Vec<&str>>()
+                .join(", ");
+            let args = std::iter::once(closure)
+                .chain(args)
+                .map(emit_value)
+                .collect::<Vec<String>>()
+                .join(", ");
+            format!(
+                "(({} (*)({params})){}->function)({args})",
+                c_type(&result),
+                emit_value(closure)
+            )
+        }
+        Operation::Map(v, f) => format!("nm_map({}, {})", emit_value(v), emit_value(f)),
+        Operation::Print(x, _) if x.ty() == Type::Long => {
+            format!("nm_print_int({})", emit_value(x))
+        }
+        Operation::Print(x, _) if x.ty() == Type::Bool => {
+            format!("nm_print_bool({})", emit_value(x))
+        }
+        Operation::Print(x, format) if x.ty() == Type::Vector => {
+            let (kind, precision) = format.runtime_args();
+            format!("nm_print_vector({}, {kind}, {precision})", emit_value(x))
+        }
+        Operation::Print(x, format) if x.ty() == Type::Matrix => {
+            let (kind, precision) = format.runtime_args();
+            format!("nm_print_matrix({}, {kind}, {precision})", emit_value(x))
+        }
+        Operation::Print(x, format) => {
+            let (kind, precision) = format.runtime_args();
+            format!("nm_print({}, {kind}, {precision})", emit_value(x))
+        }
+        Operation::SetArgs(argc, argv) => {
+            format!("nm_set_args({}, {})", emit_value(argc), emit_value(argv))
+        }
+        Operation::Arg(n) => format!("nm_arg({})", emit_value(n)),
+        Operation::Input => "nm_input()".to_string(),
+        Operation::Load(name) => global_symbol(name),
+        Operation::Store(name, x) => format!("{} = {}", global_symbol(name), emit_value(x)),
+    };
+    match &statement.dest {
+        Some(dest) => format!("\t{} = {expression};", dest.name),
+        None => format!("\t{expression};"),
+    }
+}
+
+fn emit_value(value: &Value) -> String {
+    match value {
+        Value::Temp(temp) => temp.name.clone(),
+        Value::Double(x) if x.is_infinite() => "INFINITY".to_string(),
+        Value::Double(x) => format!("{x:?}"),
+        Value::Long(x) => format!("INT64_C({x})"),
+        Value::Word(x) => x.to_string(),
+        Value::Bool(x) => (*x as i32).to_string(),
+    }
+}
+
+fn checked(function: &str, x: &Value, y: &Value) -> String {
+    format!("{function}({}, {})", emit_value(x), emit_value(y))
+}
+
+fn c_type(ty: &Type) -> &'static str {
+    match ty {
+        Type::Word | Type::Bool => "int32_t",
+        Type::Long => "int64_t",
+        Type::Double => "double",
+        Type::Vector => "nm_vector *",
+        Type::Matrix => "nm_matrix *",
+        Type::Function(..) => "nm_closure *",
+    }
+}
+
+/// The member of `nm_slot` a captured value of type `ty` is kept in.
+fn slot(ty: &Type) -> &'static str {
+    match ty {
+        Type::Word | Type::Bool => "w",
+        Type::Long => "l",
+        Type::Double => "d",
+        Type::Vector | Type::Matrix | Type::Function(..) => "p",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{compiler::compile, format::PrintFormat, parser::parse};
+
+    #[test]
+    fn emits_functions_and_prototypes() {
+        let module = compile(parse("f(x) = x ^ 2\nf(3) / 2"), PrintFormat::default()).unwrap();
+        let c = emit(&module);
+        assert!(c.contains("static double nm_f(double x_0);"));
+        assert!(c.contains("\t_1 = pow(x_0, 2.0);"));
+        assert!(c.contains("int main(int argc, char **argv) {"));
+        assert!(c.contains("\t_2 = _1 / 2.0;"));
+        assert!(c.contains("\tnm_print(_2, 0, 4);"));
+    }
+
+    #[test]
+    fn emits_math_builtins() {
+        let module = compile(parse("ln(2) + sqrt(2.5)"), PrintFormat::default()).unwrap();
+        let c = emit(&module);
+        assert!(c.contains("\t_1 = log(2.0);"));
+        assert!(c.contains("\t_2 = sqrt(2.5);"));
+    }
+
+    #[test]
+    fn reads_args_and_input() {
+        let module = compile(parse("arg(2) + input()"), PrintFormat::default()).unwrap();
+        let c = emit(&module);
+        assert!(c.contains("\tnm_set_args(argc, argv);\n\t_1 = nm_arg(INT64_C(2));"));
+        assert!(c.contains("\t_2 = nm_input();"));
+    }
+
+    #[test]
+    fn checks_int_arithmetic() {
+        let module = compile(
+            parse(
+                "n = 3
+n * 2 + int(1.5)",
+            ),
+            PrintFormat::default(),
+        )
+        .unwrap();
+        let c = emit(&module);
+        assert!(c.contains("\tn_0 = INT64_C(3);"));
+        assert!(c.contains("\t_1 = nm_imul(n_0, INT64_C(2));"));
+        assert!(c.contains("\t_2 = nm_ftoi(1.5);"));
+        assert!(c.contains("\tnm_print_int(_3);"));
+    }
+
+    #[test]
+    fn assigns_phis_in_predecessors() {
+        let module = compile(parse("x = 2\nx > 3 or x != 2"), PrintFormat::default()).unwrap();
+        let c = emit(&module);
+        assert!(c.contains("\t_1 = x_0 > INT64_C(3);"));
+        assert!(c.contains("\t_3 = 1;\n\tif (_1) goto L_join_2; else goto L_or_1;"));
+        assert!(c.contains("\t_3 = _2;\n\tgoto L_join_2;"));
+        assert!(c.contains("\tnm_print_bool(_3);"));
+    }
+
+    #[test]
+    fn emits_vector_calls() {
+        let module = compile(parse("v = [1, 2]\n1 - v\nv[1]"), PrintFormat::default()).unwrap();
+        let c = emit(&module);
+        assert!(c.contains("\tnm_vector * _1;"));
+        assert!(c.contains("\t_1 = nm_vector_of(INT64_C(2), 1.0, 2.0);"));
+        assert!(c.contains("\t_2 = nm_sv(1, 1.0, v_0);"));
+        assert!(c.contains("\tnm_print_vector(_2, 0, 4);"));
+        assert!(c.contains("\t_3 = nm_index(v_0, INT64_C(1));"));
+    }
+
+    #[test]
+    fn emits_closures() {
+        let source = "n = 2\nf = x -> x * n\nf(3)\nmap([1], f)";
+        let module = compile(parse(source), PrintFormat::default()).unwrap();
+        let c = emit(&module);
+        assert!(c.contains("static double nml_1(nm_closure * env_0, double x_0);"));
+        assert!(c.contains("\tn_0 = (int64_t)env_0->captures[0].l;"));
+        assert!(c.contains("\t_1 = nm_closure_new(INT64_C(1));"));
+        assert!(c.contains("\t_1->function = (void (*)(void))nml_1;"));
+        assert!(c.contains("\t_1->captures[0].l = n_0;"));
+        assert!(c.contains("\t_2 = ((double (*)(nm_closure *, double))f_0->function)(f_0, 3.0);"));
+        assert!(c.contains("\t_4 = nm_map(_3, f_0);"));
+    }
+
+    #[test]
+    fn emits_matrix_calls() {
+        let source = "m = [[1, 2], [3, 4]]\nsolve(T(m), [1, 1])";
+        let module = compile(parse(source), PrintFormat::default()).unwrap();
+        let c = emit(&module);
+        assert!(c.contains("\tnm_matrix * m_0;"));
+        assert!(c.contains("\t_3 = nm_matrix_of(INT64_C(2), _1, _2);"));
+        assert!(c.contains("\t_4 = nm_transpose(m_0);"));
+        assert!(c.contains("\t_6 = nm_solve(_4, _5);"));
+    }
+}
@@ -0,0 +1,273 @@
+use anyhow::{bail, Context, Result};
+use clap::ValueEnum;
+use std::{
+    env, fs,
+    path::PathBuf,
+    process::Command,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use crate::ir::{MathFunction, Module, Operation, Reduction, Type, Value};
+
+pub mod c;
+pub mod qbe;
+
+/// Support code linked into every executable, printing lives here so both backends format
+/// numbers the same way.
+const RUNTIME: &str = include_str!("runtime.c");
+
+/// The toolchain used to turn the ir into a native executable.
+#[derive(ValueEnum, PartialEq, Debug, Clone, Copy)]
+pub enum Backend {
+    /// lower to qbe ssa, assemble with `qbe` and link with `cc`
+    Qbe,
+    /// lower to c and compile with `cc`
+    C,
+}
+
+/// Builds a native executable at `output` from the module.
+pub fn build_executable(
+    module: &Module,
+    backend: Backend,
+    target: &str,
+    output: &str,
+) -> Result<()> {
+    let mut intermediates = vec![];
+    let result = build(module, backend, target, output, &mut intermediates);
+    // the intermediate files are only needed while building, whether or not it worked
+    for path in intermediates {
+        let _ = fs::remove_file(path);
+    }
+    return result;
+}
+
+/// Writes and compiles the intermediate files, adding each one to `intermediates` before it is
+/// written.
+fn build(
+    module: &Module,
+    backend: Backend,
+    target: &str,
+    output: &str,
+    intermediates: &mut Vec<PathBuf>,
+) -> Result<()> {
+    // builds running at the same time each get their own files
+    static BUILDS: AtomicUsize = AtomicUsize::new(0);
+    let build = BUILDS.fetch_add(1, Ordering::Relaxed);
+    let stem = format!("numerus-{}-{build}", std::process::id());
+    let (source, extension) = match backend {
+        Backend::Qbe => (qbe::emit(module), "ssa"),
+        Backend::C => (c::emit(module), "c"),
+    };
+    let source_path = temp_path(&stem, extension);
+    intermediates.push(source_path.clone());
+    fs::write(&source_path, source).context("failed to write intermediate file")?;
+
+    // the c backend already has the runtime pasted in
+    let to_link = match backend {
+        Backend::Qbe => {
+            let asm_path = temp_path(&stem, "s");
+            intermediates.push(asm_path.clone());
+            run(Command::new("qbe")
+                .args(["-t", target, "-o"])
+                .arg(&asm_path)
+                .arg(&source_path))?;
+            let runtime_path = temp_path(&stem, "runtime.c");
+            intermediates.push(runtime_path.clone());
+            fs::write(&runtime_path, RUNTIME).context("failed to write runtime")?;
+            vec![asm_path, runtime_path]
+        }
+        Backend::C => vec![source_path],
+    };
+    run(Command::new("cc")
+        .args(&to_link)
+        .args(["-o", output, "-lm"]))
+}
+
+fn temp_path(stem: &str, extension: &str) -> PathBuf {
+    env::temp_dir().join(format!("{stem}.{extension}"))
+}
+
+fn run(command: &mut Command) -> Result<()> {
+    let program = command.get_program().to_string_lossy().to_string();
+    let status = command
+        .status()
+        .context(format!("failed to run {program}, is it installed?"))?;
+    if !status.success() {
+        bail!("{program} exited with {status}");
+    }
+    return Ok(());
+}
+
+/// The linker symbol for an ir function, user functions are prefixed so they can't collide
+/// with libc and lifted lambdas get a prefix of their own.
+fn symbol(function: &str) -> String {
+    if function == "main" {
+        function.to_string()
+    } else if let Some(number) = function.strip_prefix("lambda.") {
+        format!("nml_{number}")
+    } else {
+        format!("nm_{}", qualified(function))
+    }
+}
+
+/// The linker symbol for a global variable, prefixed differently from functions so a
+/// variable and a function can share a name.
+fn global_symbol(global: &str) -> String {
+    format!("nmg_{}", qualified(global))
+}
+
+/// A name from an imported file like `geo.dist` as part of a symbol, `geo0dist`. Names can't
+/// hold digits so it can't be the symbol of another name.
+fn qualified(name: &str) -> String {
+    name.replace('.', "0")
+}
+
+/// The runtime function and operator code computing arithmetic with a vector element by
+/// element, the operator codes follow `nm_operate` in the runtime.
+fn elementwise(operation: &Operation, x: &Value, y: &Value) -> (&'static str, i32) {
+    let code = match operation {
+        Operation::Add(..) => 0,
+        Operation::Sub(..) => 1,
+        Operation::Mul(..) => 2,
+        Operation::Div(..) => 3,
+        Operation::Rem(..) => 4,
+        _ => 5,
+    };
+    let function = match (x.ty(), y.ty()) {
+        (Type::Vector, Type::Vector) => "nm_vv",
+        (Type::Vector, _) => "nm_vs",
+        _ => "nm_sv",
+    };
+    return (function, code);
+}
+
+/// The runtime function taking an element of a vector or a row of a matrix.
+fn index(x: &Value) -> &'static str {
+    match x.ty() {
+        Type::Matrix => "nm_row",
+        _ => "nm_index",
+    }
+}
+
+/// The runtime function computing a reduction of a vector.
+fn reduction(reduction: Reduction) -> &'static str {
+    match reduction {
+        Reduction::Sum => "nm_sum",
+        Reduction::Mean => "nm_mean",
+        Reduction::Min => "nm_min",
+        Reduction::Max => "nm_max",
+    }
+}
+
+/// The function of the c math library computing an elementary function.
+fn math(function: MathFunction) -> &'static str {
+    match function {
+        MathFunction::Sqrt => "sqrt",
+        MathFunction::Exp => "exp",
+        MathFunction::Ln => "log",
+        MathFunction::Sin => "sin",
+        MathFunction::Cos => "cos",
+    }
+}
+
+/// The runtime function and arguments computing `@` or one of the linear algebra builtins.
+fn linear_algebra(operation: &Operation) -> (&'static str, Vec<&Value>) {
+    let call = match operation {
+        Operation::MatMul(x, y) => {
+            let function = match (x.ty(), y.ty()) {
+                (Type::Matrix, Type::Matrix) => "nm_mm",
+                (Type::Matrix, _) => "nm_mv",
+                (_, Type::Matrix) => "nm_vm",
+                _ => "nm_dot",
+            };
+            (function, vec![x, y])
+        }
+        Operation::Transpose(x) => ("nm_transpose", vec![x]),
+        Operation::Det(x) => ("nm_det", vec![x]),
+        Operation::Inverse(x) => ("nm_inv", vec![x]),
+        Operation::Solve(a, b) => ("nm_solve", vec![a, b]),
+        _ => unreachable!("{operation} is not a matrix operation"),
+    };
+    return call;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{compiler::compile, format::PrintFormat, interp, parser::parse};
+    use std::{
+        io::{self, Write},
+        process::{Output, Stdio},
+    };
+
+    /// Builds the module with the c backend and runs it, `None` when there is no c compiler.
+    fn run_compiled(module: &Module, name: &str, args: &[String], stdin: &str) -> Option<Output> {
+        if Command::new("cc").arg("--version").output().is_err() {
+            return None;
+        }
+        let output = temp_path(
+            &format!("numerus-test-{}-{name}", std::process::id()),
+            "out",
+        );
+        build_executable(module, Backend::C, "amd64_sysv", output.to_str().unwrap()).unwrap();
+        let mut child = Command::new(&output)
+            .args(args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .unwrap();
+        child
+            .stdin
+            .take()
+            .unwrap()
+            .write_all(stdin.as_bytes())
+            .unwrap();
+        let compiled = child.wait_with_output().unwrap();
+        fs::remove_file(output).unwrap();
+        return Some(compiled);
+    }
+
+    #[test]
+    fn fails_at_runtime_like_the_interpreter() {
+        let cases = [
+            ("arg(1) * 2\narg(2)", vec!["3"], ""),
+            ("arg(1)", vec!["abc"], ""),
+            ("arg(1)", vec!["0x10"], ""),
+            ("arg(1)", vec!["3 "], ""),
+            ("arg(1)", vec![" 3"], ""),
+            ("input()", vec![], "nan(1)"),
+            ("input()\ninput()", vec![], "1.5 abc"),
+            ("input()", vec![], ""),
+            ("1\n0x7FFF_FFFF_FFFF_FFFF + 1", vec![], ""),
+        ];
+        for (source, args, stdin) in cases {
+            let module = compile(parse(source), PrintFormat::default()).unwrap();
+            let args: Vec<String> = args.into_iter().map(str::to_string).collect();
+            let mut interpreted = vec![];
+            let error =
+                interp::run(&module, &args, &mut stdin.as_bytes(), &mut interpreted).unwrap_err();
+            let Some(compiled) = run_compiled(&module, "fails", &args, stdin) else {
+                return;
+            };
+            assert_eq!(compiled.status.code(), Some(1), "{source}");
+            assert_eq!(compiled.stdout, interpreted, "{source}");
+            let stderr = String::from_utf8(compiled.stderr).unwrap();
+            assert_eq!(stderr, format!("{}\n", interp::runtime_error(&error)));
+        }
+    }
+
+    #[test]
+    fn prints_long_numbers_like_the_interpreter() {
+        let source = "1.0\n-1e300\n[0.5, 2]";
+        for format in ["fixed:600", "fixed:300", "sci:40", "exact"] {
+            let module = compile(parse(source), format.parse().unwrap()).unwrap();
+            let mut interpreted = vec![];
+            interp::run(&module, &[], &mut io::empty(), &mut interpreted).unwrap();
+            let Some(compiled) = run_compiled(&module, "prints", &[], "") else {
+                return;
+            };
+            assert_eq!(compiled.stdout, interpreted, "{format}");
+        }
+    }
+}
@@ -0,0 +1,414 @@
+use crate::ir::{
+    Block, Comparison, Function, Module, Operation, Statement, Temp, Terminator, Type, Value,
+};
+
+use super::{elementwise, global_symbol, index, linear_algebra, math, reduction, symbol};
+
+/// Lowers a module to the textual ssa accepted by qbe.
+pub fn emit(module: &Module) -> String {
+    let data = module
+        .globals
+        .iter()
+        .map(|global| {
+            format!(
+                "data ${} = {{ {} 0 }}\n",
+                global_symbol(&global.name),
+                global.ty
+            )
+        })
+        .collect::<String>();
+    let functions = module
+        .functions
+        .iter()
+        .map(emit_function)
+        .collect::<Vec<String>>()
+        .join("\n");
+    if data.is_empty() {
+        return functions;
+    }
+    return format!("{data}\n{functions}");
+}
+
+fn emit_function(function: &Function) -> String {
+    let export = if function.export { "export " } else { "" };
+    let params = function
+        .params
+        .iter()
+        .map(|param| format!("{} %{}", param.ty, param.name))
+        .collect::<Vec<String>>()
+        .join(", ");
+    let blocks = function
+        .blocks
+        .iter()
+        .map(emit_block)
+        .collect::<Vec<String>>()
+        .join("");
+    format!(
+        "{export}function {} ${}({params}) {{\n{blocks}}}\n",
+        function.return_type,
+        symbol(&function.name)
+    )
+}
+
+fn emit_block(block: &Block) -> String {
+    let mut lines = vec![format!("@{}", block.label)];
+    lines.extend(block.statements.iter().map(emit_statement));
+    lines.push(match &block.terminator {
+        Terminator::Return(Some(value)) => format!("\tret {}", emit_value(value)),
+        Terminator::Return(None) => "\tret".to_string(),
+        Terminator::Jump(label) => format!("\tjmp @{label}"),
+        Terminator::Branch(cond, then, otherwise) => {
+            format!("\tjnz {}, @{then}, @{otherwise}", emit_value(cond))
+        }
+    });
+    lines.join("\n") + "\n"
+}
+
+fn emit_statement(statement: &Statement) -> String {
+    if let Some(lines) = statement
+        .dest
+        .as_ref()
+        .and_then(|dest| emit_closure(dest, &statement.operation))
+    {
+        return lines;
+    }
+    let operation = match &statement.operation {
+        Operation::Add(x, y)
+        | Operation::Sub(x, y)
+        | Operation::Mul(x, y)
+        | Operation::Div(x, y)
+        | Operation::Rem(x, y)
+        | Operation::Pow(x, y)
+            if x.ty() == Type::Vector || y.ty() == Type::Vector =>
+        {
+            let (function, code) = elementwise(&statement.operation, x, y);
+            format!(
+                "call ${function}(w {code}, {}, {})",
+                emit_arg(x),
+                emit_arg(y)
+            )
+        }
+        Operation::Copy(x) => format!("copy {}", emit_value(x)),
+        // the runtime stops the program when an int overflows
+        Operation::Neg(x) if x.ty() == Type::Long => format!("call $nm_ineg({})", emit_arg(x)),
+        Operation::Add(x, y) if x.ty() == Type::Long => {
+            format!("call $nm_iadd({}, {})", emit_arg(x), emit_arg(y))
+        }
+        Operation::Sub(x, y) if x.ty() == Type::Long => {
+            format!("call $nm_isub({}, {})", emit_arg(x), emit_arg(y))
+        }
+        Operation::Mul(x, y) if x.ty() == Type::Long => {
+            format!("call $nm_imul({}, {})", emit_arg(x), emit_arg(y))
+        }
+        Operation::Neg(x) => format!("neg {}", emit_value(x)),
+        Operation::Add(x, y) => format!("add {}, {}", emit_value(x), emit_value(y)),
+        Operation::Sub(x, y) => format!("sub {}, {}", emit_value(x), emit_value(y)),
+        Operation::Mul(x, y) => format!("mul {}, {}", emit_value(x), emit_value(y)),
+        Operation::Div(x, y) if x.ty() == Type::Long => {
+            format!("call $nm_idiv({}, {})", emit_arg(x), emit_arg(y))
+        }
+        Operation::Div(x, y) => format!("div {}, {}", emit_value(x), emit_value(y)),
+        Operation::Rem(x, y) if x.ty() == Type::Long => {
+            format!("call $nm_irem({}, {})", emit_arg(x), emit_arg(y))
+        }
+        Operation::Rem(x, y) => format!("call $fmod({}, {})", emit_arg(x), emit_arg(y)),
+        Operation::Pow(x, y) if x.ty() == Type::Long => {
+            format!("call $nm_ipow({}, {})", emit_arg(x), emit_arg(y))
+        }
+        Operation::Pow(x, y) => format!("call $pow({}, {})", emit_arg(x), emit_arg(y)),
+        Operation::IntToFloat(x) => format!("sltof {}", emit_value(x)),
+        Operation::FloatToInt(x) => format!("call $nm_ftoi({})", emit_arg(x)),
+        Operation::Compare(comparison, x, y) => {
+            // ordered comparisons of ints are signed
+            let signed = match comparison {
+                Comparison::Equal | Comparison::NotEqual => "",
+                _ if x.ty() == Type::Long => "s",
+                _ => "",
+            };
+            format!(
+                "c{signed}{comparison}{} {}, {}",
+                x.ty(),
+                emit_value(x),
+                emit_value(y)
+            )
+        }
+        Operation::Not(x) => format!("ceqw {}, 0", emit_value(x)),
+        Operation::Phi(incoming) => {
+            let incoming = incoming
+                .iter()
+                .map(|(label, value)| format!("@{label} {}", emit_value(value)))
+                .collect::<Vec<String>>()
+                .join(", ");
+            format!("phi {incoming}")
+        }
+        // the elements are passed as the variadic arguments of the runtime function
+        Operation::Vector(elements) => {
+            let args = std::iter::once(format!("l {}", elements.len()))
+                .chain(std::iter::once("...".to_string()))
+                .chain(elements.iter().map(emit_arg))
+                .collect::<Vec<String>>()
+                .join(", ");
+            format!("call $nm_vector_of({args})")
+        }
+        Operation::Matrix(rows) => {
+            let args = std::iter::once(format!("l {}", rows.len()))
+                .chain(std::iter::once("...".to_string()))
+                .chain(rows.iter().map(emit_arg))
+                .collect::<Vec<String>>()
+                .join(", ");
+            format!("call $nm_matrix_of({args})")
+        }
+        Operation::Index(x, i) => {
+            format!("call ${}({}, {})", index(x), emit_arg(x), emit_arg(i))
+        }
+        Operation::MatMul(..)
+        | Operation::Transpose(_)
+        | Operation::Det(_)
+        | Operation::Inverse(_)
+        | Operation::Solve(..) => {
+            let (function, args) = linear_algebra(&statement.operation);
+            let args = args
+                .into_iter()
+                .map(emit_arg)
+                .collect::<Vec<String>>()
+                .join(", ");
+            format!("call ${function}({args})")
+        }
+        Operation::Length(x) => format!("call $nm_len({})", emit_arg(x)),
+        Operation::Reduce(r, x) => format!("call ${}({})", reduction(*r), emit_arg(x)),
+        Operation::Math(function, x) => format!("call ${}({})", math(*function), emit_arg(x)),
+        Operation::Call(name, args) => {
+            let args = args
+                .iter()
+                .map(emit_arg)
+                .collect::<Vec<String>>()
+                .join(", ");
+            format!("call ${}({args})", symbol(name))
+        }
+        Operation::Map(v, f) => format!("call $nm_map({}, {})", emit_arg(v), emit_arg(f)),
+        Operation::SetArgs(argc, argv) => {
+            format!("call $nm_set_args({}, {})", emit_arg(argc), emit_arg(argv))
+        }
+        Operation::Arg(n) => format!("call $nm_arg({})", emit_arg(n)),
+        Operation::Input => "call $nm_input()".to_string(),
+        Operation::Closure(..) | Operation::Capture(..) | Operation::CallClosure(..) => {
+            unreachable!("{} has no destination", statement.operation)
+        }
+        Operation::Print(x, _) if x.ty() == Type::Long => {
+            format!("call $nm_print_int({})", emit_arg(x))
+        }
+        Operation::Print(x, _) if x.ty() == Type::Bool => {
+            format!("call $nm_print_bool({})", emit_arg(x))
+        }
+        Operation::Print(x, format) if x.ty() == Type::Vector => {
+            let (kind, precision) = format.runtime_args();
+            format!(
+                "call $nm_print_vector({}, w {kind}, w {precision})",
+                emit_arg(x)
+            )
+        }
+        Operation::Print(x, format) if x.ty() == Type::Matrix => {
+            let (kind, precision) = format.runtime_args();
+            format!(
+                "call $nm_print_matrix({}, w {kind}, w {precision})",
+                emit_arg(x)
+            )
+        }
+        Operation::Print(x, format) => {
+            let (kind, precision) = format.runtime_args();
+            format!("call $nm_print({}, w {kind}, w {precision})", emit_arg(x))
+        }
+        Operation::Load(name) => {
+            let ty = statement
+                .dest
+                .as_ref()
+                .map_or(Type::Double, |d| d.ty.clone());
+            format!("load{ty} ${}", global_symbol(name))
+        }
+        Operation::Store(name, x) => {
+            format!(
+                "store{} {}, ${}",
+                x.ty(),
+                emit_value(x),
+                global_symbol(name)
+            )
+        }
+    };
+    match &statement.dest {
+        Some(dest) => format!("\t%{} ={} {operation}", dest.name, dest.ty),
+        None => format!("\t{operation}"),
+    }
+}
+
+/// The operations on closures, which take several instructions. A closure is a pointer to
+/// its function followed by one 8 byte slot for each captured value, the temporaries in
+/// between are named after the destination.
+fn emit_closure(dest: &Temp, operation: &Operation) -> Option<String> {
+    let name = &dest.name;
+    let lines = match operation {
+        Operation::Closure(function, captures) => {
+            let mut lines = vec![
+                format!("\t%{name} =l call $nm_closure_new(l {})", captures.len()),
+                format!("\tstorel ${}, %{name}", symbol(function)),
+            ];
+            for (index, capture) in captures.iter().enumerate() {
+                lines.push(format!(
+                    "\t%{name}.{index} =l add %{name}, {}",
+                    8 * (index + 1)
+                ));
+                lines.push(format!(
+                    "\tstore{} {}, %{name}.{index}",
+                    capture.ty(),
+                    emit_value(capture)
+                ));
+            }
+            lines
+        }
+        Operation::Capture(env, index) => vec![
+            format!(
+                "\t%{name}.addr =l add {}, {}",
+                emit_value(env),
+                8 * (index + 1)
+            ),
+            format!("\t%{name} ={} load{} %{name}.addr", dest.ty, dest.ty),
+        ],
+        Operation::CallClosure(closure, args) => {
+            let args = std::iter::once(closure)
+                .chain(args)
+                .map(emit_arg)
+                .collect::<Vec<String>>()
+                .join(", ");
+            vec![
+                format!("\t%{name}.fn =l loadl {}", emit_value(closure)),
+                format!("\t%{name} ={} call %{name}.fn({args})", dest.ty),
+            ]
+        }
+        _ => return None,
+    };
+    return Some(lines.join("\n"));
+}
+
+fn emit_arg(value: &Value) -> String {
+    format!("{} {}", value.ty(), emit_value(value))
+}
+
+fn emit_value(value: &Value) -> String {
+    match value {
+        Value::Temp(temp) => format!("%{}", temp.name),
+        Value::Double(x) => format!("d_{x:?}"),
+        Value::Long(x) => x.to_string(),
+        Value::Word(x) => x.to_string(),
+        Value::Bool(x) => (*x as i32).to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{compiler::compile, format::PrintFormat, parser::parse};
+
+    #[test]
+    fn emits_prefixed_operands() {
+        let module = compile(parse("x = 2.0\nx + 3.0 ^ x"), PrintFormat::default()).unwrap();
+        let ssa = emit(&module);
+        assert!(ssa.contains("%x_0 =d copy d_2.0"));
+        assert!(ssa.contains("%_1 =d call $pow(d d_3.0, d %x_0)"));
+        assert!(ssa.contains("%_2 =d add %x_0, %_1"));
+        assert!(ssa.contains("call $nm_print(d %_2, w 0, w 4)"));
+    }
+
+    #[test]
+    fn reads_args_and_input() {
+        let module = compile(parse("arg(2) + input()"), PrintFormat::default()).unwrap();
+        let ssa = emit(&module);
+        assert!(ssa.contains("\tcall $nm_set_args(w %argc, l %argv)\n\t%_1 =d call $nm_arg(l 2)"));
+        assert!(ssa.contains("\t%_2 =d call $nm_input()"));
+    }
+
+    #[test]
+    fn emits_user_functions() {
+        let module = compile(parse("f(x) = x * 2\nf(1)"), PrintFormat::default()).unwrap();
+        let ssa = emit(&module);
+        assert!(ssa.contains("function d $nm_f(d %x_0) {"));
+        assert!(ssa.contains("export function w $main(w %argc, l %argv) {"));
+        assert!(ssa.contains("%_1 =d call $nm_f(d d_1.0)"));
+    }
+
+    #[test]
+    fn emits_globals() {
+        let source = "rate = 0.5\ninterest(p) = p * rate\nrate = 2.0\ninterest(1)";
+        let module = compile(parse(source), PrintFormat::default()).unwrap();
+        let ssa = emit(&module);
+        assert!(ssa.starts_with("data $nmg_rate = { d 0 }\n"));
+        assert!(ssa.contains("%rate_0 =d loadd $nmg_rate"));
+        assert!(ssa.contains("stored %rate_0, $nmg_rate"));
+        assert!(ssa.contains("stored %rate_1, $nmg_rate"));
+    }
+
+    #[test]
+    fn emits_int_operations() {
+        let module = compile(
+            parse(
+                "n = 7
+n / 2 % 3
+n * 0.5
+n + 1",
+            ),
+            PrintFormat::default(),
+        )
+        .unwrap();
+        let ssa = emit(&module);
+        assert!(ssa.contains("%n_0 =l copy 7"));
+        assert!(ssa.contains("%_1 =l call $nm_idiv(l %n_0, l 2)"));
+        assert!(ssa.contains("%_2 =l call $nm_irem(l %_1, l 3)"));
+        assert!(ssa.contains("call $nm_print_int(l %_2)"));
+        assert!(ssa.contains("%_3 =d sltof %n_0"));
+        assert!(ssa.contains("%_5 =l call $nm_iadd(l %n_0, l 1)"));
+    }
+
+    #[test]
+    fn emits_short_circuits() {
+        let module = compile(parse("x = 2\nx < 3 and not x == 1"), PrintFormat::default()).unwrap();
+        let ssa = emit(&module);
+        assert!(ssa.contains("%_1 =w csltl %x_0, 3"));
+        assert!(ssa.contains("\tjnz %_1, @and_1, @join_2"));
+        assert!(ssa.contains("%_3 =w ceqw %_2, 0"));
+        assert!(ssa.contains("%_4 =w phi @start 0, @and_1 %_3"));
+        assert!(ssa.contains("call $nm_print_bool(w %_4)"));
+    }
+
+    #[test]
+    fn emits_vector_calls() {
+        let module = compile(parse("v = [1, 2.5]\nsum(v * 2)\nv"), PrintFormat::default()).unwrap();
+        let ssa = emit(&module);
+        assert!(ssa.contains("%_1 =l call $nm_vector_of(l 2, ..., d d_1.0, d d_2.5)"));
+        assert!(ssa.contains("%_2 =l call $nm_vs(w 2, l %v_0, d d_2.0)"));
+        assert!(ssa.contains("%_3 =d call $nm_sum(l %_2)"));
+        assert!(ssa.contains("call $nm_print_vector(l %v_0, w 0, w 4)"));
+    }
+
+    #[test]
+    fn emits_closures() {
+        let source = "n = 2\nf = x -> x * n\nf(3)\nmap([1], f)";
+        let module = compile(parse(source), PrintFormat::default()).unwrap();
+        let ssa = emit(&module);
+        assert!(ssa.contains("function d $nml_1(l %env_0, d %x_0) {"));
+        assert!(ssa.contains("\t%n_0.addr =l add %env_0, 8\n\t%n_0 =l loadl %n_0.addr"));
+        assert!(ssa.contains("\t%_1 =l call $nm_closure_new(l 1)\n\tstorel $nml_1, %_1"));
+        assert!(ssa.contains("\t%_1.0 =l add %_1, 8\n\tstorel %n_0, %_1.0"));
+        assert!(ssa.contains("\t%_2.fn =l loadl %f_0\n\t%_2 =d call %_2.fn(l %f_0, d d_3.0)"));
+        assert!(ssa.contains("call $nm_map(l %_3, l %f_0)"));
+    }
+
+    #[test]
+    fn emits_matrix_calls() {
+        let source = "m = [[1, 2], [3, 4]]\ndet(m)\nm[0] @ inv(m)\nm";
+        let module = compile(parse(source), PrintFormat::default()).unwrap();
+        let ssa = emit(&module);
+        assert!(ssa.contains("%_3 =l call $nm_matrix_of(l 2, ..., l %_1, l %_2)"));
+        assert!(ssa.contains("%_4 =d call $nm_det(l %m_0)"));
+        assert!(ssa.contains("%_5 =l call $nm_row(l %m_0, l 0)"));
+        assert!(ssa.contains("%_7 =l call $nm_vm(l %_5, l %_6)"));
+        assert!(ssa.contains("call $nm_print_matrix(l %m_0, w 0, w 4)"));
+    }
+}
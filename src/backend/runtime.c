@@ -0,0 +1,592 @@
+#include <ctype.h>
+#include <inttypes.h>
+#include <math.h>
+#include <stdarg.h>
+#include <stdint.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+
+void nm_print_bool(int x) {
+	puts(x ? "true" : "false");
+}
+
+/* writes one number in full however long it is, kind and precision come from
+ * PrintFormat::runtime_args */
+static void nm_write(double x, int kind, int precision) {
+	char buf[32];
+	switch (kind) {
+	case 0:
+		printf("%.*f", precision, x);
+		break;
+	case 1:
+		printf("%.*e", precision, x);
+		break;
+	case 2:
+		printf("%g", x);
+		break;
+	case 3:
+		printf("%a", x);
+		break;
+	default:
+		/* the shortest precision that reads back as the same double */
+		for (int p = 1; p <= 17; p++) {
+			snprintf(buf, sizeof buf, "%.*g", p, x);
+			if (strtod(buf, NULL) == x)
+				break;
+		}
+		fputs(buf, stdout);
+	}
+}
+
+/* prints one expression result */
+void nm_print(double x, int kind, int precision) {
+	nm_write(x, kind, precision);
+	putchar('\n');
+}
+
+/* ints are exact, so they print in full whatever the format */
+void nm_print_int(int64_t x) {
+	printf("%" PRId64 "\n", x);
+}
+
+static void nm_fail(const char *message) {
+	fprintf(stderr, "error: %s\n", message);
+	exit(1);
+}
+
+static int nm_argc;
+static char **nm_argv;
+
+/* keeps the command line for nm_arg, main calls it first */
+void nm_set_args(int argc, char **argv) {
+	nm_argc = argc;
+	nm_argv = argv;
+}
+
+/* reads the whole of text as a number, anything left over makes it not one. strtod also
+ * reads leading spaces, hex and `nan(...)`, which the interpreter doesn't, so those aren't
+ * numbers either */
+static int nm_number(const char *text, double *x) {
+	char *end;
+	const char *digits = text + (*text == '+' || *text == '-');
+	if (isspace((unsigned char)*text) || (digits[0] == '0' && (digits[1] == 'x' || digits[1] == 'X')) ||
+	    strchr(text, '('))
+		return 0;
+	*x = strtod(text, &end);
+	return end != text && *end == '\0';
+}
+
+/* the nth command line argument, counting from 1 like argv */
+double nm_arg(int64_t n) {
+	char message[512];
+	double x;
+	if (n < 1 || n >= nm_argc) {
+		int given = nm_argc - 1;
+		snprintf(message, sizeof message, "`arg(%" PRId64 ")` is missing, the program was given %d argument%s",
+			 n, given, given == 1 ? "" : "s");
+		nm_fail(message);
+	}
+	if (!nm_number(nm_argv[n], &x)) {
+		snprintf(message, sizeof message, "`arg(%" PRId64 ")` is `%s`, which is not a number", n, nm_argv[n]);
+		nm_fail(message);
+	}
+	return x;
+}
+
+/* the next number on stdin, numbers are separated by whitespace */
+double nm_input(void) {
+	char word[256], message[512];
+	double x;
+	if (scanf("%255s", word) != 1)
+		nm_fail("`input()` found no number left on stdin");
+	if (!nm_number(word, &x)) {
+		snprintf(message, sizeof message, "`input()` read `%s`, which is not a number", word);
+		nm_fail(message);
+	}
+	return x;
+}
+
+static void nm_overflow(const char *operator) {
+	char message[64];
+	snprintf(message, sizeof message, "integer overflow in `%s`", operator);
+	nm_fail(message);
+}
+
+/* ints are exact, a result that doesn't fit in one stops the program instead of wrapping */
+int64_t nm_iadd(int64_t x, int64_t y) {
+	int64_t result;
+	if (__builtin_add_overflow(x, y, &result))
+		nm_overflow("+");
+	return result;
+}
+
+int64_t nm_isub(int64_t x, int64_t y) {
+	int64_t result;
+	if (__builtin_sub_overflow(x, y, &result))
+		nm_overflow("-");
+	return result;
+}
+
+int64_t nm_imul(int64_t x, int64_t y) {
+	int64_t result;
+	if (__builtin_mul_overflow(x, y, &result))
+		nm_overflow("*");
+	return result;
+}
+
+int64_t nm_ineg(int64_t x) {
+	int64_t result;
+	if (__builtin_sub_overflow(0, x, &result))
+		nm_overflow("-");
+	return result;
+}
+
+int64_t nm_idiv(int64_t x, int64_t y) {
+	if (y == 0)
+		nm_fail("integer division by zero");
+	if (y == -1 && x == INT64_MIN)
+		nm_overflow("/");
+	return x / y;
+}
+
+int64_t nm_irem(int64_t x, int64_t y) {
+	if (y == 0)
+		nm_fail("integer division by zero");
+	if (y == -1)
+		return 0;
+	return x % y;
+}
+
+/* a negative exponent truncates 1 / x^-y like integer division does */
+int64_t nm_ipow(int64_t x, int64_t y) {
+	if (y < 0) {
+		if (x == 0)
+			nm_fail("integer division by zero");
+		if (x == 1 || x == -1)
+			return y % 2 == 0 ? 1 : x;
+		return 0;
+	}
+	/* squaring the base overflowing only matters when a later bit of y multiplies it in */
+	int64_t base = x, result = 1;
+	while (y > 0) {
+		if ((y & 1) && __builtin_mul_overflow(result, base, &result))
+			nm_overflow("^");
+		y >>= 1;
+		if (y > 0 && __builtin_mul_overflow(base, base, &base))
+			nm_overflow("^");
+	}
+	return result;
+}
+
+/* drops the fraction, saturates out of range values and turns nan into 0 */
+int64_t nm_ftoi(double x) {
+	if (isnan(x))
+		return 0;
+	if (x >= 9223372036854775808.0)
+		return INT64_MAX;
+	if (x < -9223372036854775808.0)
+		return INT64_MIN;
+	return (int64_t)x;
+}
+
+/* vectors live on the heap and are never freed, a program only runs for a moment */
+typedef struct {
+	int64_t len;
+	double data[];
+} nm_vector;
+
+static nm_vector *nm_alloc(int64_t len) {
+	nm_vector *v = malloc(sizeof(nm_vector) + len * sizeof(double));
+	if (v == NULL)
+		nm_fail("out of memory");
+	v->len = len;
+	return v;
+}
+
+nm_vector *nm_vector_of(int64_t len, ...) {
+	nm_vector *v = nm_alloc(len);
+	va_list elements;
+	va_start(elements, len);
+	for (int64_t i = 0; i < len; i++)
+		v->data[i] = va_arg(elements, double);
+	va_end(elements);
+	return v;
+}
+
+double nm_index(nm_vector *v, int64_t i) {
+	if (i < 0 || i >= v->len) {
+		char message[128];
+		snprintf(message, sizeof message,
+			 "index %" PRId64 " is out of range for a vector of length %" PRId64, i, v->len);
+		nm_fail(message);
+	}
+	return v->data[i];
+}
+
+int64_t nm_len(nm_vector *v) {
+	return v->len;
+}
+
+/* op is the operator's position in + - * / % ^ */
+static double nm_operate(int op, double x, double y) {
+	switch (op) {
+	case 0:
+		return x + y;
+	case 1:
+		return x - y;
+	case 2:
+		return x * y;
+	case 3:
+		return x / y;
+	case 4:
+		return fmod(x, y);
+	default:
+		return pow(x, y);
+	}
+}
+
+nm_vector *nm_vv(int op, nm_vector *x, nm_vector *y) {
+	if (x->len != y->len) {
+		char message[128];
+		snprintf(message, sizeof message,
+			 "vectors of length %" PRId64 " and %" PRId64 " can't be combined", x->len, y->len);
+		nm_fail(message);
+	}
+	nm_vector *v = nm_alloc(x->len);
+	for (int64_t i = 0; i < v->len; i++)
+		v->data[i] = nm_operate(op, x->data[i], y->data[i]);
+	return v;
+}
+
+nm_vector *nm_vs(int op, nm_vector *x, double y) {
+	nm_vector *v = nm_alloc(x->len);
+	for (int64_t i = 0; i < v->len; i++)
+		v->data[i] = nm_operate(op, x->data[i], y);
+	return v;
+}
+
+nm_vector *nm_sv(int op, double x, nm_vector *y) {
+	nm_vector *v = nm_alloc(y->len);
+	for (int64_t i = 0; i < v->len; i++)
+		v->data[i] = nm_operate(op, x, y->data[i]);
+	return v;
+}
+
+double nm_sum(nm_vector *v) {
+	double sum = 0;
+	for (int64_t i = 0; i < v->len; i++)
+		sum += v->data[i];
+	return sum;
+}
+
+static void nm_nonempty(nm_vector *v, const char *name) {
+	if (v->len == 0) {
+		char message[64];
+		snprintf(message, sizeof message, "%s of an empty vector", name);
+		nm_fail(message);
+	}
+}
+
+double nm_mean(nm_vector *v) {
+	nm_nonempty(v, "mean");
+	return nm_sum(v) / v->len;
+}
+
+double nm_min(nm_vector *v) {
+	nm_nonempty(v, "min");
+	double min = v->data[0];
+	for (int64_t i = 1; i < v->len; i++)
+		min = fmin(min, v->data[i]);
+	return min;
+}
+
+double nm_max(nm_vector *v) {
+	nm_nonempty(v, "max");
+	double max = v->data[0];
+	for (int64_t i = 1; i < v->len; i++)
+		max = fmax(max, v->data[i]);
+	return max;
+}
+
+static void nm_write_elements(const double *data, int64_t len, int kind, int precision) {
+	putchar('[');
+	for (int64_t i = 0; i < len; i++) {
+		if (i > 0)
+			fputs(", ", stdout);
+		nm_write(data[i], kind, precision);
+	}
+	putchar(']');
+}
+
+void nm_print_vector(nm_vector *v, int kind, int precision) {
+	nm_write_elements(v->data, v->len, kind, precision);
+	putchar('\n');
+}
+
+/* matrices are stored row by row, the elimination steps match linalg.rs in the compiler */
+typedef struct {
+	int64_t rows;
+	int64_t cols;
+	double data[];
+} nm_matrix;
+
+static nm_matrix *nm_alloc_matrix(int64_t rows, int64_t cols) {
+	nm_matrix *m = malloc(sizeof(nm_matrix) + rows * cols * sizeof(double));
+	if (m == NULL)
+		nm_fail("out of memory");
+	m->rows = rows;
+	m->cols = cols;
+	return m;
+}
+
+static nm_matrix *nm_copy_matrix(nm_matrix *x) {
+	nm_matrix *m = nm_alloc_matrix(x->rows, x->cols);
+	for (int64_t i = 0; i < x->rows * x->cols; i++)
+		m->data[i] = x->data[i];
+	return m;
+}
+
+/* describes a matrix, or a vector when rows is negative, the way the compiler does */
+static void nm_shape(char *buf, size_t size, int64_t rows, int64_t cols) {
+	if (rows < 0)
+		snprintf(buf, size, "a vector of length %" PRId64, cols);
+	else
+		snprintf(buf, size, "a %" PRId64 "x%" PRId64 " matrix", rows, cols);
+}
+
+static void nm_mismatch(const char *name, int64_t xrows, int64_t xcols, int64_t yrows, int64_t ycols) {
+	char x[64], y[64], message[192];
+	nm_shape(x, sizeof x, xrows, xcols);
+	nm_shape(y, sizeof y, yrows, ycols);
+	snprintf(message, sizeof message, "`%s` can't combine %s with %s", name, x, y);
+	nm_fail(message);
+}
+
+static void nm_check_square(nm_matrix *m, const char *name) {
+	if (m->rows != m->cols) {
+		char message[128];
+		snprintf(message, sizeof message,
+			 "`%s` needs a square matrix but found a %" PRId64 "x%" PRId64 " one", name,
+			 m->rows, m->cols);
+		nm_fail(message);
+	}
+}
+
+nm_matrix *nm_matrix_of(int64_t rows, ...) {
+	va_list args;
+	va_start(args, rows);
+	nm_vector **vectors = malloc(rows * sizeof(nm_vector *));
+	for (int64_t i = 0; i < rows; i++)
+		vectors[i] = va_arg(args, nm_vector *);
+	va_end(args);
+	int64_t cols = rows > 0 ? vectors[0]->len : 0;
+	nm_matrix *m = nm_alloc_matrix(rows, cols);
+	for (int64_t i = 0; i < rows; i++) {
+		if (vectors[i]->len != cols) {
+			char message[128];
+			snprintf(message, sizeof message,
+				 "the rows of a matrix have different lengths, %" PRId64 " and %" PRId64, cols,
+				 vectors[i]->len);
+			nm_fail(message);
+		}
+		for (int64_t j = 0; j < cols; j++)
+			m->data[i * cols + j] = vectors[i]->data[j];
+	}
+	free(vectors);
+	return m;
+}
+
+nm_vector *nm_row(nm_matrix *m, int64_t i) {
+	if (i < 0 || i >= m->rows) {
+		char message[128];
+		snprintf(message, sizeof message,
+			 "index %" PRId64 " is out of range for a matrix with %" PRId64 " rows", i, m->rows);
+		nm_fail(message);
+	}
+	nm_vector *v = nm_alloc(m->cols);
+	for (int64_t j = 0; j < m->cols; j++)
+		v->data[j] = m->data[i * m->cols + j];
+	return v;
+}
+
+static void nm_multiply(double *product, const double *x, const double *y, int64_t rows, int64_t inner,
+			int64_t cols) {
+	for (int64_t i = 0; i < rows; i++)
+		for (int64_t j = 0; j < cols; j++) {
+			double sum = 0;
+			for (int64_t k = 0; k < inner; k++)
+				sum += x[i * inner + k] * y[k * cols + j];
+			product[i * cols + j] = sum;
+		}
+}
+
+nm_matrix *nm_mm(nm_matrix *x, nm_matrix *y) {
+	if (x->cols != y->rows)
+		nm_mismatch("@", x->rows, x->cols, y->rows, y->cols);
+	nm_matrix *m = nm_alloc_matrix(x->rows, y->cols);
+	nm_multiply(m->data, x->data, y->data, x->rows, x->cols, y->cols);
+	return m;
+}
+
+nm_vector *nm_mv(nm_matrix *x, nm_vector *y) {
+	if (x->cols != y->len)
+		nm_mismatch("@", x->rows, x->cols, -1, y->len);
+	nm_vector *v = nm_alloc(x->rows);
+	nm_multiply(v->data, x->data, y->data, x->rows, x->cols, 1);
+	return v;
+}
+
+nm_vector *nm_vm(nm_vector *x, nm_matrix *y) {
+	if (x->len != y->rows)
+		nm_mismatch("@", -1, x->len, y->rows, y->cols);
+	nm_vector *v = nm_alloc(y->cols);
+	nm_multiply(v->data, x->data, y->data, 1, x->len, y->cols);
+	return v;
+}
+
+double nm_dot(nm_vector *x, nm_vector *y) {
+	if (x->len != y->len)
+		nm_mismatch("@", -1, x->len, -1, y->len);
+	double dot;
+	nm_multiply(&dot, x->data, y->data, 1, x->len, 1);
+	return dot;
+}
+
+nm_matrix *nm_transpose(nm_matrix *x) {
+	nm_matrix *m = nm_alloc_matrix(x->cols, x->rows);
+	for (int64_t i = 0; i < x->rows; i++)
+		for (int64_t j = 0; j < x->cols; j++)
+			m->data[j * x->rows + i] = x->data[i * x->cols + j];
+	return m;
+}
+
+/* the row at or below k with the largest magnitude in column k */
+static int64_t nm_pivot(const double *a, int64_t n, int64_t k) {
+	int64_t p = k;
+	for (int64_t i = k + 1; i < n; i++)
+		if (fabs(a[i * n + k]) > fabs(a[p * n + k]))
+			p = i;
+	return p;
+}
+
+static void nm_swap_rows(double *a, int64_t cols, int64_t i, int64_t j) {
+	for (int64_t c = 0; c < cols; c++) {
+		double t = a[i * cols + c];
+		a[i * cols + c] = a[j * cols + c];
+		a[j * cols + c] = t;
+	}
+}
+
+double nm_det(nm_matrix *x) {
+	nm_check_square(x, "det");
+	int64_t n = x->rows;
+	nm_matrix *a = nm_copy_matrix(x);
+	double det = 1;
+	for (int64_t k = 0; k < n; k++) {
+		int64_t p = nm_pivot(a->data, n, k);
+		if (a->data[p * n + k] == 0)
+			return 0;
+		if (p != k) {
+			nm_swap_rows(a->data, n, p, k);
+			det = -det;
+		}
+		det *= a->data[k * n + k];
+		for (int64_t i = k + 1; i < n; i++) {
+			double factor = a->data[i * n + k] / a->data[k * n + k];
+			for (int64_t j = k; j < n; j++)
+				a->data[i * n + j] -= factor * a->data[k * n + j];
+		}
+	}
+	return det;
+}
+
+/* solves a x = b for every column of the n x m matrix b in place */
+static void nm_eliminate(const char *name, nm_matrix *matrix, double *x, int64_t m) {
+	int64_t n = matrix->rows;
+	nm_matrix *a = nm_copy_matrix(matrix);
+	for (int64_t k = 0; k < n; k++) {
+		int64_t p = nm_pivot(a->data, n, k);
+		if (a->data[p * n + k] == 0) {
+			char message[64];
+			snprintf(message, sizeof message, "`%s` of a singular matrix", name);
+			nm_fail(message);
+		}
+		nm_swap_rows(a->data, n, p, k);
+		nm_swap_rows(x, m, p, k);
+		for (int64_t i = k + 1; i < n; i++) {
+			double factor = a->data[i * n + k] / a->data[k * n + k];
+			for (int64_t j = k; j < n; j++)
+				a->data[i * n + j] -= factor * a->data[k * n + j];
+			for (int64_t j = 0; j < m; j++)
+				x[i * m + j] -= factor * x[k * m + j];
+		}
+	}
+	for (int64_t i = n - 1; i >= 0; i--)
+		for (int64_t j = 0; j < m; j++) {
+			double sum = x[i * m + j];
+			for (int64_t k = i + 1; k < n; k++)
+				sum -= a->data[i * n + k] * x[k * m + j];
+			x[i * m + j] = sum / a->data[i * n + i];
+		}
+}
+
+nm_matrix *nm_inv(nm_matrix *x) {
+	nm_check_square(x, "inv");
+	nm_matrix *m = nm_alloc_matrix(x->rows, x->rows);
+	for (int64_t i = 0; i < x->rows; i++)
+		for (int64_t j = 0; j < x->rows; j++)
+			m->data[i * x->rows + j] = i == j;
+	nm_eliminate("inv", x, m->data, x->rows);
+	return m;
+}
+
+nm_vector *nm_solve(nm_matrix *a, nm_vector *b) {
+	nm_check_square(a, "solve");
+	if (a->rows != b->len)
+		nm_mismatch("solve", a->rows, a->cols, -1, b->len);
+	nm_vector *x = nm_alloc(b->len);
+	for (int64_t i = 0; i < b->len; i++)
+		x->data[i] = b->data[i];
+	nm_eliminate("solve", a, x->data, 1);
+	return x;
+}
+
+void nm_print_matrix(nm_matrix *m, int kind, int precision) {
+	putchar('[');
+	for (int64_t i = 0; i < m->rows; i++) {
+		if (i > 0)
+			printf(", ");
+		nm_write_elements(m->data + i * m->cols, m->cols, kind, precision);
+	}
+	puts("]");
+}
+
+/* one value captured by a closure, the compiled code knows which member it holds */
+typedef union {
+	int64_t l;
+	double d;
+	int32_t w;
+	void *p;
+} nm_slot;
+
+/* a lambda's function, which takes the closure before its own arguments, and the values it captured */
+typedef struct {
+	void (*function)(void);
+	nm_slot captures[];
+} nm_closure;
+
+nm_closure *nm_closure_new(int64_t count) {
+	nm_closure *c = malloc(sizeof(nm_closure) + count * sizeof(nm_slot));
+	if (c == NULL)
+		nm_fail("out of memory");
+	return c;
+}
+
+nm_vector *nm_map(nm_vector *x, nm_closure *f) {
+	double (*function)(nm_closure *, double) = (double (*)(nm_closure *, double))f->function;
+	nm_vector *v = nm_alloc(x->len);
+	for (int64_t i = 0; i < v->len; i++)
+		v->data[i] = function(f, x->data[i]);
+	return v;
+}
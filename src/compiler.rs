@@ -1,245 +1,1779 @@
-use anyhow::Result;
+use anyhow::{Context, Result};
 use std::{
-    collections::HashMap,
-    fmt::{self},
+    collections::{HashMap, HashSet, VecDeque},
+    fmt,
 };
 
 use crate::{
-    error::CompileError,
-    parser::{self, ParseToken},
+    error::{CompileError, Diagnostic},
+    format::PrintFormat,
+    infer,
+    ir::{
+        Block, Comparison, Function, Global, MathFunction, Module, Operation, Reduction, Statement,
+        Temp, Terminator, Type, Value,
+    },
+    parser::{self, Accumulator, ParseToken, Parsed},
 };
 
-const BOILER_FMT: &str = "data $fmt = { b \"%2.4f\n\", b 0 }\n";
-const BOILER_POW: &str = "export function $pow(d $x, d $y) d\n";
+/// Lowers parsed statements into the ir, user functions become their own ir functions and
+/// everything else is run in order from `main`. Expressions are printed with `format` until a
+/// `#fmt` directive changes it. A statement that fails to compile is reported and skipped, the
+/// errors from parsing are returned along with these.
+pub fn compile(parsed: Parsed, format: PrintFormat) -> Result<Module, Vec<Diagnostic>> {
+    let mut compiler = Compiler::new(format);
+    compiler.poisoned.extend(parsed.failed_declarations);
+    let mut diagnostics = parsed.diagnostics;
+    let statements = parsed.statements.into_iter().zip(parsed.spans);
+    for ((statement, span), file) in statements.zip(parsed.files) {
+        if let Err(error) = compiler.statement(statement) {
+            diagnostics.push(Diagnostic {
+                file,
+                ..Diagnostic::new(error, span)
+            });
+        }
+    }
+    if !diagnostics.is_empty() {
+        return Err(diagnostics);
+    }
+    return Ok(compiler.finish());
+}
+
+/// Warns about assignments whose name is never used by another statement, including the ones
+/// that failed to parse. Names starting with `_` and the declarations of imported files are
+/// left alone.
+pub fn lint(parsed: &Parsed) -> Vec<Diagnostic> {
+    let mut used: HashSet<&str> = parsed.failed_uses.iter().map(String::as_str).collect();
+    for statement in &parsed.statements {
+        let tokens = match statement {
+            parser::Statement::Declaration(declaration) => &declaration.body,
+            parser::Statement::Expression(expr) => expr,
+            parser::Statement::Format(_) | parser::Statement::Import(_) => continue,
+        };
+        for token in tokens {
+            if let ParseToken::Identifier(name) | ParseToken::Call(name, _) = token {
+                used.insert(name);
+            }
+        }
+    }
+    parsed
+        .statements
+        .iter()
+        .zip(&parsed.spans)
+        .zip(&parsed.files)
+        .filter_map(|((statement, span), file)| match statement {
+            parser::Statement::Declaration(declaration)
+                if file.is_none()
+                    && !used.contains(declaration.name.as_str())
+                    && !declaration.name.starts_with('_') =>
+            {
+                let warning = CompileError::UnusedDeclaration(declaration.name.clone());
+                Some(Diagnostic::warning(warning, *span))
+            }
+            _ => None,
+        })
+        .collect()
+}
 
-fn compile(statements: Vec<parser::Statement>) -> Result<String> {
-    let mut main_func = Function::new_main();
-    let mut functions: Vec<Function> = vec![];
-    let mut varcounter = VariableCounter::new();
+/// Compiles a program one statement at a time, so an error can be reported and compiling
+/// carry on with the statements after it.
+struct Compiler {
+    format: PrintFormat,
+    main: Body,
+    functions: Vec<Function>,
+    varcounter: VariableCounter,
+    /// names whose declaration failed, using one is not reported as another error
+    poisoned: HashSet<String>,
+    /// variables read by a function, main stores every assignment to them
+    globals: Vec<Global>,
+    lambdas: Lambdas,
+}
 
-    for statement in statements {
+impl Compiler {
+    fn new(format: PrintFormat) -> Self {
+        Compiler {
+            format,
+            main: Body::new(),
+            functions: vec![],
+            varcounter: VariableCounter::new(),
+            poisoned: HashSet::new(),
+            globals: vec![],
+            lambdas: Lambdas::default(),
+        }
+    }
+
+    fn statement(&mut self, statement: parser::Statement) -> Result<()> {
+        let declared = match &statement {
+            parser::Statement::Declaration(declaration) => Some(declaration.name.clone()),
+            _ => None,
+        };
+        let result = self.lower(statement);
+        if let Err(error) = &result {
+            // a loop the statement failed in doesn't hide variables from the next one
+            self.varcounter.locals.clear();
+            // only names that failed before this statement are skipped, a declaration that
+            // reads its own name is reported
+            let poisoned_use = matches!(
+                error.downcast_ref(),
+                Some(CompileError::NameError(name)) if self.poisoned.contains(name)
+            );
+            if let Some(name) = declared {
+                self.poisoned.insert(name);
+            }
+            if poisoned_use {
+                return Ok(());
+            }
+        }
+        return result;
+    }
+
+    fn lower(&mut self, statement: parser::Statement) -> Result<()> {
         match statement {
+            parser::Statement::Declaration(declaration) if !declaration.function => {
+                if self.functions.iter().any(|f| f.name == declaration.name) {
+                    return Err(CompileError::FunctionAndVariable(declaration.name).into());
+                }
+                let value = compile_expr(
+                    declaration.body,
+                    &self.functions,
+                    &mut self.varcounter,
+                    &mut self.main,
+                    &mut self.lambdas,
+                )?;
+                let var = self
+                    .varcounter
+                    .next_var(declaration.name.clone(), value.ty());
+                let shape = self.varcounter.shape(&value);
+                self.varcounter.set_shape(&var, shape);
+                self.main
+                    .push(Statement::assign(var.clone(), Operation::Copy(value)));
+                if let Some(global) = self.global(&declaration.name).map(|g| g.ty.clone()) {
+                    // an int stored in a float global is promoted, any other change of type is an error
+                    let value = match (global, &var.ty) {
+                        (Type::Double, Type::Long) => {
+                            to_double(Value::Temp(var), &mut self.varcounter, &mut self.main)
+                        }
+                        (global, ty) if global != *ty => {
+                            let error =
+                                CompileError::GlobalType(declaration.name, global, ty.clone());
+                            return Err(error.into());
+                        }
+                        _ => Value::Temp(var),
+                    };
+                    self.main
+                        .push(Statement::effect(Operation::Store(declaration.name, value)));
+                }
+            }
             parser::Statement::Declaration(declaration) => {
-
+                if self.functions.iter().any(|f| f.name == declaration.name) {
+                    return Err(CompileError::FunctionRedefined(declaration.name).into());
+                }
+                if self.varcounter.get(declaration.name.clone()).is_ok() {
+                    return Err(CompileError::FunctionAndVariable(declaration.name).into());
+                }
+                // variables the body reads are moved into globals from here on, so the function
+                // sees whatever they hold when it is called
+                let mut reads: Vec<String> = vec![];
+                // the loop variables, lambda parameters and `let` names of the scopes the body
+                // is in at each token, and how many there were before each unfinished scope
+                let mut hidden: Vec<&String> = vec![];
+                let mut scopes: Vec<usize> = vec![];
+                for token in &declaration.body {
+                    let name = match token {
+                        ParseToken::Lambda(params) => {
+                            scopes.push(hidden.len());
+                            hidden.extend(params);
+                            continue;
+                        }
+                        ParseToken::Bind(name) | ParseToken::Loop(name) => {
+                            scopes.push(hidden.len());
+                            hidden.push(name);
+                            continue;
+                        }
+                        ParseToken::EndLambda | ParseToken::EndBind | ParseToken::EndLoop(_) => {
+                            hidden.truncate(scopes.pop().unwrap_or_default());
+                            continue;
+                        }
+                        ParseToken::Identifier(name) => name,
+                        // a variable holding a function is read by calling it
+                        ParseToken::Call(name, _)
+                            if self
+                                .varcounter
+                                .get(name.clone())
+                                .is_ok_and(|temp| matches!(temp.ty, Type::Function(..))) =>
+                        {
+                            name
+                        }
+                        _ => continue,
+                    };
+                    let local = declaration.args.contains(name)
+                        || hidden.contains(&name)
+                        || self.functions.iter().any(|f| &f.name == name);
+                    if local || reads.contains(name) {
+                        continue;
+                    }
+                    let Ok(current) = self.varcounter.get(name.clone()) else {
+                        continue;
+                    };
+                    if self.global(name).is_none() {
+                        self.globals.push(Global {
+                            name: name.clone(),
+                            ty: current.ty.clone(),
+                        });
+                        self.main.push(Statement::effect(Operation::Store(
+                            name.clone(),
+                            Value::Temp(current),
+                        )));
+                    }
+                    reads.push(name.clone());
+                }
+                let globals: Vec<&Global> = reads
+                    .iter()
+                    .filter_map(|r| self.globals.iter().find(|g| &g.name == r))
+                    .collect();
+                let (params, return_type) =
+                    infer::signature(&declaration, &self.functions, &globals)?;
+                let function = compile_function(
+                    declaration,
+                    params,
+                    return_type,
+                    &self.functions,
+                    &globals,
+                    &mut self.lambdas,
+                )?;
+                self.functions.push(function);
             }
             parser::Statement::Expression(expr) => {
-                let (statements, result_id) = compile_expr(expr, &mut varcounter)?;
-                main_func.statements.extend_from_slice(&statements);
-                //main_func.statements.push(Statement::new(identifier, operation));
+                let value = compile_expr(
+                    expr,
+                    &self.functions,
+                    &mut self.varcounter,
+                    &mut self.main,
+                    &mut self.lambdas,
+                )?;
+                if let Type::Function(..) = value.ty() {
+                    return Err(CompileError::TypeMismatch(Type::Double, value.ty()).into());
+                }
+                self.main
+                    .push(Statement::effect(Operation::Print(value, self.format)));
             }
+            parser::Statement::Format(directive) => self.format = directive,
+            parser::Statement::Import(_) => panic!("imports are resolved before compiling"),
         }
+        return Ok(());
     }
 
-    let functions_formatted = functions
-        .iter()
-        .map(|f| f.to_string())
-        .collect::<Vec<String>>()
-        .join("\n");
-    return Ok(format!(
-        "{}{}\n{}\n{}",
-        BOILER_FMT, BOILER_POW, main_func, functions_formatted
+    fn finish(self) -> Module {
+        let mut main_func = Function::new_main();
+        main_func.blocks = self.main.finish(Terminator::Return(Some(Value::Word(0))));
+        let mut functions = self.functions;
+        functions.extend(self.lambdas.functions);
+        // the runtime only needs the command line when a program reads it
+        let reads_args = functions
+            .iter()
+            .chain([&main_func])
+            .flat_map(|f| &f.blocks)
+            .flat_map(|block| &block.statements)
+            .any(|statement| matches!(statement.operation, Operation::Arg(_)));
+        if reads_args {
+            let argc = Value::Temp(main_func.params[0].clone());
+            let argv = Value::Temp(main_func.params[1].clone());
+            main_func.blocks[0]
+                .statements
+                .insert(0, Statement::effect(Operation::SetArgs(argc, argv)));
+        }
+        functions.push(main_func);
+        return Module {
+            functions,
+            globals: self.globals,
+        };
+    }
+
+    fn global(&self, name: &str) -> Option<&Global> {
+        self.globals.iter().find(|g| g.name == name)
+    }
+}
+
+/// Compiles a user function with the parameter and result types inferred for it, `globals`
+/// are loaded on entry and then read like parameters.
+fn compile_function(
+    declaration: parser::Declaration,
+    param_types: Vec<Type>,
+    return_type: Type,
+    functions: &[Function],
+    globals: &[&Global],
+    lambdas: &mut Lambdas,
+) -> Result<Function> {
+    let mut counter = VariableCounter::new();
+    let params = declaration
+        .args
+        .into_iter()
+        .zip(param_types)
+        .map(|(arg, ty)| counter.next_var(arg, ty))
+        .collect();
+    let mut function = Function::new(declaration.name, params, return_type.clone());
+    let mut body = Body::new();
+    for global in globals {
+        let temp = counter.next_var(global.name.clone(), global.ty.clone());
+        body.push(Statement::assign(
+            temp,
+            Operation::Load(global.name.clone()),
+        ));
+    }
+    let value = compile_expr(
+        declaration.body,
+        functions,
+        &mut counter,
+        &mut body,
+        lambdas,
+    )?;
+    let value = convert(value, &return_type, &mut counter, &mut body)?;
+    function.blocks = body.finish(Terminator::Return(Some(value)));
+    return Ok(function);
+}
+
+/// The lambdas of a program, each is lifted into a function taking its closure before its
+/// own parameters.
+#[derive(Default)]
+struct Lambdas {
+    functions: Vec<Function>,
+    /// the types inferred for the lambdas of the expression being compiled that haven't been
+    /// compiled yet, in the order they start
+    types: VecDeque<Type>,
+    /// the lambda calling each function used as a value
+    wrappers: HashMap<String, String>,
+}
+
+impl Lambdas {
+    /// A name no function can be declared with, since it has a `.`.
+    fn next_name(&self) -> String {
+        format!("lambda.{}", self.functions.len() + 1)
+    }
+}
+
+/// Lifts a lambda into a function and makes a closure of it. The variables its body reads
+/// from the enclosing function are captured, the lifted function reads them from its closure.
+fn lambda(
+    params: Vec<String>,
+    tokens: Vec<ParseToken>,
+    functions: &[Function],
+    counter: &mut VariableCounter,
+    body: &mut Body,
+    lambdas: &mut Lambdas,
+) -> Result<Value> {
+    let ty = lambdas
+        .types
+        .pop_front()
+        .ok_or(CompileError::OperandError)?;
+    let Type::Function(param_types, return_type) = ty.clone() else {
+        panic!("a lambda has a function type");
+    };
+    let mut captures: Vec<(String, Temp)> = vec![];
+    for token in &tokens {
+        let (ParseToken::Identifier(name) | ParseToken::Call(name, _)) = token else {
+            continue;
+        };
+        let named = functions.iter().any(|f| &f.name == name);
+        if named || params.contains(name) || captures.iter().any(|(c, _)| c == name) {
+            continue;
+        }
+        if let Ok(temp) = counter.get(name.clone()) {
+            captures.push((name.clone(), temp));
+        }
+    }
+
+    let mut inner = VariableCounter::new();
+    let env = inner.next_var("env".to_string(), ty.clone());
+    let mut lifted_params = vec![env.clone()];
+    for (param, param_ty) in params.into_iter().zip(param_types) {
+        lifted_params.push(inner.next_var(param, param_ty));
+    }
+    let mut lifted_body = Body::new();
+    for (index, (name, temp)) in captures.iter().enumerate() {
+        let capture = inner.next_var(name.clone(), temp.ty.clone());
+        let operation = Operation::Capture(Value::Temp(env.clone()), index);
+        lifted_body.push(Statement::assign(capture, operation));
+    }
+    let value = lower_expr(tokens, functions, &mut inner, &mut lifted_body, lambdas)?;
+    let value = convert(value, &return_type, &mut inner, &mut lifted_body)?;
+    let name = lambdas.next_name();
+    let mut function = Function::new(name.clone(), lifted_params, *return_type);
+    function.blocks = lifted_body.finish(Terminator::Return(Some(value)));
+    lambdas.functions.push(function);
+
+    let captures = captures
+        .into_iter()
+        .map(|(_, temp)| Value::Temp(temp))
+        .collect();
+    return Ok(assign(
+        Operation::Closure(name, captures),
+        ty,
+        None,
+        counter,
+        body,
     ));
 }
 
-fn compile_expr(expr: Vec<ParseToken>, counter: &mut VariableCounter) -> Result<(Vec<Statement>, String)> {
-    let mut compiled: Vec<Statement> = vec![];
-    let mut stack: Vec<ParseToken> = vec![];
-    for token in expr {
+/// A closure of a declared function, so it can be passed around like a lambda.
+fn function_value(
+    func: &Function,
+    counter: &mut VariableCounter,
+    body: &mut Body,
+    lambdas: &mut Lambdas,
+) -> Value {
+    let params: Vec<Type> = func.params.iter().map(|p| p.ty.clone()).collect();
+    let ty = Type::Function(params, Box::new(func.return_type.clone()));
+    let name = match lambdas.wrappers.get(&func.name) {
+        Some(name) => name.clone(),
+        None => {
+            let name = lambdas.next_name();
+            let mut inner = VariableCounter::new();
+            let mut lifted_params = vec![inner.next_var("env".to_string(), ty.clone())];
+            let mut args = vec![];
+            for param in &func.params {
+                let temp = inner.next_var(param.name.clone(), param.ty.clone());
+                args.push(Value::Temp(temp.clone()));
+                lifted_params.push(temp);
+            }
+            let result = Temp::new(inner.next_temp(), func.return_type.clone());
+            let mut lifted_body = Body::new();
+            lifted_body.push(Statement::assign(
+                result.clone(),
+                Operation::Call(func.name.clone(), args),
+            ));
+            let mut wrapper = Function::new(name.clone(), lifted_params, func.return_type.clone());
+            wrapper.blocks = lifted_body.finish(Terminator::Return(Some(Value::Temp(result))));
+            lambdas.functions.push(wrapper);
+            lambdas.wrappers.insert(func.name.clone(), name.clone());
+            name
+        }
+    };
+    return assign(Operation::Closure(name, vec![]), ty, None, counter, body);
+}
+
+/// Evaluates an expression in reverse polish notation, appending the statements needed to
+/// compute it and returning the value holding the result. Operators on two ints give an int,
+/// any other mix of numbers is computed with doubles. The right side of `and` and `or` is
+/// compiled into its own block, so it only runs when the left side doesn't decide the result.
+/// The body of a `sum` or `prod` becomes a loop whose counter and accumulator are phis.
+/// A lambda is lifted into its own function, the types of its parameters are inferred from
+/// the whole expression first.
+fn compile_expr(
+    expr: Vec<ParseToken>,
+    functions: &[Function],
+    counter: &mut VariableCounter,
+    body: &mut Body,
+    lambdas: &mut Lambdas,
+) -> Result<Value> {
+    if expr
+        .iter()
+        .any(|token| matches!(token, ParseToken::Lambda(_)))
+    {
+        let types = infer::lambdas(&expr, functions, &counter.variables())?;
+        lambdas.types = types.into();
+    }
+    return lower_expr(expr, functions, counter, body, lambdas);
+}
+
+/// Compiles an expression whose lambdas have been inferred, see `compile_expr`.
+fn lower_expr(
+    expr: Vec<ParseToken>,
+    functions: &[Function],
+    counter: &mut VariableCounter,
+    body: &mut Body,
+    lambdas: &mut Lambdas,
+) -> Result<Value> {
+    let mut stack: Vec<Value> = vec![];
+    // the block each unfinished `and`/`or` branched from, and the block it joins in
+    let mut short_circuits: Vec<(String, String)> = vec![];
+    let mut loops: Vec<Loop> = vec![];
+    let mut tokens = expr.into_iter();
+    while let Some(token) = tokens.next() {
         match token {
-            _ if token.is_operator() => {
-                if let Some(ParseToken::Identifier(y)) = stack.pop()
-                    && let Some(ParseToken::Identifier(x)) = stack.pop()
-                {
-                    let operation = Operation::Add(x, y);
-                    compiled.push(Statement::new(counter.next_temp(), operation));
+            ParseToken::Number(n) => stack.push(Value::Double(n)),
+            ParseToken::Integer(n) => stack.push(Value::Long(n)),
+            ParseToken::Boolean(b) => stack.push(Value::Bool(b)),
+            ParseToken::Call(name, count) => {
+                if stack.len() < count {
+                    return Err(CompileError::OperandError.into());
+                }
+                let args = stack.split_off(stack.len() - count);
+                // a variable holding a function is called through its closure
+                let is_function = functions.iter().any(|f| f.name == name)
+                    || BUILTINS.iter().any(|(builtin, _)| *builtin == name);
+                match counter.get(name.clone()) {
+                    Ok(closure) if matches!(closure.ty, Type::Function(..)) => {
+                        stack.push(call_closure(name, closure, args, counter, body)?);
+                    }
+                    Ok(variable) if !is_function => {
+                        return Err(CompileError::NotAFunction(name, variable.ty).into());
+                    }
+                    _ => stack.push(call(name, args, functions, counter, body)?),
+                }
+            }
+            // a function without parameters can be called without parenthesis
+            ParseToken::Identifier(name)
+                if functions
+                    .iter()
+                    .any(|f| f.name == name && f.params.is_empty()) =>
+            {
+                stack.push(call(name, vec![], functions, counter, body)?);
+            }
+            ParseToken::Identifier(name) => {
+                let value = match counter.get(name.clone()) {
+                    Ok(temp) => Value::Temp(temp),
+                    // a function named without calling it is a value
+                    Err(error) => match functions.iter().find(|f| f.name == name) {
+                        Some(func) => function_value(func, counter, body, lambdas),
+                        None => return Err(error),
+                    },
+                };
+                stack.push(value);
+            }
+            ParseToken::Lambda(params) => {
+                // the body is everything up to the lambda's end, lambdas inside it included
+                let mut depth = 0;
+                let mut lambda_body = vec![];
+                for token in tokens.by_ref() {
+                    match token {
+                        ParseToken::Lambda(_) => depth += 1,
+                        ParseToken::EndLambda if depth == 0 => break,
+                        ParseToken::EndLambda => depth -= 1,
+                        _ => {}
+                    }
+                    lambda_body.push(token);
+                }
+                let closure = lambda(params, lambda_body, functions, counter, body, lambdas)?;
+                stack.push(closure);
+            }
+            // the name stands for a copy of the value until the `let` ends
+            ParseToken::Bind(name) => {
+                let value = stack.pop().ok_or(CompileError::OperandError)?;
+                let (ty, shape) = (value.ty(), counter.shape(&value));
+                let Value::Temp(temp) = assign(Operation::Copy(value), ty, shape, counter, body)
+                else {
+                    unreachable!("assign always gives a temporary");
+                };
+                counter.locals.push((name, temp));
+            }
+            ParseToken::EndBind => {
+                counter.locals.pop();
+            }
+            ParseToken::Negate => {
+                let x = stack.pop().ok_or(CompileError::OperandError)?;
+                expect_arithmetic(&x)?;
+                let (ty, shape) = (x.ty(), counter.shape(&x));
+                let operation = match ty {
+                    Type::Vector => Operation::Mul(x, Value::Double(-1.0)),
+                    _ => Operation::Neg(x),
+                };
+                stack.push(assign(operation, ty, shape, counter, body));
+            }
+            ParseToken::Vector(count) => {
+                if stack.len() < count {
+                    return Err(CompileError::OperandError.into());
+                }
+                let elements = stack.split_off(stack.len() - count);
+                // a vector of vectors is a matrix with them as its rows
+                if elements.first().is_some_and(|e| e.ty() == Type::Vector) {
+                    stack.push(matrix(elements, counter, body)?);
+                    continue;
+                }
+                let elements = elements
+                    .into_iter()
+                    .map(|element| {
+                        expect_number(&element)?;
+                        Ok(to_double(element, counter, body))
+                    })
+                    .collect::<Result<_>>()?;
+                let shape = Some(Shape::Vector(count));
+                let vector = assign(
+                    Operation::Vector(elements),
+                    Type::Vector,
+                    shape,
+                    counter,
+                    body,
+                );
+                stack.push(vector);
+            }
+            ParseToken::Index => {
+                let (Some(index), Some(vector)) = (stack.pop(), stack.pop()) else {
+                    return Err(CompileError::OperandError.into());
+                };
+                // indexing a matrix gives one of its rows
+                let (ty, shape) = match (vector.ty(), counter.shape(&vector)) {
+                    (Type::Matrix, Some(Shape::Matrix(_, cols))) => {
+                        (Type::Vector, Some(Shape::Vector(cols)))
+                    }
+                    (Type::Matrix, _) => (Type::Vector, None),
+                    (Type::Vector, _) => (Type::Double, None),
+                    (found, _) => {
+                        return Err(CompileError::TypeMismatch(Type::Vector, found).into())
+                    }
+                };
+                let index = convert(index, &Type::Long, counter, body)?;
+                stack.push(assign(
+                    Operation::Index(vector, index),
+                    ty,
+                    shape,
+                    counter,
+                    body,
+                ));
+            }
+            ParseToken::Loop(variable) => {
+                let (Some(end), Some(start)) = (stack.pop(), stack.pop()) else {
+                    return Err(CompileError::OperandError.into());
+                };
+                let start = convert(start, &Type::Long, counter, body)?;
+                let end = convert(end, &Type::Long, counter, body)?;
+                let header = body.next_label("loop");
+                let label = body.next_label("body");
+                let done = body.next_label("done");
+                let from = body.label().to_string();
+                body.branch(Terminator::Jump(header.clone()), header);
+                let index = Temp::new(counter.next_temp(), Type::Long);
+                let condition = Temp::new(counter.next_temp(), Type::Bool);
+                body.push(Statement::assign(
+                    condition.clone(),
+                    Operation::Compare(Comparison::LessEqual, Value::Temp(index.clone()), end),
+                ));
+                let header = body.blocks.len() - 1;
+                let terminator =
+                    Terminator::Branch(Value::Temp(condition), label.clone(), done.clone());
+                body.branch(terminator, label);
+                counter.locals.push((variable, index.clone()));
+                loops.push(Loop {
+                    from,
+                    header,
+                    done,
+                    index,
+                    start,
+                });
+            }
+            ParseToken::EndLoop(accumulator) => {
+                let value = stack.pop().ok_or(CompileError::OperandError)?;
+                expect_number(&value)?;
+                let Loop {
+                    from,
+                    header,
+                    done,
+                    index,
+                    start,
+                } = loops.pop().ok_or(CompileError::OperandError)?;
+                counter.locals.pop();
+                let ty = value.ty();
+                let total = Temp::new(counter.next_temp(), ty.clone());
+                let (initial, operation) = match accumulator {
+                    Accumulator::Sum => (0, Operation::Add(Value::Temp(total.clone()), value)),
+                    Accumulator::Product => (1, Operation::Mul(Value::Temp(total.clone()), value)),
+                };
+                let initial = match ty {
+                    Type::Long => Value::Long(initial),
+                    _ => Value::Double(initial as f64),
+                };
+                let next_total = assign(operation, ty, None, counter, body);
+                let next_index = assign(
+                    Operation::Add(Value::Temp(index.clone()), Value::Long(1)),
+                    Type::Long,
+                    None,
+                    counter,
+                    body,
+                );
+                let latch = body.label().to_string();
+                let header_label = body.blocks[header].label.clone();
+                body.branch(Terminator::Jump(header_label), done);
+                // the phis go first in the header, now that the values from the body are known
+                let phis = [
+                    (index, start, next_index),
+                    (total.clone(), initial, next_total),
+                ];
+                for (position, (dest, first, next)) in phis.into_iter().enumerate() {
+                    let phi = Operation::Phi(vec![(from.clone(), first), (latch.clone(), next)]);
+                    body.blocks[header]
+                        .statements
+                        .insert(position, Statement::assign(dest, phi));
+                }
+                stack.push(Value::Temp(total));
+            }
+            ParseToken::Not => {
+                let x = stack.pop().ok_or(CompileError::OperandError)?;
+                expect_bool(&x)?;
+                let result = Temp::new(counter.next_temp(), Type::Bool);
+                body.push(Statement::assign(result.clone(), Operation::Not(x)));
+                stack.push(Value::Temp(result));
+            }
+            ParseToken::AndThen | ParseToken::OrElse => {
+                let left = stack.pop().ok_or(CompileError::OperandError)?;
+                expect_bool(&left)?;
+                let and = token == ParseToken::AndThen;
+                let right = body.next_label(if and { "and" } else { "or" });
+                let join = body.next_label("join");
+                let terminator = if and {
+                    Terminator::Branch(left, right.clone(), join.clone())
                 } else {
+                    Terminator::Branch(left, join.clone(), right.clone())
+                };
+                short_circuits.push((body.label().to_string(), join));
+                body.branch(terminator, right);
+            }
+            ParseToken::And | ParseToken::Or => {
+                let right = stack.pop().ok_or(CompileError::OperandError)?;
+                expect_bool(&right)?;
+                let (from, join) = short_circuits.pop().ok_or(CompileError::OperandError)?;
+                let right_from = body.label().to_string();
+                body.branch(Terminator::Jump(join.clone()), join);
+                // the left side decided the result when the right side was skipped
+                let decided = Value::Bool(token == ParseToken::Or);
+                let result = Temp::new(counter.next_temp(), Type::Bool);
+                body.push(Statement::assign(
+                    result.clone(),
+                    Operation::Phi(vec![(from, decided), (right_from, right)]),
+                ));
+                stack.push(Value::Temp(result));
+            }
+            _ if token.is_comparison() => {
+                let (Some(mut y), Some(mut x)) = (stack.pop(), stack.pop()) else {
                     return Err(CompileError::OperandError.into());
+                };
+                let comparison = match token {
+                    ParseToken::Equal => Comparison::Equal,
+                    ParseToken::NotEqual => Comparison::NotEqual,
+                    ParseToken::Less => Comparison::Less,
+                    ParseToken::LessEqual => Comparison::LessEqual,
+                    ParseToken::Greater => Comparison::Greater,
+                    _ => Comparison::GreaterEqual,
+                };
+                let equality = matches!(comparison, Comparison::Equal | Comparison::NotEqual);
+                if equality && x.ty() == Type::Bool {
+                    expect_bool(&y)?;
+                } else {
+                    expect_number(&x)?;
+                    expect_number(&y)?;
+                }
+                if x.ty() != y.ty() {
+                    x = to_double(x, counter, body);
+                    y = to_double(y, counter, body);
                 }
+                let result = Temp::new(counter.next_temp(), Type::Bool);
+                body.push(Statement::assign(
+                    result.clone(),
+                    Operation::Compare(comparison, x, y),
+                ));
+                stack.push(Value::Temp(result));
+            }
+            ParseToken::MatrixMultiply => {
+                let (Some(y), Some(x)) = (stack.pop(), stack.pop()) else {
+                    return Err(CompileError::OperandError.into());
+                };
+                stack.push(matmul(x, y, counter, body)?);
+            }
+            _ if token.is_operator() => {
+                let (Some(mut y), Some(mut x)) = (stack.pop(), stack.pop()) else {
+                    return Err(CompileError::OperandError.into());
+                };
+                expect_arithmetic(&x)?;
+                expect_arithmetic(&y)?;
+                if x.ty() != y.ty() {
+                    x = to_double(x, counter, body);
+                    y = to_double(y, counter, body);
+                }
+                // a vector with a number is computed element by element
+                let ty = match y.ty() {
+                    Type::Vector => Type::Vector,
+                    _ => x.ty(),
+                };
+                let shape = match (counter.shape(&x), counter.shape(&y)) {
+                    (Some(a), Some(b)) if a != b => {
+                        let error = CompileError::DimensionMismatch(
+                            token.to_string(),
+                            a.to_string(),
+                            b.to_string(),
+                        );
+                        return Err(error.into());
+                    }
+                    (a, b) => a.or(b),
+                };
+                let operation = match token {
+                    ParseToken::Add => Operation::Add(x, y),
+                    ParseToken::Subtract => Operation::Sub(x, y),
+                    ParseToken::Multiply => Operation::Mul(x, y),
+                    ParseToken::Divide => Operation::Div(x, y),
+                    ParseToken::Remainder => Operation::Rem(x, y),
+                    ParseToken::Exponent => Operation::Pow(x, y),
+                    _ => return Err(CompileError::InvalidToken(token).into()),
+                };
+                stack.push(assign(operation, ty, shape, counter, body));
             }
             _ => return Err(CompileError::InvalidToken(token).into()),
         }
     }
-    return Ok((compiled, counter.current_temp()));
+    match (stack.pop(), stack.is_empty()) {
+        (Some(value), true) => Ok(value),
+        _ => Err(CompileError::OperandError.into()),
+    }
+}
+
+/// Calls the function of a closure, the arguments are converted to its parameter types.
+fn call_closure(
+    name: String,
+    closure: Temp,
+    args: Vec<Value>,
+    counter: &mut VariableCounter,
+    body: &mut Body,
+) -> Result<Value> {
+    let Type::Function(params, result) = closure.ty.clone() else {
+        return Err(CompileError::NotAFunction(name, closure.ty).into());
+    };
+    if args.len() != params.len() {
+        return Err(CompileError::ArgumentCount(name, params.len(), args.len()).into());
+    }
+    let args = args
+        .into_iter()
+        .zip(&params)
+        .enumerate()
+        .map(|(index, (arg, param))| {
+            convert(arg, param, counter, body)
+                .with_context(|| format!("argument {} of `{name}`", index + 1))
+        })
+        .collect::<Result<_>>()?;
+    return Ok(assign(
+        Operation::CallClosure(Value::Temp(closure), args),
+        *result,
+        None,
+        counter,
+        body,
+    ));
+}
+
+/// Calls a user function, or one of the builtins when no function has that name.
+fn call(
+    name: String,
+    args: Vec<Value>,
+    functions: &[Function],
+    counter: &mut VariableCounter,
+    body: &mut Body,
+) -> Result<Value> {
+    let expected = match functions.iter().find(|f| f.name == name) {
+        Some(func) => func.params.len(),
+        None => match BUILTINS.iter().find(|(builtin, _)| *builtin == name) {
+            Some((_, arity)) => *arity,
+            None => return Err(CompileError::NameError(name).into()),
+        },
+    };
+    if args.len() != expected {
+        return Err(CompileError::ArgumentCount(name, expected, args.len()).into());
+    }
+    let Some(func) = functions.iter().find(|f| f.name == name) else {
+        return builtin(&name, args, counter, body);
+    };
+    let args = args
+        .into_iter()
+        .zip(&func.params)
+        .enumerate()
+        .map(|(index, (arg, param))| {
+            convert(arg, &param.ty, counter, body)
+                .with_context(|| format!("argument {} of `{name}`", index + 1))
+        })
+        .collect::<Result<_>>()?;
+    let result = Temp::new(counter.next_temp(), func.return_type.clone());
+    body.push(Statement::assign(
+        result.clone(),
+        Operation::Call(name, args),
+    ));
+    return Ok(Value::Temp(result));
+}
+
+/// The functions every program can call without declaring them, with how many arguments each
+/// takes.
+const BUILTINS: [(&str, usize); 19] = [
+    ("int", 1),
+    ("float", 1),
+    ("len", 1),
+    ("sum", 1),
+    ("mean", 1),
+    ("min", 1),
+    ("max", 1),
+    ("T", 1),
+    ("det", 1),
+    ("inv", 1),
+    ("solve", 2),
+    ("map", 2),
+    ("sqrt", 1),
+    ("exp", 1),
+    ("ln", 1),
+    ("sin", 1),
+    ("cos", 1),
+    ("arg", 1),
+    ("input", 0),
+];
+
+/// Calls one of `BUILTINS`, the conversions `int(x)` and `float(x)`, an elementary function
+/// like `sqrt(x)`, a function of a vector or matrix, `map(v, f)` or reads a number with `arg(n)` and `input()`.
+fn builtin(
+    name: &str,
+    mut args: Vec<Value>,
+    counter: &mut VariableCounter,
+    body: &mut Body,
+) -> Result<Value> {
+    if name == "input" {
+        return Ok(assign(Operation::Input, Type::Double, None, counter, body));
+    }
+    let x = args.remove(0);
+    if name == "arg" {
+        let n = convert(x, &Type::Long, counter, body)?;
+        return Ok(assign(Operation::Arg(n), Type::Double, None, counter, body));
+    }
+    if matches!(name, "T" | "det" | "inv" | "solve") {
+        return linear_algebra(name, x, args.pop(), counter, body);
+    }
+    if name == "map" {
+        let f = args.remove(0);
+        let mapping = Type::Function(vec![Type::Double], Box::new(Type::Double));
+        if x.ty() != Type::Vector {
+            return Err(CompileError::TypeMismatch(Type::Vector, x.ty()).into());
+        }
+        if f.ty() != mapping {
+            return Err(CompileError::TypeMismatch(mapping, f.ty()).into());
+        }
+        let shape = counter.shape(&x);
+        return Ok(assign(
+            Operation::Map(x, f),
+            Type::Vector,
+            shape,
+            counter,
+            body,
+        ));
+    }
+    let (operation, ty) = match name {
+        "int" | "float" => {
+            expect_number(&x)?;
+            return Ok(match (name, x.ty()) {
+                ("float", _) => to_double(x, counter, body),
+                (_, Type::Double) => {
+                    let result = Temp::new(counter.next_temp(), Type::Long);
+                    body.push(Statement::assign(result.clone(), Operation::FloatToInt(x)));
+                    Value::Temp(result)
+                }
+                _ => x,
+            });
+        }
+        "sqrt" | "exp" | "ln" | "sin" | "cos" => {
+            expect_number(&x)?;
+            let function = match name {
+                "sqrt" => MathFunction::Sqrt,
+                "exp" => MathFunction::Exp,
+                "ln" => MathFunction::Ln,
+                "sin" => MathFunction::Sin,
+                _ => MathFunction::Cos,
+            };
+            let x = convert(x, &Type::Double, counter, body)?;
+            (Operation::Math(function, x), Type::Double)
+        }
+        _ if x.ty() != Type::Vector => {
+            return Err(CompileError::TypeMismatch(Type::Vector, x.ty()).into());
+        }
+        "len" => (Operation::Length(x), Type::Long),
+        "sum" => (Operation::Reduce(Reduction::Sum, x), Type::Double),
+        "mean" => (Operation::Reduce(Reduction::Mean, x), Type::Double),
+        "min" => (Operation::Reduce(Reduction::Min, x), Type::Double),
+        _ => (Operation::Reduce(Reduction::Max, x), Type::Double),
+    };
+    return Ok(assign(operation, ty, None, counter, body));
+}
+
+/// `T(m)`, `det(m)`, `inv(m)` and `solve(m, b)`, checking the sizes when they are known.
+fn linear_algebra(
+    name: &str,
+    m: Value,
+    b: Option<Value>,
+    counter: &mut VariableCounter,
+    body: &mut Body,
+) -> Result<Value> {
+    if m.ty() != Type::Matrix {
+        return Err(CompileError::TypeMismatch(Type::Matrix, m.ty()).into());
+    }
+    let shape = counter.shape(&m);
+    if name == "T" {
+        let shape = shape.map(|shape| Shape::Matrix(shape.columns(), shape.rows()));
+        return Ok(assign(
+            Operation::Transpose(m),
+            Type::Matrix,
+            shape,
+            counter,
+            body,
+        ));
+    }
+    if let Some(Shape::Matrix(rows, cols)) = shape
+        && rows != cols
+    {
+        return Err(CompileError::NotSquare(name.to_string(), rows, cols).into());
+    }
+    let (operation, ty, shape) = match (name, b) {
+        ("det", _) => (Operation::Det(m), Type::Double, None),
+        ("inv", _) => (Operation::Inverse(m), Type::Matrix, shape),
+        (_, Some(b)) => {
+            if b.ty() != Type::Vector {
+                return Err(CompileError::TypeMismatch(Type::Vector, b.ty()).into());
+            }
+            if let (Some(a), Some(length)) = (shape, counter.shape(&b))
+                && a.rows() != length.rows()
+            {
+                let error = CompileError::DimensionMismatch(
+                    name.to_string(),
+                    a.to_string(),
+                    length.to_string(),
+                );
+                return Err(error.into());
+            }
+            let shape = shape.map(|a| Shape::Vector(a.rows()));
+            (Operation::Solve(m, b), Type::Vector, shape)
+        }
+        _ => panic!("`{name}` is not a linear algebra builtin"),
+    };
+    return Ok(assign(operation, ty, shape, counter, body));
+}
+
+/// Builds a matrix from vectors holding its rows, which need the same length when it is known.
+fn matrix(rows: Vec<Value>, counter: &mut VariableCounter, body: &mut Body) -> Result<Value> {
+    let mut cols = None;
+    for row in &rows {
+        if row.ty() != Type::Vector {
+            return Err(CompileError::TypeMismatch(Type::Vector, row.ty()).into());
+        }
+        match (cols, counter.shape(row)) {
+            (Some(cols), Some(length)) if cols != length.rows() => {
+                return Err(CompileError::RaggedMatrix(cols, length.rows()).into());
+            }
+            (None, Some(length)) => cols = Some(length.rows()),
+            _ => {}
+        }
+    }
+    let shape = cols.map(|cols| Shape::Matrix(rows.len(), cols));
+    return Ok(assign(
+        Operation::Matrix(rows),
+        Type::Matrix,
+        shape,
+        counter,
+        body,
+    ));
+}
+
+/// `x @ y` of matrices and vectors, the columns of `x` have to match the rows of `y` when both
+/// sizes are known.
+fn matmul(x: Value, y: Value, counter: &mut VariableCounter, body: &mut Body) -> Result<Value> {
+    let ty = match (x.ty(), y.ty()) {
+        (Type::Matrix, Type::Matrix) => Type::Matrix,
+        (Type::Matrix, Type::Vector) | (Type::Vector, Type::Matrix) => Type::Vector,
+        (Type::Vector, Type::Vector) => Type::Double,
+        (Type::Matrix | Type::Vector, found) | (found, _) => {
+            return Err(CompileError::TypeMismatch(Type::Matrix, found).into());
+        }
+    };
+    let (left, right) = (counter.shape(&x), counter.shape(&y));
+    if let (Some(left), Some(right)) = (left, right)
+        && left.columns() != right.rows()
+    {
+        let error =
+            CompileError::DimensionMismatch("@".to_string(), left.to_string(), right.to_string());
+        return Err(error.into());
+    }
+    let shape = match (left, right) {
+        (Some(Shape::Matrix(rows, _)), Some(Shape::Matrix(_, cols))) => {
+            Some(Shape::Matrix(rows, cols))
+        }
+        (Some(Shape::Matrix(rows, _)), _) if ty == Type::Vector => Some(Shape::Vector(rows)),
+        (_, Some(Shape::Matrix(_, cols))) if ty == Type::Vector => Some(Shape::Vector(cols)),
+        _ => None,
+    };
+    return Ok(assign(Operation::MatMul(x, y), ty, shape, counter, body));
+}
+
+/// Assigns the result of `operation` to a new temporary, remembering its shape if it is known.
+fn assign(
+    operation: Operation,
+    ty: Type,
+    shape: Option<Shape>,
+    counter: &mut VariableCounter,
+    body: &mut Body,
+) -> Value {
+    let result = Temp::new(counter.next_temp(), ty);
+    counter.set_shape(&result, shape);
+    body.push(Statement::assign(result.clone(), operation));
+    return Value::Temp(result);
+}
+
+/// Makes a value the type `ty`, an int is promoted to a double but a double is never
+/// truncated to an int.
+fn convert(
+    value: Value,
+    ty: &Type,
+    counter: &mut VariableCounter,
+    body: &mut Body,
+) -> Result<Value> {
+    match (value.ty(), ty) {
+        (Type::Long, Type::Double) => Ok(to_double(value, counter, body)),
+        (found, _) if found != *ty => Err(CompileError::TypeMismatch(ty.clone(), found).into()),
+        _ => Ok(value),
+    }
 }
 
+fn expect_number(value: &Value) -> Result<()> {
+    if !matches!(value.ty(), Type::Long | Type::Double) {
+        return Err(CompileError::TypeMismatch(Type::Double, value.ty()).into());
+    }
+    return Ok(());
+}
+
+/// Arithmetic works on numbers and vectors.
+fn expect_arithmetic(value: &Value) -> Result<()> {
+    if matches!(value.ty(), Type::Bool | Type::Matrix) {
+        return Err(CompileError::TypeMismatch(Type::Double, value.ty()).into());
+    }
+    return Ok(());
+}
+
+fn expect_bool(value: &Value) -> Result<()> {
+    if value.ty() != Type::Bool {
+        return Err(CompileError::TypeMismatch(Type::Bool, value.ty()).into());
+    }
+    return Ok(());
+}
+
+/// Promotes an int to a double, any other value is returned as is.
+fn to_double(value: Value, counter: &mut VariableCounter, body: &mut Body) -> Value {
+    match value {
+        Value::Long(n) => Value::Double(n as f64),
+        Value::Temp(ref temp) if temp.ty == Type::Long => {
+            let result = Temp::new(counter.next_temp(), Type::Double);
+            body.push(Statement::assign(
+                result.clone(),
+                Operation::IntToFloat(value),
+            ));
+            Value::Temp(result)
+        }
+        _ => value,
+    }
+}
+
+/// The blocks of a function while it is compiled, statements are added to the last one.
+struct Body {
+    blocks: Vec<Block>,
+    labels: usize,
+}
+
+impl Body {
+    fn new() -> Self {
+        Body {
+            blocks: vec![Block::new("start".to_string())],
+            labels: 0,
+        }
+    }
+
+    fn push(&mut self, statement: Statement) {
+        self.current().statements.push(statement);
+    }
+
+    /// The label of the block statements are added to.
+    fn label(&self) -> &str {
+        &self.blocks[self.blocks.len() - 1].label
+    }
+
+    /// A label no other block of the function has.
+    fn next_label(&mut self, prefix: &str) -> String {
+        self.labels += 1;
+        format!("{prefix}_{}", self.labels)
+    }
+
+    /// Ends the current block with `terminator` and continues in a new block.
+    fn branch(&mut self, terminator: Terminator, label: String) {
+        self.current().terminator = terminator;
+        self.blocks.push(Block::new(label));
+    }
+
+    fn finish(mut self, terminator: Terminator) -> Vec<Block> {
+        self.current().terminator = terminator;
+        return self.blocks;
+    }
+
+    fn current(&mut self) -> &mut Block {
+        self.blocks.last_mut().expect("a body always has a block")
+    }
+}
+
+/// A `sum` or `prod` whose body is being compiled.
+struct Loop {
+    /// the block entering the loop
+    from: String,
+    /// the index of the block checking the counter in `Body::blocks`
+    header: usize,
+    /// the block after the loop
+    done: String,
+    /// the counter, which the loop variable reads
+    index: Temp,
+    start: Value,
+}
+
+/// The size of a vector or matrix.
+#[derive(PartialEq, Debug, Clone, Copy)]
+enum Shape {
+    Vector(usize),
+    Matrix(usize, usize),
+}
+
+impl Shape {
+    /// The rows of a matrix, a vector is a column.
+    fn rows(self) -> usize {
+        match self {
+            Shape::Vector(length) | Shape::Matrix(length, _) => length,
+        }
+    }
+
+    /// The columns of a matrix, a vector is a row.
+    fn columns(self) -> usize {
+        match self {
+            Shape::Vector(length) | Shape::Matrix(_, length) => length,
+        }
+    }
+}
+
+impl fmt::Display for Shape {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Shape::Vector(length) => write!(f, "a vector of length {length}"),
+            Shape::Matrix(rows, cols) => write!(f, "a {rows}x{cols} matrix"),
+        }
+    }
+}
+
+/// The temporary holding one version of a variable. The `.` of a name from an imported file
+/// can't be in a c identifier, it becomes a `0` which no name can hold.
+fn version(identifier: &str, count: i32) -> String {
+    return format!("{}_{}", identifier.replace('.', "0"), count);
+}
+
+/// Names the ssa temporaries of one function. Every assignment to a variable gets a new
+/// version, `x_0`, `x_1`, ..., and reading a variable gives its latest version.
 struct VariableCounter {
     tempcount: i32,
-    pairs: HashMap<String, i32>,
+    pairs: HashMap<String, (i32, Type)>,
+    /// the size of every vector and matrix temporary whose size is known while compiling
+    shapes: HashMap<String, Shape>,
+    /// the variables of the loops being compiled, innermost last, they hide any other
+    /// variable with the same name
+    locals: Vec<(String, Temp)>,
 }
 
 impl VariableCounter {
     fn new() -> Self {
-        VariableCounter{tempcount: 0, pairs: HashMap::new()}
+        VariableCounter {
+            tempcount: 0,
+            pairs: HashMap::new(),
+            shapes: HashMap::new(),
+            locals: vec![],
+        }
     }
 
-    fn next_var(&mut self, identifier: String) -> String {
-        if let Some(count) = self.pairs.get(&identifier) {
-            format!("%{}_{}", identifier, count)
-        } else {
-            self.pairs.insert(identifier.clone(), 0);
-            format!("%{}_{}", identifier, 0)
+    fn shape(&self, value: &Value) -> Option<Shape> {
+        match value {
+            Value::Temp(temp) => self.shapes.get(&temp.name).copied(),
+            _ => None,
         }
     }
 
-    fn get(&self, identifier: String) -> Result<String> {
-        let count = self
+    fn set_shape(&mut self, temp: &Temp, shape: Option<Shape>) {
+        if let Some(shape) = shape {
+            self.shapes.insert(temp.name.clone(), shape);
+        }
+    }
+
+    fn next_var(&mut self, identifier: String, ty: Type) -> Temp {
+        let (count, _) = self
+            .pairs
+            .entry(identifier.clone())
+            .and_modify(|(count, old)| {
+                *count += 1;
+                *old = ty.clone();
+            })
+            .or_insert((0, ty.clone()));
+        Temp::new(version(&identifier, *count), ty)
+    }
+
+    fn get(&self, identifier: String) -> Result<Temp> {
+        if let Some((_, temp)) = self
+            .locals
+            .iter()
+            .rev()
+            .find(|(name, _)| *name == identifier)
+        {
+            return Ok(temp.clone());
+        }
+        let (count, ty) = self
             .pairs
             .get(&identifier)
             .ok_or(CompileError::NameError(identifier.clone()))?;
-        return Ok(format!("%{}_{}", identifier, count));
+        return Ok(Temp::new(version(&identifier, *count), ty.clone()));
+    }
+
+    /// Every variable that can be read and its type, loop variables last.
+    fn variables(&self) -> Vec<(String, Type)> {
+        let mut variables: Vec<(String, Type)> = self
+            .pairs
+            .iter()
+            .map(|(name, (_, ty))| (name.clone(), ty.clone()))
+            .collect();
+        variables.extend(
+            self.locals
+                .iter()
+                .map(|(name, temp)| (name.clone(), temp.ty.clone())),
+        );
+        return variables;
     }
 
     fn next_temp(&mut self) -> String {
         self.tempcount += 1;
-        format!("%_{}", self.tempcount)
+        format!("_{}", self.tempcount)
     }
+}
 
-    fn current_temp(&self) -> String {
-        return format!("%_{}", self.tempcount);
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{error::Severity, ir, parser::parse};
+
+    fn compile_source(source: &str) -> Result<Module> {
+        let module = compile(parse(source), PrintFormat::default())
+            .map_err(|mut diagnostics| diagnostics.remove(0).error)?;
+        ir::verify(&module)?;
+        Ok(module)
     }
-}
 
-#[derive(PartialEq, Debug, Clone)]
-struct Function {
-    export: bool,
-    return_type: Type,
-    name: String,
-    args: Vec<String>,
-    statements: Vec<Statement>,
-    return_val: String,
-}
+    #[test]
+    fn compiles_expression_to_print() {
+        let module = compile_source("1+2.5").unwrap();
+        let main = module.function("main").unwrap();
+        let sum = Temp::new("_1".to_string(), Type::Double);
+        assert_eq!(
+            main.blocks[0].statements,
+            vec![
+                Statement::assign(
+                    sum.clone(),
+                    Operation::Add(Value::Double(1.0), Value::Double(2.5))
+                ),
+                Statement::effect(Operation::Print(Value::Temp(sum), PrintFormat::Fixed(4))),
+            ]
+        );
+    }
+
+    #[test]
+    fn compiles_function_declaration() {
+        let module = compile_source("f(x, y) = x * y\nf(2, 3)").unwrap();
+        let f = module.function("f").unwrap();
+        assert_eq!(f.params.len(), 2);
+        assert_eq!(f.blocks[0].statements.len(), 1);
+        let main = module.function("main").unwrap();
+        assert!(matches!(
+            &main.blocks[0].statements[0].operation,
+            Operation::Call(name, args) if name == "f" && args.len() == 2
+        ));
+    }
+
+    #[test]
+    fn errors_on_unknown_variable() {
+        assert!(compile_source("x + 1").is_err());
+    }
+
+    #[test]
+    fn reports_every_failed_statement() {
+        let diagnostics = compile(parse("a + 1\n1 +\nb"), PrintFormat::default()).unwrap_err();
+        let lines: Vec<usize> = diagnostics.iter().map(|d| d.span.line).collect();
+        assert_eq!(lines, vec![2, 1, 3]);
+    }
 
-impl Function {
-    fn new(name: String, args: Vec<String>) -> Self {
-        Self {
-            name,
-            args,
-            ..Default::default()
+    #[test]
+    fn versions_reassigned_variables() {
+        let module = compile_source("x = 1\nx = x + 1\nx").unwrap();
+        let main = module.function("main").unwrap();
+        let x = |version: &str| Temp::new(format!("x_{version}"), Type::Long);
+        let sum = Temp::new("_1".to_string(), Type::Long);
+        assert_eq!(
+            main.blocks[0].statements,
+            vec![
+                Statement::assign(x("0"), Operation::Copy(Value::Long(1))),
+                Statement::assign(
+                    sum.clone(),
+                    Operation::Add(Value::Temp(x("0")), Value::Long(1))
+                ),
+                Statement::assign(x("1"), Operation::Copy(Value::Temp(sum))),
+                Statement::effect(Operation::Print(Value::Temp(x("1")), PrintFormat::Fixed(4))),
+            ]
+        );
+    }
+
+    #[test]
+    fn stores_variables_read_by_functions() {
+        let module = compile_source("a = 1\nb = 2\nf(x) = x + b\nb = 3\na + f(a)").unwrap();
+        assert_eq!(
+            module.globals,
+            vec![Global {
+                name: "b".to_string(),
+                ty: Type::Long,
+            }]
+        );
+        let f = module.function("f").unwrap();
+        assert_eq!(
+            f.blocks[0].statements[0],
+            Statement::assign(
+                Temp::new("b_0".to_string(), Type::Long),
+                Operation::Load("b".to_string())
+            )
+        );
+        let stores: Vec<&Statement> = module.function("main").unwrap().blocks[0]
+            .statements
+            .iter()
+            .filter(|s| matches!(s.operation, Operation::Store(..)))
+            .collect();
+        assert_eq!(stores.len(), 2);
+    }
+
+    #[test]
+    fn promotes_mixed_ints_to_doubles() {
+        let module = compile_source("n = 3\nf(x) = x\nn * 0.5 + f(n)").unwrap();
+        let main = module.function("main").unwrap();
+        let n = Value::Temp(Temp::new("n_0".to_string(), Type::Long));
+        let promoted = |name: &str| Temp::new(name.to_string(), Type::Double);
+        assert_eq!(
+            main.blocks[0].statements[1..4],
+            [
+                Statement::assign(promoted("_1"), Operation::IntToFloat(n.clone())),
+                Statement::assign(
+                    promoted("_2"),
+                    Operation::Mul(Value::Temp(promoted("_1")), Value::Double(0.5))
+                ),
+                Statement::assign(promoted("_3"), Operation::IntToFloat(n)),
+            ]
+        );
+        assert!(compile_source("int(2.5) / 2 + float(1) % 2").is_ok());
+    }
+
+    #[test]
+    fn errors_on_float_assigned_to_int_global() {
+        assert!(compile_source("n = 1\nf(x) = x * n\nn = 2\nf(1)").is_ok());
+        assert!(matches!(
+            compile_source("n = 1\nf(x) = x * n\nn = 2.5\nf(1)")
+                .unwrap_err()
+                .downcast_ref(),
+            Some(CompileError::GlobalType(..))
+        ));
+        assert!(compile_source("r = 1.5\nf(x) = x * r\nr = 2\nf(1)").is_ok());
+    }
+
+    #[test]
+    fn errors_on_wrong_argument_count() {
+        for source in ["f(x, y) = x * y\nf(1)", "int(1, 2)", "float()"] {
+            assert!(
+                matches!(
+                    compile_source(source).unwrap_err().downcast_ref(),
+                    Some(CompileError::ArgumentCount(..))
+                ),
+                "{source}"
+            );
+        }
+    }
+
+    #[test]
+    fn checks_vector_types() {
+        let module = compile_source("avg(v) = sum(v) / len(v)\navg([1, 2])").unwrap();
+        assert_eq!(module.function("avg").unwrap().params[0].ty, Type::Vector);
+        for (source, expected, found) in [
+            ("[1, 2][0.5]", Type::Long, Type::Double),
+            ("sum(3)", Type::Vector, Type::Long),
+            ("[1, 2] < 1", Type::Double, Type::Vector),
+            ("[true]", Type::Double, Type::Bool),
+        ] {
+            let error = compile_source(source).unwrap_err();
+            assert!(
+                matches!(
+                    error.downcast_ref(),
+                    Some(CompileError::TypeMismatch(e, f)) if *e == expected && *f == found
+                ),
+                "{source}: {error:#}"
+            );
         }
     }
 
-    fn new_main() -> Self {
-        return Self {
-            export: true,
-            return_type: Type::Word,
-            name: "$main".to_string(),
-            args: vec![],
-            statements: vec![],
-            return_val: "0".to_string(),
+    #[test]
+    fn compiles_loops() {
+        let module = compile_source("f(n) = sum(i, 1, n, prod(j, 1, i, j))\nf(3)").unwrap();
+        let f = module.function("f").unwrap();
+        assert_eq!(f.params[0].ty, Type::Long);
+        assert_eq!(f.return_type, Type::Long);
+        let labels: Vec<&str> = f.blocks.iter().map(|b| b.label.as_str()).collect();
+        assert_eq!(
+            labels,
+            ["start", "loop_1", "body_2", "loop_4", "body_5", "done_6", "done_3"]
+        );
+        let error = compile_source("sum(i, 0, 1.5, i)").unwrap_err();
+        assert!(matches!(
+            error.downcast_ref(),
+            Some(CompileError::TypeMismatch(Type::Long, Type::Double))
+        ));
+        // a global with the loop variable's name is read outside the loop
+        let module = compile_source("n = 3\nf(x) = sum(n, 1, 2, n) + n\nf(1)").unwrap();
+        let n = Global {
+            name: "n".to_string(),
+            ty: Type::Long,
         };
+        assert_eq!(module.globals, vec![n]);
+        // the loop variable is gone after the loop even when compiling it failed
+        let errors = compile(parse("sum(i, 1, 2, [i])\ni"), PrintFormat::default()).unwrap_err();
+        assert!(matches!(
+            errors[1].error.downcast_ref(),
+            Some(CompileError::NameError(name)) if name == "i"
+        ));
+    }
+
+    #[test]
+    fn checks_matrix_dimensions() {
+        let module = compile_source("apply(m, v) = m @ v\napply([[1, 0]], [2])").unwrap();
+        let apply = module.function("apply").unwrap();
+        assert_eq!(apply.params[0].ty, Type::Matrix);
+        assert_eq!(apply.params[1].ty, Type::Vector);
+        assert_eq!(apply.return_type, Type::Vector);
+        for (source, message) in [
+            (
+                "m = [[1, 2], [3, 4]]\nm @ [1, 2, 3]",
+                "`@` can't combine a 2x2 matrix with a vector of length 3",
+            ),
+            (
+                "T([[1, 2]]) @ T([[1, 2]])",
+                "`@` can't combine a 2x1 matrix with a 2x1 matrix",
+            ),
+            (
+                "[1, 2] + [1]",
+                "`+` can't combine a vector of length 2 with a vector of length 1",
+            ),
+            (
+                "inv([[1, 2]])",
+                "`inv` needs a square matrix but found a 1x2 one",
+            ),
+            (
+                "[[1], [2, 3]]",
+                "the rows of a matrix have different lengths, 1 and 2",
+            ),
+        ] {
+            let error = compile_source(source).unwrap_err();
+            assert_eq!(error.to_string(), message, "{source}");
+        }
+        assert!(compile_source("[[1, 2]] * 2").is_err());
     }
-}
 
-impl Default for Function {
-    fn default() -> Self {
-        return Self {
-            export: false,
-            return_type: Type::Double,
-            name: "".to_string(),
-            args: vec![],
-            statements: vec![],
-            return_val: "".to_string(),
+    #[test]
+    fn lifts_lambdas_into_functions() {
+        let module = compile_source("k = 2\nscale(v) = map(v, x -> k * x)\nscale([1])").unwrap();
+        let lambda = module.function("lambda.1").unwrap();
+        let function = Type::Function(vec![Type::Double], Box::new(Type::Double));
+        let env = Temp::new("env_0".to_string(), function.clone());
+        assert_eq!(
+            lambda.params,
+            vec![env.clone(), Temp::new("x_0".to_string(), Type::Double)]
+        );
+        assert_eq!(
+            lambda.blocks[0].statements[0],
+            Statement::assign(
+                Temp::new("k_0".to_string(), Type::Long),
+                Operation::Capture(Value::Temp(env), 0)
+            )
+        );
+        let scale = module.function("scale").unwrap();
+        assert_eq!(
+            scale.blocks[0].statements[1].operation,
+            Operation::Closure(
+                "lambda.1".to_string(),
+                vec![Value::Temp(Temp::new("k_0".to_string(), Type::Long))]
+            )
+        );
+        // a named function is wrapped once however often it is passed
+        let module = compile_source("sq(x) = x * x\nmap([1], sq); map([2], sq)").unwrap();
+        assert!(module.function("lambda.1").is_some());
+        assert!(module.function("lambda.2").is_none());
+        let module = compile_source("a = 2\nf(v) = map(v, a -> a * 2)[0] + a\nf([1])").unwrap();
+        let a = Global {
+            name: "a".to_string(),
+            ty: Type::Long,
         };
+        assert_eq!(module.globals, vec![a]);
     }
-}
 
-impl fmt::Display for Function {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        let export = if self.export { "export " } else { "" };
-        let args = self
-            .args
-            .iter()
-            .map(|arg| format!("d %{arg}"))
-            .collect::<Vec<String>>()
-            .join(", ");
-        let statements = self
-            .statements
-            .iter()
-            .map(|stmt| stmt.to_string())
-            .collect::<Vec<String>>()
-            .join("\n");
-
-        write!(
-            f,
-            "{} function {} ${}({}) {{\n@start\n{}\n\tret {}}}\n",
-            export, self.return_type, self.name, args, statements, self.return_val
-        )
+    #[test]
+    fn binds_let_names_to_copies() {
+        let module =
+            compile_source("f(x) = let t = x * 2 in t + t\nx = 1\nlet x = 2.5 in x\nx").unwrap();
+        let f = module.function("f").unwrap();
+        let t = Value::Temp(Temp::new("_2".to_string(), Type::Double));
+        assert_eq!(
+            f.blocks[0].statements[2].operation,
+            Operation::Add(t.clone(), t)
+        );
+        // the name only stands for the copy up to the end of the `let`
+        let main = module.function("main").unwrap();
+        let x = Temp::new("x_0".to_string(), Type::Long);
+        assert_eq!(
+            main.blocks[0].statements[3].operation,
+            Operation::Print(Value::Temp(x), PrintFormat::Fixed(4))
+        );
+        let module = compile_source("a = 2\nf(x) = (let a = x in a) + a\nf(1)").unwrap();
+        let a = Global {
+            name: "a".to_string(),
+            ty: Type::Long,
+        };
+        assert_eq!(module.globals, vec![a]);
     }
-}
 
-#[derive(PartialEq, Debug, Clone)]
-struct Statement {
-    identifier: String,
-    assign_type: Type,
-    operation: Operation,
-}
+    #[test]
+    fn calls_variables_holding_functions() {
+        let module = compile_source("adder(n) = x -> x + n\nf = adder(1)\nf(2)").unwrap();
+        let main = module.function("main").unwrap();
+        let f = Temp::new(
+            "f_0".to_string(),
+            Type::Function(vec![Type::Double], Box::new(Type::Double)),
+        );
+        assert_eq!(
+            main.blocks[0].statements[2].operation,
+            Operation::CallClosure(Value::Temp(f), vec![Value::Double(2.0)])
+        );
+        let error = compile_source("x = 2\nx(3)").unwrap_err();
+        assert!(matches!(
+            error.downcast_ref(),
+            Some(CompileError::NotAFunction(name, Type::Long)) if name == "x"
+        ));
+        let error = compile_source("f = x -> x\nf(1, 2)").unwrap_err();
+        assert!(matches!(
+            error.downcast_ref(),
+            Some(CompileError::ArgumentCount(name, 1, 2)) if name == "f"
+        ));
+    }
+
+    #[test]
+    fn types_functions_from_inference() {
+        let module =
+            compile_source("steps(n: int) = n * 2\ntwice(a) = steps(a)\ntwice(3)").unwrap();
+        let twice = module.function("twice").unwrap();
+        assert_eq!(twice.params[0].ty, Type::Long);
+        assert_eq!(twice.return_type, Type::Long);
+        let error = compile_source("steps(n: int) = n * 2\nsteps(2.5)").unwrap_err();
+        assert!(matches!(
+            error.downcast_ref(),
+            Some(CompileError::TypeMismatch(Type::Long, Type::Double))
+        ));
+        assert_eq!(
+            format!("{error:#}"),
+            "argument 1 of `steps`: expected int but found float"
+        );
+    }
 
-impl Statement {
-    fn new(identifier: String, operation: Operation) -> Self {
-        Statement {
-            identifier,
-            assign_type: Type::Double,
-            operation,
+    #[test]
+    fn errors_on_function_reading_undeclared_variable() {
+        assert!(compile_source("f(x) = x * rate\nrate = 2\nf(1)").is_err());
+    }
+
+    #[test]
+    fn warns_about_unused_declarations() {
+        let warnings = lint(&parse("x = 1\n_y = 2\nf(a) = a\ng(b) = b\nf(3)"));
+        let lines: Vec<usize> = warnings.iter().map(|w| w.span.line).collect();
+        assert_eq!(lines, vec![1, 4]);
+        assert!(warnings.iter().all(|w| w.severity == Severity::Warning));
+        // a name read by a statement with an error is still used
+        for source in [
+            "x = 2\nx +",
+            "x = 2\ny = (x",
+            "x = 2\nx $ 1",
+            "x = 2\nf(a) = a + x *",
+        ] {
+            assert!(lint(&parse(source)).is_empty(), "{source}");
         }
     }
-}
 
-impl fmt::Display for Statement {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(
-            f,
-            "\t{} ={} {}",
-            self.identifier, self.assign_type, self.operation
+    #[test]
+    fn sets_args_only_when_read() {
+        let module = compile_source(
+            "scale(k) = arg(k) * 2
+scale(1) + input()",
         )
+        .unwrap();
+        let main = module.function("main").unwrap();
+        let argc = Value::Temp(main.params[0].clone());
+        let argv = Value::Temp(main.params[1].clone());
+        assert_eq!(
+            main.blocks[0].statements[0].operation,
+            Operation::SetArgs(argc, argv)
+        );
+        let scale = module.function("scale").unwrap();
+        assert_eq!(scale.return_type, Type::Double);
+        let module = compile_source("input() * 2").unwrap();
+        let main = module.function("main").unwrap();
+        assert!(main.blocks[0].statements[0].operation == Operation::Input);
+        assert!(compile_source("arg(1.5)").is_err());
     }
-}
-
-#[derive(PartialEq, Debug, Clone)]
-enum Operation {
-    Add(String, String),
-    Sub(String, String),
-    Div(String, String),
-    Mul(String, String),
-    Pow(String, String),
-    Call(String),
-}
 
-impl fmt::Display for Operation {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(
-            f,
-            "{}",
-            match self.clone() {
-                Operation::Add(x, y) => format!("add %{x} %{y}"),
-                Operation::Sub(x, y) => format!("sub %{x} %{y}"),
-                Operation::Mul(x, y) => format!("mul %{x} %{y}"),
-                Operation::Div(x, y) => format!("div %{x} %{y}"),
-                Operation::Pow(x, y) => format!("call $pow(d {x}, d {y})"),
-                Operation::Call(func) => format!("call {func}"),
-            }
+    #[test]
+    fn rejects_redefined_functions() {
+        let error = compile_source("f(x) = x\nf(x) = x * 2\nf(3)").unwrap_err();
+        assert!(matches!(
+            error.downcast_ref(),
+            Some(CompileError::FunctionRedefined(name)) if name == "f"
+        ));
+        let diagnostics = compile(
+            parse("f(x) = x\nf(x) = x * 2\nf(3)"),
+            PrintFormat::default(),
         )
+        .unwrap_err();
+        let lines: Vec<usize> = diagnostics.iter().map(|d| d.span.line).collect();
+        assert_eq!(lines, vec![2]);
     }
-}
 
-#[derive(PartialEq, Debug, Clone)]
-enum Type {
-    Word,
-    Long,
-    Single,
-    Double,
-}
+    #[test]
+    fn rejects_functions_and_variables_with_the_same_name() {
+        for source in [
+            "f(x) = x\nf = 3\nf + 1",
+            "f = 3\nf(x) = x\nf(1)",
+            "f = x -> x\nf() = 1",
+        ] {
+            let error = compile_source(source).unwrap_err();
+            assert!(
+                matches!(
+                    error.downcast_ref(),
+                    Some(CompileError::FunctionAndVariable(name)) if name == "f"
+                ),
+                "{source}"
+            );
+        }
+    }
 
-impl fmt::Display for Type {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(
-            f,
-            "{}",
-            match self.clone() {
-                Type::Word => "w",
-                Type::Long => "l",
-                Type::Single => "s",
-                Type::Double => "d",
-            }
-        )
+    #[test]
+    fn declares_functions_with_empty_parameters() {
+        let module = compile_source("c() = 5\nc()\nc + 1").unwrap();
+        let c = module.function("c").unwrap();
+        assert!(c.params.is_empty());
+        assert_eq!(c.return_type, Type::Long);
+        assert!(module.globals.is_empty());
+    }
+
+    #[test]
+    fn reports_declarations_reading_their_own_name() {
+        for source in ["f(x) = f(x - 1)\nf(3)\n1", "x = x + 1\nx\n2"] {
+            let diagnostics = compile(parse(source), PrintFormat::default()).unwrap_err();
+            assert_eq!(diagnostics.len(), 1, "{source}");
+            assert_eq!(diagnostics[0].span.line, 1);
+            let error = diagnostics[0].error.downcast_ref::<CompileError>().unwrap();
+            assert_eq!(error.code(), "E0005");
+        }
+    }
+
+    #[test]
+    fn skips_uses_of_failed_declarations() {
+        let mut compiler = Compiler::new(PrintFormat::default());
+        let mut statements = parse("x = y + 1\nx * 2\nz").statements.into_iter();
+        assert!(compiler.statement(statements.next().unwrap()).is_err());
+        assert!(compiler.statement(statements.next().unwrap()).is_ok());
+        assert!(compiler.statement(statements.next().unwrap()).is_err());
     }
 }
@@ -1,17 +1,339 @@
+use std::fmt;
 use thiserror::Error;
 
-use crate::parser::ParseToken;
+use crate::{
+    ir::Type,
+    parser::{ParseToken, Span},
+};
 
 #[derive(Error, Debug)]
 pub enum CompileError {
     #[error("found invalid character: {0}")]
     InvalidCharacter(char),
+    #[error("invalid number literal {0}: {1}")]
+    InvalidNumber(String, String),
+    #[error("`\\` can only continue a statement at the end of a line")]
+    InvalidContinuation,
     #[error("invalid identifier used for assignment")]
     InvalidAssignment,
+    #[error("opening parenthesis is never closed")]
+    UnclosedParen,
+    #[error("closing parenthesis without a matching opening one")]
+    UnexpectedCloseParen,
+    #[error("expected an operand before `{0}`")]
+    MissingOperand(ParseToken),
+    #[error("operator `{0}` has no right operand")]
+    TrailingOperator(ParseToken),
+    #[error("empty argument in function call")]
+    EmptyArgument,
+    #[error("expected an operator before `{0}`")]
+    MissingOperator(ParseToken),
+    #[error("unexpected `{0}` in expression")]
+    UnexpectedToken(ParseToken),
+    #[error("expression is empty")]
+    EmptyExpression,
     #[error("invalid token found in RPN list")]
     InvalidToken(ParseToken),
     #[error("not enough operands in stack for operator")]
     OperandError,
     #[error("name not found: {0}")]
     NameError(String),
+    #[error("invalid print format: {0}")]
+    InvalidFormat(String),
+    #[error("malformed ir in function ${0}: {1}")]
+    InvalidIr(String, String),
+    #[error("the left side of `=` must be `name` or `name(param, ...)`")]
+    ExpressionAssignment,
+    #[error("parameter `{0}` is declared more than once")]
+    DuplicateParameter(String),
+    #[error("a statement can only have one `=`")]
+    MultipleAssignment,
+    #[error("`{0}` is read by a function as {}, it can't be assigned a {} value", .1.name(), .2.name())]
+    GlobalType(String, Type, Type),
+    #[error("`{0}` takes {1} arguments but is given {2}")]
+    ArgumentCount(String, usize, usize),
+    #[error("`{0}` is not a type, the types are `int`, `float`, `bool`, `vector`, `matrix` and functions like `(float) -> float`")]
+    UnknownType(String),
+    #[error("expected a type after `:`")]
+    MissingType,
+    #[error("expected {} but found {}", .0.name(), .1.name())]
+    TypeMismatch(Type, Type),
+    #[error("opening bracket is never closed")]
+    UnclosedBracket,
+    #[error("closing bracket without a matching opening one")]
+    UnexpectedCloseBracket,
+    #[error("`{0}` can't combine {1} with {2}")]
+    DimensionMismatch(String, String, String),
+    #[error("`{0}` needs a square matrix but found a {1}x{2} one")]
+    NotSquare(String, usize, usize),
+    #[error("the rows of a matrix have different lengths, {0} and {1}")]
+    RaggedMatrix(usize, usize),
+    #[error("`{0}` has type {} and can't be called", .1.name())]
+    NotAFunction(String, Type),
+    #[error("`let` is never followed by `in`")]
+    LetWithoutIn,
+    #[error("`let` needs a name and `=` before its value")]
+    LetWithoutValue,
+    #[error("opening brace is never closed")]
+    UnclosedBrace,
+    #[error("closing brace without a matching opening one")]
+    UnexpectedCloseBrace,
+    #[error("a block has to end with the expression giving its value")]
+    MissingBlockValue,
+    #[error("only the last statement of a block can be an expression")]
+    BlockExpression,
+    #[error("string is never closed")]
+    UnterminatedString,
+    #[error("expected `import \"file.nms\"` or `from \"file.nms\" import name, ...`")]
+    InvalidImport,
+    #[error("can't find `{0}` to import")]
+    ImportNotFound(String),
+    #[error("import cycle: {0}")]
+    ImportCycle(String),
+    #[error("`{0}` is not declared in {1}")]
+    MissingImport(String, String),
+    #[error("two different imported files are named `{0}`")]
+    DuplicateNamespace(String),
+    #[error("the function `{0}` is already declared")]
+    FunctionRedefined(String),
+    #[error("`{0}` can't be both a function and a variable")]
+    FunctionAndVariable(String),
+    #[error("`{0}` is declared but never used")]
+    UnusedDeclaration(String),
+}
+
+impl CompileError {
+    /// A code that identifies the kind of error and never changes once given out, explained by
+    /// `numerus explain <code>`. Warnings start with `W`.
+    pub fn code(&self) -> &'static str {
+        match self {
+            CompileError::InvalidCharacter(_) => "E0001",
+            CompileError::InvalidNumber(..) => "E0006",
+            CompileError::InvalidContinuation => "E0007",
+            CompileError::InvalidAssignment => "E0002",
+            CompileError::UnclosedParen => "E0008",
+            CompileError::UnexpectedCloseParen => "E0009",
+            CompileError::MissingOperand(_) => "E0010",
+            CompileError::TrailingOperator(_) => "E0011",
+            CompileError::EmptyArgument => "E0012",
+            CompileError::MissingOperator(_) => "E0013",
+            CompileError::UnexpectedToken(_) => "E0014",
+            CompileError::EmptyExpression => "E0015",
+            CompileError::InvalidToken(_) => "E0003",
+            CompileError::OperandError => "E0004",
+            CompileError::NameError(_) => "E0005",
+            CompileError::InvalidFormat(_) => "E0016",
+            CompileError::InvalidIr(..) => "E0017",
+            CompileError::ExpressionAssignment => "E0018",
+            CompileError::DuplicateParameter(_) => "E0019",
+            CompileError::MultipleAssignment => "E0020",
+            CompileError::GlobalType(..) => "E0021",
+            CompileError::ArgumentCount(..) => "E0022",
+            CompileError::UnknownType(_) => "E0023",
+            CompileError::MissingType => "E0024",
+            CompileError::TypeMismatch(..) => "E0025",
+            CompileError::UnclosedBracket => "E0026",
+            CompileError::UnexpectedCloseBracket => "E0027",
+            CompileError::DimensionMismatch(..) => "E0028",
+            CompileError::NotSquare(..) => "E0029",
+            CompileError::RaggedMatrix(..) => "E0030",
+            CompileError::NotAFunction(..) => "E0031",
+            CompileError::LetWithoutIn => "E0032",
+            CompileError::LetWithoutValue => "E0033",
+            CompileError::UnclosedBrace => "E0034",
+            CompileError::UnexpectedCloseBrace => "E0035",
+            CompileError::MissingBlockValue => "E0036",
+            CompileError::BlockExpression => "E0037",
+            CompileError::UnterminatedString => "E0038",
+            CompileError::InvalidImport => "E0039",
+            CompileError::ImportNotFound(_) => "E0040",
+            CompileError::ImportCycle(_) => "E0041",
+            CompileError::MissingImport(..) => "E0042",
+            CompileError::DuplicateNamespace(_) => "E0043",
+            CompileError::FunctionRedefined(_) => "E0044",
+            CompileError::FunctionAndVariable(_) => "E0045",
+            CompileError::UnusedDeclaration(_) => "W0001",
+        }
+    }
+
+    /// A hint at how to fix the error, if there is a usual one.
+    pub fn help(&self) -> Option<&'static str> {
+        match self {
+            CompileError::InvalidContinuation => Some("move the `\\` to the end of the line"),
+            CompileError::UnclosedParen => Some("add a `)` to close it"),
+            CompileError::UnclosedBracket => Some("add a `]` to close it"),
+            CompileError::UnclosedBrace => Some("add a `}` to close it"),
+            CompileError::UnterminatedString => Some("add a `\"` to close it"),
+            CompileError::ImportNotFound(_) => Some(
+                "files are looked for next to the importing one, then in the `-I` directories and `NUMERUS_PATH`",
+            ),
+            CompileError::DuplicateNamespace(_) => {
+                Some("the names of a file are under its name, rename one of the files")
+            }
+            CompileError::BlockExpression => {
+                Some("assign it to a name, or move it to the end of the block")
+            }
+            CompileError::ExpressionAssignment => {
+                Some("move the calculation to the right side of the `=`")
+            }
+            CompileError::DuplicateParameter(_) => Some("give each parameter its own name"),
+            CompileError::FunctionRedefined(_) => Some("give the new function its own name"),
+            CompileError::FunctionAndVariable(_) => Some("rename the function or the variable"),
+            CompileError::MultipleAssignment => Some("split it into one statement per `=`"),
+            CompileError::TrailingOperator(_) => Some("add the missing operand after it"),
+            CompileError::EmptyArgument => Some("remove the extra `,`"),
+            CompileError::NameError(_) => Some("assign to it on an earlier line, like `x = 1`"),
+            CompileError::InvalidFormat(_) => {
+                Some("use fixed:N, sci:N, general, hex-float or exact")
+            }
+            CompileError::GlobalType(..) => Some("convert the value with `int(...)` first"),
+            CompileError::TypeMismatch(Type::Long, Type::Double) => {
+                Some("convert the value with `int(...)` first")
+            }
+            CompileError::LetWithoutIn => Some("add `in` and the expression using the name"),
+            CompileError::UnusedDeclaration(_) => {
+                Some("remove it, or start its name with `_` to keep it")
+            }
+            _ => None,
+        }
+    }
+}
+
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Severity::Error => write!(f, "error"),
+            Severity::Warning => write!(f, "warning"),
+        }
+    }
+}
+
+/// An error together with the part of the source it is about.
+#[derive(Error, Debug)]
+#[error("{}:{}: {:#}", .span.line, .span.column, .error)]
+pub struct Diagnostic {
+    pub error: anyhow::Error,
+    pub span: Span,
+    pub severity: Severity,
+    /// the imported file the span is in, `None` when it is in the file being compiled
+    pub file: Option<String>,
+}
+
+impl Diagnostic {
+    pub fn new(error: impl Into<anyhow::Error>, span: Span) -> Self {
+        Diagnostic {
+            error: error.into(),
+            span,
+            severity: Severity::Error,
+            file: None,
+        }
+    }
+
+    pub fn warning(error: impl Into<anyhow::Error>, span: Span) -> Self {
+        Diagnostic {
+            severity: Severity::Warning,
+            ..Diagnostic::new(error, span)
+        }
+    }
+
+    fn compile_error(&self) -> Option<&CompileError> {
+        self.error.downcast_ref()
+    }
+
+    /// `file:line:column: error[E0001]: message`, with the help on the next line.
+    pub fn human(&self, file: &str) -> String {
+        let Span { line, column, .. } = self.span;
+        let code = self
+            .compile_error()
+            .map_or(String::new(), |error| format!("[{}]", error.code()));
+        let mut text = format!(
+            "{file}:{line}:{column}: {}{code}: {:#}",
+            self.severity, self.error
+        );
+        if let Some(help) = self.compile_error().and_then(CompileError::help) {
+            text.push_str(&format!("\n  help: {help}"));
+        }
+        text
+    }
+
+    /// One line of json, `code` and `help` are null when there isn't one.
+    pub fn json(&self, file: &str) -> String {
+        let optional = |value: Option<&str>| value.map_or("null".to_string(), json_string);
+        let fields = [
+            (
+                "code",
+                optional(self.compile_error().map(CompileError::code)),
+            ),
+            ("message", json_string(&format!("{:#}", self.error))),
+            ("severity", json_string(&self.severity.to_string())),
+            ("file", json_string(file)),
+            ("line", self.span.line.to_string()),
+            ("column", self.span.column.to_string()),
+            ("end_line", self.span.end_line.to_string()),
+            ("end_column", self.span.end_column.to_string()),
+            (
+                "help",
+                optional(self.compile_error().and_then(CompileError::help)),
+            ),
+        ];
+        let fields: Vec<String> = fields
+            .iter()
+            .map(|(key, value)| format!("\"{key}\":{value}"))
+            .collect();
+        format!("{{{}}}", fields.join(","))
+    }
+}
+
+fn json_string(text: &str) -> String {
+    let mut quoted = String::from("\"");
+    for ch in text.chars() {
+        match ch {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\t' => quoted.push_str("\\t"),
+            '\r' => quoted.push_str("\\r"),
+            ch if (ch as u32) < 0x20 => quoted.push_str(&format!("\\u{:04x}", ch as u32)),
+            ch => quoted.push(ch),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn diagnostic() -> Diagnostic {
+        let span = Span {
+            line: 2,
+            column: 5,
+            end_line: 2,
+            end_column: 6,
+        };
+        Diagnostic::new(CompileError::UnclosedParen, span)
+    }
+
+    #[test]
+    fn renders_human_diagnostic() {
+        assert_eq!(
+            diagnostic().human("a.nms"),
+            "a.nms:2:5: error[E0008]: opening parenthesis is never closed\n  help: add a `)` to close it"
+        );
+    }
+
+    #[test]
+    fn renders_json_diagnostic() {
+        assert_eq!(
+            diagnostic().json("dir/\"a\".nms"),
+            r#"{"code":"E0008","message":"opening parenthesis is never closed","severity":"error","file":"dir/\"a\".nms","line":2,"column":5,"end_line":2,"end_column":6,"help":"add a `)` to close it"}"#
+        );
+    }
 }
@@ -0,0 +1,753 @@
+/// Longer descriptions of each diagnostic code, printed by `numerus explain <code>`.
+const EXPLANATIONS: &[(&str, &str)] = &[
+    (
+        "E0001",
+        "A character that isn't part of the language was found.
+
+Only letters, digits, `_`, `.`, the operators `+ - * / % ^ @`, the comparisons
+`== != < <= > >=`, `=`, `->`, `,`, `:`, `;`, parenthesis, brackets, braces, `\"` around the file of an
+import and `\\` at the end of a line can appear outside of a comment. A `!` is only allowed as part of `!=`, `not` negates a bool.
+
+Erroneous code example:
+
+    price = 4 $ 2
+
+Remove the character, or start a comment with `#` if it was meant as one:
+
+    price = 4 * 2",
+    ),
+    (
+        "E0002",
+        "The left side of an assignment is not a name or a function signature.
+
+Erroneous code example:
+
+    2 = x + 1
+
+Assign to a name instead:
+
+    y = x + 1",
+    ),
+    (
+        "E0003",
+        "The compiler found a token it can't evaluate in an expression.
+
+This points to a bug in the compiler rather than in the program, the parser should
+have rejected the expression with a more specific error first. Please report it with
+the line that caused it.",
+    ),
+    (
+        "E0004",
+        "An operator or call did not have enough operands when it was compiled.
+
+This points to a bug in the compiler rather than in the program, the parser should
+have rejected the expression with a more specific error first. Please report it with
+the line that caused it.",
+    ),
+    (
+        "E0005",
+        "A name was used that hasn't been assigned yet.
+
+Names have to be assigned on an earlier line than the one using them.
+
+Erroneous code example:
+
+    total = price * 2
+    price = 4
+
+Assign the name before using it:
+
+    price = 4
+    total = price * 2",
+    ),
+    (
+        "E0006",
+        "A number literal is malformed.
+
+Literals are decimal with an optional exponent like `6.022e23`, or integers written in
+hexadecimal with `0x` or binary with `0b`. `_` may only appear between two digits, and an
+integer has to fit in 64 bits.
+
+Erroneous code example:
+
+    mask = 0xG1
+    big = 1__000
+
+Use only digits allowed for the literal's base:
+
+    mask = 0xF1
+    big = 1_000",
+    ),
+    (
+        "E0007",
+        "A `\\` appeared somewhere other than the end of a line.
+
+`\\` continues a statement onto the next line, so nothing but a comment may follow it.
+
+Erroneous code example:
+
+    total = 1 + \\ 2
+
+Break the line after the `\\`:
+
+    total = 1 + \\
+        2",
+    ),
+    (
+        "E0008",
+        "An opening parenthesis is never closed.
+
+A statement continues onto the next line while a parenthesis is open, but it ends at a
+blank line or the end of the file.
+
+Erroneous code example:
+
+    area = (width + 2 * height
+
+Close the parenthesis:
+
+    area = (width + 2) * height",
+    ),
+    (
+        "E0009",
+        "A closing parenthesis has no opening parenthesis to match.
+
+Erroneous code example:
+
+    area = width + 2) * height
+
+Add the opening parenthesis, or remove the extra closing one:
+
+    area = (width + 2) * height",
+    ),
+    (
+        "E0010",
+        "An operator has nothing on its left side.
+
+Only `-` can appear without a left operand, where it negates what follows.
+
+Erroneous code example:
+
+    x = * 2
+
+Add the left operand:
+
+    x = 3 * 2",
+    ),
+    (
+        "E0011",
+        "An operator has nothing on its right side.
+
+Erroneous code example:
+
+    x = 3 *
+
+Add the right operand:
+
+    x = 3 * 2",
+    ),
+    (
+        "E0012",
+        "A function call has an empty argument.
+
+Erroneous code example:
+
+    f(1, , 2)
+
+Remove the extra comma, or fill in the argument:
+
+    f(1, 2)",
+    ),
+    (
+        "E0013",
+        "Two operands are next to each other without an operator between them.
+
+Multiplication is never implied.
+
+Erroneous code example:
+
+    y = 2(x + 1)
+
+Write the operator:
+
+    y = 2 * (x + 1)",
+    ),
+    (
+        "E0014",
+        "A token appeared where it can't be used in an expression.
+
+This happens with a `,` outside of a function call's parenthesis.
+
+Erroneous code example:
+
+    x = (1, 2)
+
+Only separate values with `,` when calling a function:
+
+    x = max(1, 2)",
+    ),
+    (
+        "E0015",
+        "An assignment or expression has nothing in it.
+
+Erroneous code example:
+
+    x =
+
+Give the assignment a value:
+
+    x = 1",
+    ),
+    (
+        "E0016",
+        "A print format is not one of the supported ones.
+
+Formats are `fixed N`, `sci N`, `general`, `hex-float` and `exact` in a `#fmt` line, or
+`fixed:N`, `sci:N` and the others on the command line with `--print-format`.
+
+Erroneous code example:
+
+    #fmt fixed
+
+Give fixed and sci formats a precision:
+
+    #fmt fixed 2",
+    ),
+    (
+        "E0017",
+        "The compiler generated malformed ir.
+
+This is a bug in the compiler rather than in the program. Please report it with the
+program that caused it.",
+    ),
+    (
+        "E0018",
+        "The left side of an assignment is neither a name nor a function signature.
+
+Assignments either give a name to a value, `name = ...`, or declare a function with
+its parameters, `name(param, ...) = ...`. Parameters have to be plain names.
+
+Erroneous code example:
+
+    f(x + 1) = 2 * x
+    g x = x
+
+Declare the parameters by name and do the calculation on the right side:
+
+    f(x) = 2 * (x - 1)
+    g(x) = x",
+    ),
+    (
+        "E0019",
+        "A function declares the same parameter twice.
+
+Erroneous code example:
+
+    area(w, w) = w * w
+
+Give each parameter a different name:
+
+    area(w, h) = w * h",
+    ),
+    (
+        "E0020",
+        "A statement has more than one `=`.
+
+Assignments can't be chained, and `=` never compares two values.
+
+Erroneous code example:
+
+    x = y = 1
+
+Assign each name in its own statement:
+
+    y = 1
+    x = y",
+    ),
+    (
+        "E0021",
+        "A variable read by a function is assigned a float after holding an int.
+
+Functions read variables while they run, so a variable they read keeps the type it had
+when the function was declared. An int can be assigned to a float variable, but not the
+other way around.
+
+Erroneous code example:
+
+    count = 3
+    total(x) = x * count
+    count = 2.5
+
+Convert the value to an int, or start the variable out as a float:
+
+    count = 3.0
+    total(x) = x * count
+    count = 2.5",
+    ),
+    (
+        "E0022",
+        "A function is called with the wrong number of arguments.
+
+`sum` and `prod` over a range always take the loop variable, the first and last value and
+the expression to add up or multiply, like `sum(k, 1, n, k^2)`.
+
+Erroneous code example:
+
+    area(w, h) = w * h
+    area(2)
+
+Pass one argument for each parameter:
+
+    area(2, 3)",
+    ),
+    (
+        "E0023",
+        "A type annotation names a type that doesn't exist.
+
+The types are `int`, `float`, `bool`, `vector` and `matrix`. A function is typed by its
+parameters and result, like `(float, int) -> float`.
+
+Erroneous code example:
+
+    half(x: double) = x / 2
+
+Use one of the types:
+
+    half(x: float) = x / 2",
+    ),
+    (
+        "E0024",
+        "A `:` for a type annotation isn't followed by a type.
+
+Erroneous code example:
+
+    half(x): = x / 2
+
+Write the type after the `:`, or remove the `:`:
+
+    half(x): float = x / 2",
+    ),
+    (
+        "E0025",
+        "A value has a different type than where it is used needs.
+
+An int is turned into a float wherever a float is needed, but a float is never turned
+into an int without `int(...)`. Function parameters without an annotation get their type
+from how the function uses them, and are floats if nothing needs them to be ints.
+A bool is never a number, arithmetic needs numbers and `and`, `or` and `not` need bools.
+
+Erroneous code example:
+
+    steps(n: int) = n * 2
+    steps(2.5)
+    half(x): int = x / 2.0
+
+Convert the value with `int(...)`, or change the annotation:
+
+    steps(int(2.5))
+    half(x): float = x / 2.0",
+    ),
+    (
+        "E0026",
+        "An opening bracket is never closed.
+
+Brackets hold the elements of a vector like `[1, 2, 3]` or an index like `v[0]`. A
+statement continues onto the next line while a bracket is open, but it ends at a blank line
+or the end of the file.
+
+Erroneous code example:
+
+    samples = [1.5, 2.5, 4.0
+
+Close the bracket:
+
+    samples = [1.5, 2.5, 4.0]",
+    ),
+    (
+        "E0027",
+        "A closing bracket has no opening bracket to match.
+
+Erroneous code example:
+
+    first = samples 0]
+
+Add the opening bracket, or remove the extra closing one:
+
+    first = samples[0]",
+    ),
+    (
+        "E0028",
+        "Two vectors or matrices whose sizes don't fit together are combined.
+
+Arithmetic on two vectors needs them to have the same length. `a @ b` needs `a` to have as
+many columns as `b` has rows, where a vector on the left is a row and a vector on the right
+is a column. `solve(a, b)` needs `b` to have one element for every row of `a`. The sizes are
+checked while compiling when they are known and while running otherwise.
+
+Erroneous code example:
+
+    rotation = [[0, -1], [1, 0]]
+    rotation @ [1, 2, 3]
+
+Make the sizes match:
+
+    rotation = [[0, -1], [1, 0]]
+    rotation @ [1, 2]",
+    ),
+    (
+        "E0029",
+        "A matrix that isn't square is used where a square one is needed.
+
+`det`, `inv` and `solve` only work on matrices with as many rows as columns.
+
+Erroneous code example:
+
+    det([[1, 2, 3], [4, 5, 6]])
+
+Use a square matrix:
+
+    det([[1, 2], [4, 5]])",
+    ),
+    (
+        "E0030",
+        "The rows of a matrix literal have different lengths.
+
+A matrix is written as a vector of its rows, every row needs the same number of elements.
+
+Erroneous code example:
+
+    m = [[1, 2], [3]]
+
+Give every row the same length:
+
+    m = [[1, 2], [3, 0]]",
+    ),
+    (
+        "E0031",
+        "A variable that doesn't hold a function is called.
+
+Only functions, builtins and variables holding a lambda or a function can be called.
+
+Erroneous code example:
+
+    scale = 2
+    scale(3)
+
+Multiply instead, or assign a lambda to the variable:
+
+    scale = x -> 2 * x
+    scale(3)",
+    ),
+    (
+        "E0032",
+        "A `let` binds a name that no `in` ever uses.
+
+`let name = value in body` makes `name` stand for `value` inside `body` only, so the
+`in` and the body are required. The `in` has to come before the parenthesis, bracket or
+argument around the `let` ends.
+
+Erroneous code example:
+
+    f(x) = (let t = x ^ 2) * t
+
+Use the name after `in`:
+
+    f(x) = let t = x ^ 2 in t * t",
+    ),
+    (
+        "E0034",
+        "A `{` opening a block is never closed.
+
+A block can go over several lines and hold blank lines, so a missing `}` makes it run to
+the end of the file.
+
+Erroneous code example:
+
+    f(x) = {
+        a = x * 2
+        a + 1
+
+    f(2)
+
+Close the block after its last statement:
+
+    f(x) = {
+        a = x * 2
+        a + 1
+    }
+
+    f(2)",
+    ),
+    (
+        "E0035",
+        "A `}` was found without a `{` before it.
+
+Erroneous code example:
+
+    f(x) = x * 2 }
+
+Remove the `}` or add the missing `{`:
+
+    f(x) = x * 2",
+    ),
+    (
+        "E0036",
+        "A block doesn't end with an expression.
+
+The value of a block is its last statement, so that statement can't be an assignment and
+the block can't be empty.
+
+Erroneous code example:
+
+    f(x) = { a = x * 2; b = a + 1 }
+
+End the block with the value it gives:
+
+    f(x) = { a = x * 2; b = a + 1; a * b }",
+    ),
+    (
+        "E0037",
+        "A statement other than the last one in a block isn't an assignment.
+
+Only the last statement gives the block its value, an expression anywhere else would be
+computed and thrown away. The statements before it have to be `name = value`.
+
+Erroneous code example:
+
+    f(x) = { x * 2; x + 1 }
+
+Assign the value to a name and use it:
+
+    f(x) = { a = x * 2; a + 1 }",
+    ),
+    (
+        "E0038",
+        "A string is missing its closing `\"`.
+
+Strings are only used to name the file of an import, and have to end on the line they start.
+
+Erroneous code example:
+
+    import \"finance.nms
+
+Close the string:
+
+    import \"finance.nms\"",
+    ),
+    (
+        "E0039",
+        "An import statement isn't written like one.
+
+A whole file is imported with `import \"file.nms\"`, its names are then used with the name of
+the file in front like `file.name`. Some names can be imported to be used on their own with
+`from \"file.nms\" import name, other`. A module of the standard library is named without quotes,
+like `import std.stats`.
+
+Erroneous code example:
+
+    import finance.nms
+
+Put the file between double quotes:
+
+    import \"finance.nms\"",
+    ),
+    (
+        "E0040",
+        "The file of an import can't be found or read.
+
+The file is looked for in the directory of the file importing it, then in every directory given
+with `-I` in order, then in every directory listed in the `NUMERUS_PATH` environment variable.
+
+Erroneous code example:
+
+    import \"finance.nms\"
+
+Run with the directory holding the file:
+
+    numerus main.nms -I lib",
+    ),
+    (
+        "E0041",
+        "A file imports itself, directly or through the files it imports.
+
+The declarations of a file are added before the statements after its import, so a file can't
+use names from a file that needs its own names first.
+
+Erroneous code example, with `a.nms` holding:
+
+    import \"b.nms\"
+
+and `b.nms` holding:
+
+    import \"a.nms\"
+
+Move the declarations both files need into a third file that they both import.",
+    ),
+    (
+        "E0042",
+        "A `from` import names something the file doesn't declare.
+
+Only the names assigned in the imported file can be imported from it.
+
+Erroneous code example, with `geo.nms` declaring `dist` and `area`:
+
+    from \"geo.nms\" import distance
+
+Import a name the file declares:
+
+    from \"geo.nms\" import dist",
+    ),
+    (
+        "E0043",
+        "Two different imported files have the same name.
+
+The names of an imported file are used with the name of the file in front, like `geo.dist`, so
+two files named `geo.nms` in different directories can't both be imported.
+
+Erroneous code example:
+
+    import \"geo.nms\"
+    import \"lib/geo.nms\"
+
+Rename one of the files:
+
+    import \"geo.nms\"
+    import \"lib/shapes.nms\"",
+    ),
+    (
+        "E0044",
+        "A function is declared again with a name an earlier function already has.
+
+Unlike a variable, a function can't be assigned a new value, calls made before and after the
+second declaration would disagree about what it does.
+
+Erroneous code example:
+
+    f(x) = x
+    f(x) = x * 2
+
+Give the new function its own name:
+
+    f(x) = x
+    double(x) = x * 2",
+    ),
+    (
+        "E0045",
+        "A variable is assigned with the name of a function, or a function is declared with the name
+of a variable.
+
+A name stands for one thing everywhere in a program, so function bodies and the statements
+outside of them can't disagree about which one it means.
+
+Erroneous code example:
+
+    f(x) = x
+    f = 3
+
+Give the variable its own name:
+
+    f(x) = x
+    k = 3",
+    ),
+    (
+        "E0033",
+        "A `let` isn't followed by a name and `=`.
+
+Erroneous code example:
+
+    f(x) = let x ^ 2 in t * t
+
+Name the value before using it:
+
+    f(x) = let t = x ^ 2 in t * t",
+    ),
+    (
+        "W0001",
+        "A name is assigned but no other statement uses it.
+
+Example:
+
+    rate = 0.05
+    1000 * 1.05
+
+Use the name, or start it with `_` if it is kept on purpose:
+
+    rate = 0.05
+    1000 * (1 + rate)",
+    ),
+];
+
+/// The explanation of a diagnostic code like `E0004`.
+pub fn explain(code: &str) -> Option<&'static str> {
+    EXPLANATIONS
+        .iter()
+        .find(|(known, _)| known.eq_ignore_ascii_case(code))
+        .map(|(_, explanation)| *explanation)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{error::CompileError, ir::Type, parser::ParseToken};
+
+    #[test]
+    fn explains_every_code() {
+        let errors = [
+            CompileError::InvalidCharacter('$'),
+            CompileError::InvalidAssignment,
+            CompileError::InvalidToken(ParseToken::Comma),
+            CompileError::OperandError,
+            CompileError::NameError("x".to_string()),
+            CompileError::InvalidNumber("0x".to_string(), String::new()),
+            CompileError::InvalidContinuation,
+            CompileError::UnclosedParen,
+            CompileError::UnexpectedCloseParen,
+            CompileError::MissingOperand(ParseToken::Add),
+            CompileError::TrailingOperator(ParseToken::Add),
+            CompileError::EmptyArgument,
+            CompileError::MissingOperator(ParseToken::Number(1.0)),
+            CompileError::UnexpectedToken(ParseToken::Comma),
+            CompileError::EmptyExpression,
+            CompileError::InvalidFormat(String::new()),
+            CompileError::InvalidIr(String::new(), String::new()),
+            CompileError::ExpressionAssignment,
+            CompileError::DuplicateParameter("x".to_string()),
+            CompileError::MultipleAssignment,
+            CompileError::GlobalType("x".to_string(), Type::Long, Type::Double),
+            CompileError::ArgumentCount("f".to_string(), 1, 2),
+            CompileError::UnknownType("double".to_string()),
+            CompileError::MissingType,
+            CompileError::TypeMismatch(Type::Long, Type::Double),
+            CompileError::UnclosedBracket,
+            CompileError::UnexpectedCloseBracket,
+            CompileError::DimensionMismatch("@".to_string(), String::new(), String::new()),
+            CompileError::NotSquare("det".to_string(), 2, 3),
+            CompileError::RaggedMatrix(2, 1),
+            CompileError::NotAFunction("x".to_string(), Type::Long),
+            CompileError::LetWithoutIn,
+            CompileError::LetWithoutValue,
+            CompileError::UnclosedBrace,
+            CompileError::UnexpectedCloseBrace,
+            CompileError::MissingBlockValue,
+            CompileError::BlockExpression,
+            CompileError::UnterminatedString,
+            CompileError::InvalidImport,
+            CompileError::ImportNotFound("x.nms".to_string()),
+            CompileError::ImportCycle("x.nms -> x.nms".to_string()),
+            CompileError::MissingImport("x".to_string(), "x.nms".to_string()),
+            CompileError::DuplicateNamespace("x".to_string()),
+            CompileError::FunctionRedefined("f".to_string()),
+            CompileError::FunctionAndVariable("f".to_string()),
+            CompileError::UnusedDeclaration("x".to_string()),
+        ];
+        for error in errors {
+            assert!(
+                explain(error.code()).is_some(),
+                "{} is not explained",
+                error.code()
+            );
+        }
+        assert_eq!(explain("e0005"), explain("E0005"));
+        assert_eq!(explain("E9999"), None);
+    }
+}
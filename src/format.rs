@@ -0,0 +1,211 @@
+use std::{fmt, str::FromStr};
+
+use crate::error::CompileError;
+
+/// How the result of an expression statement is printed. Every variant mirrors a printf
+/// conversion so the interpreter and the compiled runtime print identical text.
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum PrintFormat {
+    /// `%.Nf`
+    Fixed(usize),
+    /// `%.Ne`
+    Sci(usize),
+    /// `%g`
+    General,
+    /// `%a`
+    HexFloat,
+    /// the shortest `%.Ng` that parses back to the same value
+    Exact,
+}
+
+impl Default for PrintFormat {
+    fn default() -> Self {
+        PrintFormat::Fixed(4)
+    }
+}
+
+impl PrintFormat {
+    /// Parses the words following a `#fmt` directive, like `sci 6` or `general`.
+    pub fn from_directive(words: &[&str]) -> Result<Self, CompileError> {
+        let spec = match words {
+            [kind] => kind.to_string(),
+            [kind, precision] => format!("{kind}:{precision}"),
+            _ => return Err(CompileError::InvalidFormat(words.join(" "))),
+        };
+        return spec.parse();
+    }
+
+    /// The numeric tag and precision passed to `nm_print` in the runtime.
+    pub fn runtime_args(&self) -> (i32, i32) {
+        match self {
+            PrintFormat::Fixed(p) => (0, *p as i32),
+            PrintFormat::Sci(p) => (1, *p as i32),
+            PrintFormat::General => (2, 6),
+            PrintFormat::HexFloat => (3, 0),
+            PrintFormat::Exact => (4, 0),
+        }
+    }
+
+    pub fn format(&self, x: f64) -> String {
+        if !x.is_finite() {
+            return non_finite(x);
+        }
+        match self {
+            PrintFormat::Fixed(p) => format!("{x:.p$}"),
+            PrintFormat::Sci(p) => sci(x, *p),
+            PrintFormat::General => general(x, 6),
+            PrintFormat::HexFloat => hex_float(x),
+            PrintFormat::Exact => (1..=17)
+                .map(|p| general(x, p))
+                .find(|s| s.parse::<f64>() == Ok(x))
+                .unwrap_or_else(|| general(x, 17)),
+        }
+    }
+}
+
+impl FromStr for PrintFormat {
+    type Err = CompileError;
+
+    /// Parses the `--print-format` syntax: `fixed:N`, `sci:N`, `general`, `hex-float` or `exact`.
+    fn from_str(spec: &str) -> Result<Self, CompileError> {
+        let invalid = || CompileError::InvalidFormat(spec.to_string());
+        let (kind, precision) = match spec.split_once(':') {
+            Some((kind, precision)) => (
+                kind,
+                Some(precision.parse::<usize>().map_err(|_| invalid())?),
+            ),
+            None => (spec, None),
+        };
+        let format = match (kind, precision) {
+            ("fixed", Some(p)) => PrintFormat::Fixed(p),
+            ("sci", Some(p)) => PrintFormat::Sci(p),
+            ("general", None) => PrintFormat::General,
+            ("hex-float", None) => PrintFormat::HexFloat,
+            ("exact", None) => PrintFormat::Exact,
+            _ => return Err(invalid()),
+        };
+        return Ok(format);
+    }
+}
+
+impl fmt::Display for PrintFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PrintFormat::Fixed(p) => write!(f, "fixed:{p}"),
+            PrintFormat::Sci(p) => write!(f, "sci:{p}"),
+            PrintFormat::General => write!(f, "general"),
+            PrintFormat::HexFloat => write!(f, "hex-float"),
+            PrintFormat::Exact => write!(f, "exact"),
+        }
+    }
+}
+
+fn non_finite(x: f64) -> String {
+    let sign = if x.is_sign_negative() { "-" } else { "" };
+    let name = if x.is_nan() { "nan" } else { "inf" };
+    format!("{sign}{name}")
+}
+
+/// `%.Ne`, rust leaves off the sign and padding c puts on the exponent.
+fn sci(x: f64, precision: usize) -> String {
+    let formatted = format!("{x:.precision$e}");
+    let (mantissa, exponent) = formatted
+        .split_once('e')
+        .expect("exponent formatting always contains an 'e'");
+    let exponent: i32 = exponent.parse().expect("exponent is always an integer");
+    let sign = if exponent < 0 { '-' } else { '+' };
+    format!("{mantissa}e{sign}{:02}", exponent.abs())
+}
+
+/// `%.Pg`, fixed or scientific depending on the exponent with trailing zeros removed.
+fn general(x: f64, precision: usize) -> String {
+    let precision = precision.max(1);
+    let exponent = if x == 0.0 {
+        0
+    } else {
+        let formatted = format!("{x:.prec$e}", prec = precision - 1);
+        let (_, exponent) = formatted.split_once('e').unwrap();
+        exponent.parse::<i32>().unwrap()
+    };
+    if exponent < -4 || exponent >= precision as i32 {
+        let formatted = sci(x, precision - 1);
+        let (mantissa, exponent) = formatted.split_once('e').unwrap();
+        format!("{}e{exponent}", strip_zeros(mantissa))
+    } else {
+        let decimals = (precision as i32 - 1 - exponent) as usize;
+        strip_zeros(&format!("{x:.decimals$}")).to_string()
+    }
+}
+
+fn strip_zeros(number: &str) -> &str {
+    if number.contains('.') {
+        number.trim_end_matches('0').trim_end_matches('.')
+    } else {
+        number
+    }
+}
+
+/// `%a` the way glibc prints it: normalized, trailing zero nibbles removed.
+fn hex_float(x: f64) -> String {
+    let sign = if x.is_sign_negative() { "-" } else { "" };
+    let bits = x.to_bits();
+    let biased = ((bits >> 52) & 0x7ff) as i32;
+    let fraction = bits & ((1 << 52) - 1);
+    let (lead, exponent) = match (biased, fraction) {
+        (0, 0) => (0, 0),
+        (0, _) => (0, -1022),
+        _ => (1, biased - 1023),
+    };
+    let digits = format!("{fraction:013x}");
+    let digits = digits.trim_end_matches('0');
+    let point = if digits.is_empty() { "" } else { "." };
+    let exponent_sign = if exponent < 0 { '-' } else { '+' };
+    format!(
+        "{sign}0x{lead}{point}{digits}p{exponent_sign}{}",
+        exponent.abs()
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_cli_syntax() {
+        assert_eq!(
+            "fixed:2".parse::<PrintFormat>().unwrap(),
+            PrintFormat::Fixed(2)
+        );
+        assert_eq!("sci:6".parse::<PrintFormat>().unwrap(), PrintFormat::Sci(6));
+        assert_eq!(
+            "hex-float".parse::<PrintFormat>().unwrap(),
+            PrintFormat::HexFloat
+        );
+        assert!("fixed".parse::<PrintFormat>().is_err());
+        assert!("general:3".parse::<PrintFormat>().is_err());
+    }
+
+    #[test]
+    fn parses_directive_words() {
+        let format = PrintFormat::from_directive(&["sci", "6"]).unwrap();
+        assert_eq!(format, PrintFormat::Sci(6));
+        assert!(PrintFormat::from_directive(&[]).is_err());
+    }
+
+    #[test]
+    fn formats_like_printf() {
+        assert_eq!(PrintFormat::Fixed(4).format(1e-9), "0.0000");
+        assert_eq!(PrintFormat::Sci(6).format(1e-9), "1.000000e-09");
+        assert_eq!(PrintFormat::Sci(3).format(1e300), "1.000e+300");
+        assert_eq!(PrintFormat::General.format(1.0 / 3.0), "0.333333");
+        assert_eq!(PrintFormat::General.format(5e-5), "5e-05");
+        assert_eq!(PrintFormat::General.format(1234567.0), "1.23457e+06");
+        assert_eq!(PrintFormat::General.format(100.0), "100");
+        assert_eq!(PrintFormat::HexFloat.format(1.5), "0x1.8p+0");
+        assert_eq!(PrintFormat::HexFloat.format(0.125), "0x1p-3");
+        assert_eq!(PrintFormat::HexFloat.format(-0.0), "-0x0p+0");
+        assert_eq!(PrintFormat::Exact.format(0.1), "0.1");
+        assert_eq!(PrintFormat::Exact.format(1.0 / 3.0), "0.3333333333333333");
+        assert_eq!(PrintFormat::Fixed(2).format(f64::NEG_INFINITY), "-inf");
+    }
+}
@@ -0,0 +1,385 @@
+//! Resolves `import` statements before compiling. The declarations of an imported file are put
+//! in place of its first import, named under the file's namespace like `geo.dist` for `dist` in
+//! `geo.nms`, and `from "geo.nms" import dist` lets the importing file use the short name. The
+//! standard library is a set of such files bundled with the compiler.
+
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    error::{CompileError, Diagnostic},
+    parser::{self, Import, ParseToken, Parsed, Statement},
+};
+
+/// Replaces the imports of the program read from `path` with the declarations of the files they
+/// name. A file is looked for next to the file importing it, then in each of the `include`
+/// directories in order. Every file is only read once however many files import it.
+pub fn resolve(parsed: Parsed, path: &Path, include: &[PathBuf]) -> Parsed {
+    let mut resolver = Resolver {
+        include,
+        output: Parsed::default(),
+        loaded: HashMap::new(),
+        stack: vec![canonical(path)],
+    };
+    resolver.file(parsed, path, None);
+    return resolver.output;
+}
+
+/// The modules of the standard library, imported like `import std.stats`.
+const STD: &[(&str, &str)] = &[
+    ("finance", include_str!("../std/finance.nms")),
+    ("geometry", include_str!("../std/geometry.nms")),
+    ("stats", include_str!("../std/stats.nms")),
+    ("units", include_str!("../std/units.nms")),
+];
+
+struct Resolver<'a> {
+    include: &'a [PathBuf],
+    output: Parsed,
+    /// the namespace and declared names of every file imported so far
+    loaded: HashMap<PathBuf, (String, Vec<String>)>,
+    /// the files being resolved, each imported by the one before it
+    stack: Vec<PathBuf>,
+}
+
+impl Resolver<'_> {
+    /// Adds the statements of one file to the output and returns the names it declares. Only the
+    /// declarations of an imported file are kept, `namespace` is `None` for the compiled file.
+    fn file(&mut self, parsed: Parsed, path: &Path, namespace: Option<&str>) -> Vec<String> {
+        let file = namespace.map(|_| path.display().to_string());
+        let in_file = |diagnostic: Diagnostic| Diagnostic {
+            file: file.clone(),
+            ..diagnostic
+        };
+        // what each name the file uses stands for in the merged program
+        let mut names: HashMap<String, String> = HashMap::new();
+        let mut declared = vec![];
+        let diagnostics = parsed.diagnostics.into_iter().map(in_file);
+        self.output.diagnostics.extend(diagnostics);
+        let failed = parsed.failed_declarations.iter();
+        let failed = failed.map(|name| qualify(namespace, name));
+        self.output.failed_declarations.extend(failed);
+        if namespace.is_none() {
+            self.output.failed_uses.extend(parsed.failed_uses);
+        }
+        for (statement, span) in parsed.statements.into_iter().zip(parsed.spans) {
+            let statement = match statement {
+                Statement::Import(import) => {
+                    if let Err(error) = self.import(&import, path, &mut names) {
+                        let diagnostic = in_file(Diagnostic::new(error, span));
+                        self.output.diagnostics.push(diagnostic);
+                    }
+                    continue;
+                }
+                Statement::Declaration(mut declaration) => {
+                    rename(&mut declaration.body, &names, &declaration.args);
+                    let qualified = qualify(namespace, &declaration.name);
+                    names.insert(declaration.name.clone(), qualified.clone());
+                    declared.push(std::mem::replace(&mut declaration.name, qualified));
+                    Statement::Declaration(declaration)
+                }
+                _ if namespace.is_some() => continue,
+                Statement::Expression(mut expr) => {
+                    rename(&mut expr, &names, &[]);
+                    Statement::Expression(expr)
+                }
+                format @ Statement::Format(_) => format,
+            };
+            self.output.statements.push(statement);
+            self.output.spans.push(span);
+            self.output.files.push(file.clone());
+        }
+        return declared;
+    }
+
+    /// Resolves one import of the file at `from`, adding the names it brings in to `names`.
+    fn import(
+        &mut self,
+        import: &Import,
+        from: &Path,
+        names: &mut HashMap<String, String>,
+    ) -> Result<(), CompileError> {
+        let not_found = || CompileError::ImportNotFound(import.path.clone());
+        // a module of the standard library is named like the file it was written in
+        let (path, key, bundled) = if import.std {
+            let module = import.path.trim_start_matches("std.");
+            let (_, source) = STD
+                .iter()
+                .find(|(name, _)| *name == module)
+                .ok_or_else(not_found)?;
+            let path = PathBuf::from(format!("std/{module}.nms"));
+            (path.clone(), path, Some(*source))
+        } else {
+            let path = self.find(&import.path, from).ok_or_else(not_found)?;
+            let key = canonical(&path);
+            (path, key, None)
+        };
+        if let Some(start) = self.stack.iter().position(|file| *file == key) {
+            let cycle: Vec<String> = self.stack[start..]
+                .iter()
+                .chain([&key])
+                .map(|file| {
+                    file.file_name()
+                        .unwrap_or_default()
+                        .to_string_lossy()
+                        .into()
+                })
+                .collect();
+            return Err(CompileError::ImportCycle(cycle.join(" -> ")));
+        }
+        if !self.loaded.contains_key(&key) {
+            let source = match bundled {
+                Some(source) => source.to_string(),
+                None => fs::read_to_string(&path).map_err(|_| not_found())?,
+            };
+            let namespace = path.file_stem().unwrap_or_default().to_string_lossy();
+            let namespace = namespace.to_string();
+            if self.loaded.values().any(|(taken, _)| *taken == namespace) {
+                return Err(CompileError::DuplicateNamespace(namespace));
+            }
+            self.stack.push(key.clone());
+            let declared = self.file(parser::parse(&source), &path, Some(&namespace));
+            self.stack.pop();
+            self.loaded.insert(key.clone(), (namespace, declared));
+        }
+        let (namespace, declared) = &self.loaded[&key];
+        for name in import.names.iter().flatten() {
+            if !declared.contains(name) {
+                return Err(CompileError::MissingImport(
+                    name.clone(),
+                    import.path.clone(),
+                ));
+            }
+            names.insert(name.clone(), format!("{namespace}.{name}"));
+        }
+        return Ok(());
+    }
+
+    /// The first existing file named `file` next to `from` or in an include directory.
+    fn find(&self, file: &str, from: &Path) -> Option<PathBuf> {
+        let beside = from.parent().map(|dir| dir.join(file));
+        let included = self.include.iter().map(|dir| dir.join(file));
+        return beside
+            .into_iter()
+            .chain(included)
+            .find(|path| path.is_file());
+    }
+}
+
+/// The same path for every way of naming a file, so it is only imported once.
+fn canonical(path: &Path) -> PathBuf {
+    return path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
+}
+
+fn qualify(namespace: Option<&str>, name: &str) -> String {
+    return match namespace {
+        Some(namespace) => format!("{namespace}.{name}"),
+        None => name.to_string(),
+    };
+}
+
+/// Renames the names used by a statement to what they stand for in the merged program, leaving
+/// alone the ones hidden by a parameter, a lambda's parameter, a `let` or a loop variable.
+fn rename(tokens: &mut [ParseToken], names: &HashMap<String, String>, params: &[String]) {
+    let mut hidden: Vec<String> = params.to_vec();
+    // how many names were hidden before each unfinished lambda, `let` or loop
+    let mut scopes: Vec<usize> = vec![];
+    for token in tokens {
+        match token {
+            ParseToken::Identifier(name) | ParseToken::Call(name, _) if !hidden.contains(name) => {
+                if let Some(renamed) = names.get(name) {
+                    *name = renamed.clone();
+                }
+            }
+            ParseToken::Lambda(params) => {
+                scopes.push(hidden.len());
+                hidden.extend(params.iter().cloned());
+            }
+            ParseToken::Bind(name) | ParseToken::Loop(name) => {
+                scopes.push(hidden.len());
+                hidden.push(name.clone());
+            }
+            ParseToken::EndLambda | ParseToken::EndBind | ParseToken::EndLoop(_) => {
+                hidden.truncate(scopes.pop().unwrap_or_default());
+            }
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{compiler, format::PrintFormat, interp, parser::Declaration};
+    use std::{env, io};
+
+    /// Writes each file into a new directory and resolves the first one.
+    fn resolve_files(test: &str, files: &[(&str, &str)]) -> Parsed {
+        let dir = env::temp_dir().join(format!("numerus-{test}-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        for (name, source) in files {
+            fs::write(dir.join(name), source).unwrap();
+        }
+        let main = dir.join(files[0].0);
+        let parsed = resolve(parser::parse(files[0].1), &main, &[]);
+        fs::remove_dir_all(&dir).unwrap();
+        return parsed;
+    }
+
+    /// Compiles a program that imports from the standard library and runs it.
+    fn run_std(source: &str) -> String {
+        let parsed = resolve(parser::parse(source), Path::new("main.nms"), &[]);
+        let module = compiler::compile(parsed, PrintFormat::default()).unwrap();
+        let mut output = vec![];
+        interp::run(&module, &[], &mut io::empty(), &mut output).unwrap();
+        return String::from_utf8(output).unwrap();
+    }
+
+    fn errors(parsed: Parsed) -> Vec<CompileError> {
+        return parsed
+            .diagnostics
+            .into_iter()
+            .map(|diagnostic| diagnostic.error.downcast().unwrap())
+            .collect();
+    }
+
+    #[test]
+    fn merges_declarations_under_their_namespace() {
+        let parsed = resolve_files(
+            "merges",
+            &[
+                (
+                    "main.nms",
+                    "import \"geo.nms\"\nimport \"geo.nms\"\nfrom \"geo.nms\" import sq\ngeo.area(1) + sq(2)",
+                ),
+                ("geo.nms", "pi = 3.14\nsq(x) = x * x\narea(r) = pi * sq(r)\narea(2)"),
+            ],
+        );
+        assert!(parsed.diagnostics.is_empty());
+        assert_eq!(parsed.files.len(), 4);
+        assert!(parsed.files[..3]
+            .iter()
+            .all(|file| file.as_deref().is_some_and(|f| f.ends_with("geo.nms"))));
+        assert_eq!(parsed.files[3], None);
+        assert_eq!(
+            parsed.statements[2],
+            Statement::Declaration(Declaration {
+                name: "geo.area".to_string(),
+                function: true,
+                args: vec!["r".to_string()],
+                body: vec![
+                    ParseToken::Identifier("geo.pi".to_string()),
+                    ParseToken::Identifier("r".to_string()),
+                    ParseToken::Call("geo.sq".to_string(), 1),
+                    ParseToken::Multiply,
+                ],
+                ..Declaration::default()
+            })
+        );
+        assert_eq!(
+            parsed.statements[3],
+            Statement::Expression(vec![
+                ParseToken::Integer(1),
+                ParseToken::Call("geo.area".to_string(), 1),
+                ParseToken::Integer(2),
+                ParseToken::Call("geo.sq".to_string(), 1),
+                ParseToken::Add,
+            ])
+        );
+    }
+
+    #[test]
+    fn keeps_hidden_names() {
+        let mut statements = parser::parse("f(y) = sum(x, 1, x, x) + let x = x in x").statements;
+        let Statement::Declaration(declaration) = statements.remove(0) else {
+            panic!("not a declaration");
+        };
+        let names = HashMap::from([("x".to_string(), "lib.x".to_string())]);
+        let renamed = |params: &[String]| {
+            let mut body = declaration.body.clone();
+            rename(&mut body, &names, params);
+            let lib_x = ParseToken::Identifier("lib.x".to_string());
+            return body.iter().filter(|token| **token == lib_x).count();
+        };
+        // the bound of the loop and the value of the `let`
+        assert_eq!(renamed(&[]), 2);
+        assert_eq!(renamed(&["x".to_string()]), 0);
+    }
+
+    #[test]
+    fn reports_bad_imports() {
+        let parsed = resolve_files(
+            "bad",
+            &[
+                (
+                    "main.nms",
+                    "import \"missing.nms\"\nfrom \"a.nms\" import nothing\nimport \"a.nms\"",
+                ),
+                ("a.nms", "import \"b.nms\"\ny = 1"),
+                ("b.nms", "import \"a.nms\"\nx = 1 +"),
+            ],
+        );
+        assert_eq!(parsed.diagnostics[0].file, None);
+        assert!(parsed.diagnostics[1]
+            .file
+            .as_deref()
+            .is_some_and(|file| file.ends_with("b.nms")));
+        assert!(matches!(
+            errors(parsed).as_slice(),
+            [
+                CompileError::ImportNotFound(missing),
+                CompileError::TrailingOperator(_),
+                CompileError::ImportCycle(cycle),
+                CompileError::MissingImport(name, _),
+            ] if missing == "missing.nms" && cycle == "a.nms -> b.nms -> a.nms" && name == "nothing"
+        ));
+    }
+
+    #[test]
+    fn runs_std_stats() {
+        let output = run_std(
+            "import std.stats\nv = [2, 4, 4, 4, 5, 5, 7, 9]\nstats.mean(v)\nstats.variance(v)\nstats.stddev(v)",
+        );
+        assert_eq!(output, "5.0000\n4.0000\n2.0000\n");
+    }
+
+    #[test]
+    fn runs_std_finance() {
+        let output = run_std(
+            "from std.finance import npv, pmt\nimport std.finance\n\
+             npv(0.1, [-100, 60, 60])\npmt(0.05, 10, 1000)\nfinance.fv(0.05, 10, 0, 100)",
+        );
+        assert_eq!(output, "4.1322\n129.5046\n1257.7893\n");
+    }
+
+    #[test]
+    fn runs_std_geometry() {
+        let output = run_std(
+            "import std.geometry\ngeometry.dist([0, 0], [3, 4])\ngeometry.circle_area(1)\n\
+             geometry.triangle_area(3, 4, 5)\ngeometry.pi",
+        );
+        assert_eq!(output, "5.0000\n3.1416\n6.0000\n3.1416\n");
+    }
+
+    #[test]
+    fn runs_std_units() {
+        let output = run_std(
+            "from std.units import km_to_miles, celsius_to_fahrenheit, fahrenheit_to_celsius\n\
+             km_to_miles(1.609344)\ncelsius_to_fahrenheit(100)\nfahrenheit_to_celsius(-40)\n\
+             import std.units\nunits.inches_to_cm(units.cm_to_inches(10))",
+        );
+        assert_eq!(output, "1.0000\n212.0000\n-40.0000\n10.0000\n");
+        let parsed = resolve(
+            parser::parse("import std.nothing"),
+            Path::new("main.nms"),
+            &[],
+        );
+        assert!(matches!(
+            errors(parsed).as_slice(),
+            [CompileError::ImportNotFound(module)] if module == "std.nothing"
+        ));
+    }
+}
@@ -0,0 +1,723 @@
+use anyhow::{Context, Result};
+
+use crate::{
+    error::CompileError,
+    ir::{Function, Global, Type},
+    parser::{Declaration, ParseToken},
+};
+
+/// Infers the types of a function's parameters and result from its body. Annotated
+/// parameters keep their type, the others start out unknown and become ints only when the
+/// body passes them somewhere an int is needed, every parameter left unknown is a float.
+/// A parameter the body calls is a function taking what it is passed. The result is whatever
+/// the body computes unless it is annotated.
+pub fn signature(
+    declaration: &Declaration,
+    functions: &[Function],
+    globals: &[&Global],
+) -> Result<(Vec<Type>, Type)> {
+    let mut inference = Inference::default();
+    let params: Vec<Ty> = declaration
+        .args
+        .iter()
+        .map(|arg| match declaration.annotations.get(arg) {
+            Some(ty) => lift(ty),
+            None => inference.fresh(),
+        })
+        .collect();
+    // the parameters come last so they hide globals with the same name
+    for global in globals {
+        inference
+            .scope
+            .push((global.name.clone(), lift(&global.ty)));
+    }
+    for (arg, ty) in declaration.args.iter().zip(&params) {
+        inference.scope.push((arg.clone(), ty.clone()));
+    }
+
+    let body = inference.expression(&declaration.body, functions)?;
+    if let Some(annotation) = &declaration.return_type {
+        inference
+            .expect(body.clone(), annotation)
+            .with_context(|| format!("the result of `{}`", declaration.name))?;
+    }
+    inference.settle()?;
+    let return_type = match &declaration.return_type {
+        Some(annotation) => annotation.clone(),
+        None => inference.finish(body),
+    };
+    let params = params.into_iter().map(|ty| inference.finish(ty)).collect();
+    return Ok((params, return_type));
+}
+
+/// Infers the types of the lambdas in an expression that can read `variables`, in the order
+/// the lambdas start. A lambda's parameters are typed by what it is passed to, unknown ones
+/// are floats.
+pub fn lambdas(
+    expr: &[ParseToken],
+    functions: &[Function],
+    variables: &[(String, Type)],
+) -> Result<Vec<Type>> {
+    let mut inference = Inference::default();
+    for (name, ty) in variables {
+        inference.scope.push((name.clone(), lift(ty)));
+    }
+    inference.expression(expr, functions)?;
+    inference.settle()?;
+    let lambdas = std::mem::take(&mut inference.lambdas);
+    return Ok(lambdas
+        .into_iter()
+        .map(|lambda| match lambda {
+            Some((ty, _)) => inference.finish(ty),
+            None => panic!("every lambda is ended"),
+        })
+        .collect());
+}
+
+/// The type a function parameter or variable starts out with.
+fn lift(ty: &Type) -> Ty {
+    match ty {
+        Type::Function(params, result) => {
+            Ty::Function(params.iter().map(lift).collect(), Box::new(lift(result)))
+        }
+        ty => Ty::Known(ty.clone()),
+    }
+}
+
+/// The builtins `Inference::call` knows the types of, by their parameters and result.
+fn builtin(name: &str) -> Option<(Vec<Type>, Type)> {
+    let mapping = Type::Function(vec![Type::Double], Box::new(Type::Double));
+    let types = match name {
+        "int" => (vec![Type::Double], Type::Long),
+        "float" | "sqrt" | "exp" | "ln" | "sin" | "cos" => (vec![Type::Double], Type::Double),
+        "len" => (vec![Type::Vector], Type::Long),
+        "sum" | "mean" | "min" | "max" => (vec![Type::Vector], Type::Double),
+        "T" | "inv" => (vec![Type::Matrix], Type::Matrix),
+        "det" => (vec![Type::Matrix], Type::Double),
+        "solve" => (vec![Type::Matrix, Type::Vector], Type::Vector),
+        "map" => (vec![Type::Vector, mapping], Type::Vector),
+        "arg" => (vec![Type::Long], Type::Double),
+        "input" => (vec![], Type::Double),
+        _ => return None,
+    };
+    return Some(types);
+}
+
+/// A type while inferring, a variable stands for a type that isn't known yet. A function
+/// type is never `Known`, so its parameters and result can be variables.
+#[derive(PartialEq, Debug, Clone)]
+enum Ty {
+    Known(Type),
+    Var(usize),
+    Function(Vec<Ty>, Box<Ty>),
+}
+
+/// The type variables of one function, each is either unbound or bound to another type.
+#[derive(Default)]
+struct Inference {
+    bindings: Vec<Option<Ty>>,
+    /// the names an expression can read, later ones hide earlier ones with the same name
+    scope: Vec<(String, Ty)>,
+    /// the type of every lambda in the order they start, with the type of its body, a lambda
+    /// can give back a float where its body computes an int
+    lambdas: Vec<Option<(Ty, Ty)>>,
+    /// the variable standing for the result of each operator whose operands were both
+    /// unknown, with the operands, so neither forces its type on the other
+    joins: Vec<(usize, Ty, Ty)>,
+}
+
+impl Inference {
+    /// The type of an expression in reverse polish notation.
+    fn expression(&mut self, tokens: &[ParseToken], functions: &[Function]) -> Result<Ty> {
+        let mut stack: Vec<Ty> = vec![];
+        // the lambdas being inferred, by their index in `lambdas`, where their parameters
+        // start in the scope and the parameter types
+        let mut open: Vec<(usize, usize, Vec<Ty>)> = vec![];
+        for token in tokens {
+            let ty = match token {
+                ParseToken::Number(_) => Ty::Known(Type::Double),
+                ParseToken::Integer(_) => Ty::Known(Type::Long),
+                ParseToken::Boolean(_) => Ty::Known(Type::Bool),
+                ParseToken::Call(name, count) => {
+                    if stack.len() < *count {
+                        return Err(CompileError::OperandError.into());
+                    }
+                    let args = stack.split_off(stack.len() - count);
+                    let is_function =
+                        functions.iter().any(|f| &f.name == name) || builtin(name).is_some();
+                    match self.lookup(name).map(|ty| self.resolve(ty)) {
+                        Some(callee @ (Ty::Var(_) | Ty::Function(..))) => {
+                            self.call_closure(name, callee, args)?
+                        }
+                        Some(found) if !is_function => {
+                            let found = self.finish(found);
+                            return Err(CompileError::NotAFunction(name.clone(), found).into());
+                        }
+                        _ => self.call(name, args, functions)?,
+                    }
+                }
+                // a function without parameters can be called without parenthesis
+                ParseToken::Identifier(name)
+                    if functions
+                        .iter()
+                        .any(|f| &f.name == name && f.params.is_empty()) =>
+                {
+                    self.call(name, vec![], functions)?
+                }
+                ParseToken::Identifier(name) => match self.lookup(name) {
+                    Some(ty) => ty,
+                    // a function named without calling it is a value
+                    None => match functions.iter().find(|f| &f.name == name) {
+                        Some(func) => {
+                            let params = func.params.iter().map(|p| p.ty.clone()).collect();
+                            lift(&Type::Function(params, Box::new(func.return_type.clone())))
+                        }
+                        None => return Err(CompileError::NameError(name.clone()).into()),
+                    },
+                },
+                ParseToken::Negate => stack.pop().ok_or(CompileError::OperandError)?,
+                ParseToken::Vector(count) => {
+                    if stack.len() < *count {
+                        return Err(CompileError::OperandError.into());
+                    }
+                    let elements = stack.split_off(stack.len() - count);
+                    // a vector of vectors is a matrix with them as its rows
+                    let (element, ty) = match elements.first().map(|e| self.resolve(e.clone())) {
+                        Some(Ty::Known(Type::Vector)) => (Type::Vector, Type::Matrix),
+                        _ => (Type::Double, Type::Vector),
+                    };
+                    for element_ty in elements {
+                        self.expect(element_ty, &element)?;
+                    }
+                    Ty::Known(ty)
+                }
+                ParseToken::Index => {
+                    let (Some(index), Some(vector)) = (stack.pop(), stack.pop()) else {
+                        return Err(CompileError::OperandError.into());
+                    };
+                    self.expect(index, &Type::Long)?;
+                    // indexing a matrix gives one of its rows
+                    if self.resolve(vector.clone()) == Ty::Known(Type::Matrix) {
+                        Ty::Known(Type::Vector)
+                    } else {
+                        self.expect(vector, &Type::Vector)?;
+                        Ty::Known(Type::Double)
+                    }
+                }
+                ParseToken::Loop(variable) => {
+                    let (Some(end), Some(start)) = (stack.pop(), stack.pop()) else {
+                        return Err(CompileError::OperandError.into());
+                    };
+                    self.expect(start, &Type::Long)?;
+                    self.expect(end, &Type::Long)?;
+                    self.scope.push((variable.clone(), Ty::Known(Type::Long)));
+                    continue;
+                }
+                // the total has the type of the body
+                ParseToken::EndLoop(_) => {
+                    let body = stack.pop().ok_or(CompileError::OperandError)?;
+                    self.scope.pop();
+                    match self.resolve(body.clone()) {
+                        Ty::Known(found @ (Type::Vector | Type::Matrix)) => {
+                            return Err(CompileError::TypeMismatch(Type::Double, found).into());
+                        }
+                        _ => self.join(body, Ty::Known(Type::Long))?,
+                    }
+                }
+                ParseToken::Bind(name) => {
+                    let value = stack.pop().ok_or(CompileError::OperandError)?;
+                    self.scope.push((name.clone(), value));
+                    continue;
+                }
+                // the body of a `let` is its value
+                ParseToken::EndBind => {
+                    self.scope.pop();
+                    continue;
+                }
+                ParseToken::Lambda(params) => {
+                    let types: Vec<Ty> = params.iter().map(|_| self.fresh()).collect();
+                    open.push((self.lambdas.len(), self.scope.len(), types.clone()));
+                    self.lambdas.push(None);
+                    self.scope.extend(params.iter().cloned().zip(types));
+                    continue;
+                }
+                ParseToken::EndLambda => {
+                    let body = stack.pop().ok_or(CompileError::OperandError)?;
+                    let (index, scope, params) = open.pop().ok_or(CompileError::OperandError)?;
+                    self.scope.truncate(scope);
+                    let ty = Ty::Function(params, Box::new(self.fresh()));
+                    self.lambdas[index] = Some((ty.clone(), body));
+                    ty
+                }
+                ParseToken::MatrixMultiply => {
+                    let (Some(y), Some(x)) = (stack.pop(), stack.pop()) else {
+                        return Err(CompileError::OperandError.into());
+                    };
+                    self.matmul(x, y)?
+                }
+                // the left side of `and`/`or` is taken off when it is branched on
+                ParseToken::AndThen | ParseToken::OrElse => {
+                    let left = stack.pop().ok_or(CompileError::OperandError)?;
+                    self.expect(left, &Type::Bool)?;
+                    continue;
+                }
+                ParseToken::Not | ParseToken::And | ParseToken::Or => {
+                    let x = stack.pop().ok_or(CompileError::OperandError)?;
+                    self.expect(x, &Type::Bool)?;
+                    Ty::Known(Type::Bool)
+                }
+                _ if token.is_comparison() => {
+                    let (Some(y), Some(x)) = (stack.pop(), stack.pop()) else {
+                        return Err(CompileError::OperandError.into());
+                    };
+                    self.join(x, y)?;
+                    Ty::Known(Type::Bool)
+                }
+                _ if token.is_operator() => {
+                    let (Some(y), Some(x)) = (stack.pop(), stack.pop()) else {
+                        return Err(CompileError::OperandError.into());
+                    };
+                    self.join(x, y)?
+                }
+                _ => return Err(CompileError::InvalidToken(token.clone()).into()),
+            };
+            stack.push(ty);
+        }
+        match (stack.pop(), stack.is_empty()) {
+            (Some(ty), true) => Ok(ty),
+            _ => Err(CompileError::OperandError.into()),
+        }
+    }
+
+    fn lookup(&self, name: &str) -> Option<Ty> {
+        self.scope
+            .iter()
+            .rev()
+            .find(|(variable, _)| variable == name)
+            .map(|(_, ty)| ty.clone())
+    }
+
+    /// Gives every lambda whose result isn't known yet the type of its body, and checks the
+    /// others' bodies fit their result. Then gives every operator that had two unknown
+    /// operands the type of its operands now that more is known about them.
+    fn settle(&mut self) -> Result<()> {
+        for (ty, body) in self.lambdas.clone().into_iter().flatten() {
+            let Ty::Function(_, result) = ty else {
+                panic!("a lambda has a function type");
+            };
+            match self.resolve(*result) {
+                Ty::Var(var) => {
+                    let body = self.resolve(body);
+                    if body != Ty::Var(var) {
+                        self.bindings[var] = Some(body);
+                    }
+                }
+                result => self.pass(body, result)?,
+            }
+        }
+        for (result, x, y) in self.joins.clone() {
+            match self.resolve(Ty::Var(result)) {
+                Ty::Known(ty @ (Type::Long | Type::Bool | Type::Vector)) => {
+                    self.expect_operands(result, &ty)?;
+                }
+                result => {
+                    // operands that are both still unknown end up floats, as does the result
+                    if let (Ty::Var(_), Ty::Var(_)) =
+                        (self.resolve(x.clone()), self.resolve(y.clone()))
+                    {
+                        continue;
+                    }
+                    let joined = self.join(x, y)?;
+                    match result {
+                        Ty::Var(var) if joined != Ty::Var(var) => self.bindings[var] = Some(joined),
+                        Ty::Var(_) => {}
+                        result => self.pass(joined, result)?,
+                    }
+                }
+            }
+        }
+        return Ok(());
+    }
+
+    fn fresh(&mut self) -> Ty {
+        self.bindings.push(None);
+        return Ty::Var(self.bindings.len() - 1);
+    }
+
+    /// Follows bound variables until reaching a known type or an unbound variable.
+    fn resolve(&self, ty: Ty) -> Ty {
+        match ty {
+            Ty::Var(var) if let Some(bound) = &self.bindings[var] => self.resolve(bound.clone()),
+            ty => ty,
+        }
+    }
+
+    /// The type of an operator's result. A float operand makes it a float and an int operand
+    /// takes the type of the other side. A vector operand makes it a vector. Two unknown
+    /// operands give an unknown result that is settled once they are known, unless it is
+    /// needed as an int first.
+    fn join(&mut self, x: Ty, y: Ty) -> Result<Ty> {
+        let ty = match (self.resolve(x), self.resolve(y)) {
+            (function @ Ty::Function(..), _) | (_, function @ Ty::Function(..)) => {
+                let found = self.finish(function);
+                return Err(CompileError::TypeMismatch(Type::Double, found).into());
+            }
+            (Ty::Known(Type::Bool), Ty::Known(Type::Bool)) => Ty::Known(Type::Bool),
+            (Ty::Known(found @ (Type::Bool | Type::Matrix)), _)
+            | (_, Ty::Known(found @ (Type::Bool | Type::Matrix))) => {
+                return Err(CompileError::TypeMismatch(Type::Double, found).into());
+            }
+            (Ty::Known(Type::Vector), _) | (_, Ty::Known(Type::Vector)) => Ty::Known(Type::Vector),
+            (Ty::Known(Type::Double), _) | (_, Ty::Known(Type::Double)) => Ty::Known(Type::Double),
+            (Ty::Known(Type::Long), other) | (other, Ty::Known(Type::Long)) => other,
+            (Ty::Var(x), Ty::Var(y)) if x == y => Ty::Var(x),
+            (x @ Ty::Var(_), y @ Ty::Var(_)) => {
+                let result = self.fresh();
+                let Ty::Var(var) = result else {
+                    panic!("a fresh type is a variable");
+                };
+                self.joins.push((var, x, y));
+                result
+            }
+            (x, _) => x,
+        };
+        return Ok(ty);
+    }
+
+    /// Checks a value of type `ty` can be used where `expected` is needed, an int is promoted
+    /// to a float but a float can't become an int. An unknown type needed as an int or a
+    /// bool is one, and so are both operands when it is an operator's result. A function has
+    /// to take and give back exactly the expected types.
+    fn expect(&mut self, ty: Ty, expected: &Type) -> Result<()> {
+        if let Type::Function(..) = expected {
+            return self.same(ty, lift(expected));
+        }
+        match self.resolve(ty) {
+            Ty::Var(var) => {
+                if *expected != Type::Double {
+                    self.bindings[var] = Some(Ty::Known(expected.clone()));
+                }
+                self.expect_operands(var, expected)?;
+            }
+            function @ Ty::Function(..) => {
+                let found = self.finish(function);
+                return Err(CompileError::TypeMismatch(expected.clone(), found).into());
+            }
+            Ty::Known(Type::Long) if *expected == Type::Double => {}
+            Ty::Known(found) if found != *expected => {
+                return Err(CompileError::TypeMismatch(expected.clone(), found).into());
+            }
+            Ty::Known(_) => {}
+        }
+        return Ok(());
+    }
+
+    /// Makes the operands of an operator whose result is needed as `expected` fit it. An int or
+    /// a bool needs both operands to be one, an unknown operand of a vector is taken to be a
+    /// vector too.
+    fn expect_operands(&mut self, result: usize, expected: &Type) -> Result<()> {
+        if *expected == Type::Double {
+            return Ok(());
+        }
+        let operands: Vec<Ty> = self
+            .joins
+            .iter()
+            .filter(|(var, ..)| *var == result)
+            .flat_map(|(_, x, y)| [x.clone(), y.clone()])
+            .collect();
+        for operand in operands {
+            let unknown = matches!(self.resolve(operand.clone()), Ty::Var(_));
+            if *expected != Type::Vector || unknown {
+                self.expect(operand, expected)?;
+            }
+        }
+        return Ok(());
+    }
+
+    /// The type of `x @ y`, an unknown side is a matrix unless the other side is one, then it
+    /// is a vector.
+    fn matmul(&mut self, x: Ty, y: Ty) -> Result<Ty> {
+        let (x, y) = (self.resolve(x), self.resolve(y));
+        if matches!(x, Ty::Var(_)) {
+            self.expect(x.clone(), &Type::Matrix)?;
+        }
+        let left = self.finish(x);
+        if let Ty::Var(_) = y {
+            let right = match left {
+                Type::Matrix => Type::Vector,
+                _ => Type::Matrix,
+            };
+            self.expect(y.clone(), &right)?;
+        }
+        let ty = match (left, self.finish(y)) {
+            (Type::Matrix, Type::Matrix) => Type::Matrix,
+            (Type::Matrix, Type::Vector) | (Type::Vector, Type::Matrix) => Type::Vector,
+            (Type::Vector, Type::Vector) => Type::Double,
+            (Type::Matrix | Type::Vector, found) | (found, _) => {
+                return Err(CompileError::TypeMismatch(Type::Matrix, found).into());
+            }
+        };
+        return Ok(Ty::Known(ty));
+    }
+
+    /// Passes a value of type `arg` where `param` is needed. A number leaves an unknown
+    /// parameter free to be a float, since an int can be promoted.
+    fn pass(&mut self, arg: Ty, param: Ty) -> Result<()> {
+        match (self.resolve(arg), self.resolve(param)) {
+            (arg, Ty::Known(param)) => self.expect(arg, &param),
+            (Ty::Known(Type::Long | Type::Double), Ty::Var(_)) => Ok(()),
+            (arg, param) => self.same(arg, param),
+        }
+    }
+
+    /// Makes two types the same, binding the unknown parts of either.
+    fn same(&mut self, x: Ty, y: Ty) -> Result<()> {
+        match (self.resolve(x), self.resolve(y)) {
+            (Ty::Var(x), Ty::Var(y)) if x == y => {}
+            (Ty::Var(var), other) | (other, Ty::Var(var)) => self.bindings[var] = Some(other),
+            (Ty::Known(x), Ty::Known(y)) if x == y => {}
+            (Ty::Function(xs, x), Ty::Function(ys, y)) if xs.len() == ys.len() => {
+                for (x, y) in xs.into_iter().zip(ys) {
+                    self.same(x, y)?;
+                }
+                self.same(*x, *y)?;
+            }
+            (found, expected) => {
+                let error = CompileError::TypeMismatch(self.finish(expected), self.finish(found));
+                return Err(error.into());
+            }
+        }
+        return Ok(());
+    }
+
+    /// Calls a variable holding a function, an unknown one becomes a function of the
+    /// arguments.
+    fn call_closure(&mut self, name: &str, callee: Ty, args: Vec<Ty>) -> Result<Ty> {
+        let (params, result) = match self.resolve(callee) {
+            Ty::Var(var) => {
+                let params: Vec<Ty> = args.iter().map(|_| self.fresh()).collect();
+                let result = self.fresh();
+                let function = Ty::Function(params.clone(), Box::new(result.clone()));
+                self.bindings[var] = Some(function);
+                (params, result)
+            }
+            Ty::Function(params, result) => (params, *result),
+            Ty::Known(found) => {
+                return Err(CompileError::NotAFunction(name.to_string(), found).into());
+            }
+        };
+        if args.len() != params.len() {
+            let error = CompileError::ArgumentCount(name.to_string(), params.len(), args.len());
+            return Err(error.into());
+        }
+        for (index, (arg, param)) in args.into_iter().zip(params).enumerate() {
+            self.pass(arg, param)
+                .with_context(|| format!("argument {} of `{name}`", index + 1))?;
+        }
+        return Ok(result);
+    }
+
+    fn call(&mut self, name: &str, mut args: Vec<Ty>, functions: &[Function]) -> Result<Ty> {
+        let Some(func) = functions.iter().find(|f| f.name == name) else {
+            let Some((params, result)) = builtin(name) else {
+                return Err(CompileError::NameError(name.to_string()).into());
+            };
+            if args.len() != params.len() {
+                let error = CompileError::ArgumentCount(name.to_string(), params.len(), args.len());
+                return Err(error.into());
+            }
+            // the conversions take ints as well as floats
+            if matches!(name, "int" | "float") {
+                args.clear();
+            }
+            for (arg, param) in args.into_iter().zip(&params) {
+                self.expect(arg, param)?;
+            }
+            return Ok(Ty::Known(result));
+        };
+        if args.len() != func.params.len() {
+            let error =
+                CompileError::ArgumentCount(name.to_string(), func.params.len(), args.len());
+            return Err(error.into());
+        }
+        for (index, (arg, param)) in args.into_iter().zip(&func.params).enumerate() {
+            self.expect(arg, &param.ty)
+                .with_context(|| format!("argument {} of `{name}`", index + 1))?;
+        }
+        return Ok(Ty::Known(func.return_type.clone()));
+    }
+
+    /// The type a variable ended up with, unknown ones are floats.
+    fn finish(&self, ty: Ty) -> Type {
+        match self.resolve(ty) {
+            Ty::Known(ty) => ty,
+            Ty::Var(_) => Type::Double,
+            Ty::Function(params, result) => Type::Function(
+                params.into_iter().map(|param| self.finish(param)).collect(),
+                Box::new(self.finish(*result)),
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::{parse, Statement};
+
+    fn infer(source: &str, functions: &[Function]) -> Result<(Vec<Type>, Type)> {
+        let Statement::Declaration(declaration) = parse(source).statements.remove(0) else {
+            panic!("{source} is not a declaration");
+        };
+        signature(&declaration, functions, &[])
+    }
+
+    #[test]
+    fn defaults_parameters_to_floats() {
+        let (params, result) = infer("f(x, y) = x * 2 + y % 3", &[]).unwrap();
+        assert_eq!(params, vec![Type::Double, Type::Double]);
+        assert_eq!(result, Type::Double);
+        let (_, result) = infer("f(x) = int(x) / 2", &[]).unwrap();
+        assert_eq!(result, Type::Long);
+    }
+
+    #[test]
+    fn infers_ints_from_calls() {
+        let steps = Function::new(
+            "steps".to_string(),
+            vec![crate::ir::Temp::new("n_0".to_string(), Type::Long)],
+            Type::Long,
+        );
+        let functions = [steps];
+        let (params, result) = infer("f(a, b) = steps(a - b) * b", &functions).unwrap();
+        assert_eq!(params, vec![Type::Long, Type::Long]);
+        assert_eq!(result, Type::Long);
+        assert!(infer("f(x) = steps(x * 0.5)", &functions).is_err());
+    }
+
+    #[test]
+    fn infers_operands_in_either_order() {
+        let h = Function::new(
+            "h".to_string(),
+            vec![crate::ir::Temp::new("n_0".to_string(), Type::Long)],
+            Type::Long,
+        );
+        let functions = [h];
+        for source in ["f(a, b) = h(b) + a * b", "f(a, b) = a * b + h(b)"] {
+            let (params, result) = infer(source, &functions).unwrap();
+            assert_eq!(params, vec![Type::Double, Type::Long]);
+            assert_eq!(result, Type::Double);
+        }
+        let v = Global {
+            name: "v".to_string(),
+            ty: Type::Vector,
+        };
+        for source in ["f(a, i) = a * i + v[i]", "f(a, i) = v[i] + a * i"] {
+            let Statement::Declaration(declaration) = parse(source).statements.remove(0) else {
+                panic!("{source} is not a declaration");
+            };
+            let (params, _) = signature(&declaration, &[], &[&v]).unwrap();
+            assert_eq!(params, vec![Type::Double, Type::Long]);
+        }
+    }
+
+    #[test]
+    fn checks_annotations() {
+        let (params, result) = infer("f(n: int, x): float = n * 2 + x", &[]).unwrap();
+        assert_eq!(params, vec![Type::Long, Type::Double]);
+        assert_eq!(result, Type::Double);
+        let (params, result) = infer("f(n): int = n + 1", &[]).unwrap();
+        assert_eq!((params, result), (vec![Type::Long], Type::Long));
+        let error = infer("f(x: float): int = x", &[]).unwrap_err();
+        assert!(matches!(
+            error.downcast_ref(),
+            Some(CompileError::TypeMismatch(Type::Long, Type::Double))
+        ));
+    }
+
+    #[test]
+    fn infers_loops() {
+        let (params, result) = infer("f(n) = sum(k, 1, n, k * k)", &[]).unwrap();
+        assert_eq!((params, result), (vec![Type::Long], Type::Long));
+        let (params, result) = infer("f(x, n) = prod(k, 1, n, x / k)", &[]).unwrap();
+        assert_eq!(params, vec![Type::Double, Type::Long]);
+        assert_eq!(result, Type::Double);
+    }
+
+    #[test]
+    fn infers_let_bindings() {
+        let (params, result) = infer("f(v) = let n = len(v) in n * n", &[]).unwrap();
+        assert_eq!((params, result), (vec![Type::Vector], Type::Long));
+        let (_, result) = infer("f(x) = let x = x > 0 in x", &[]).unwrap();
+        assert_eq!(result, Type::Bool);
+    }
+
+    #[test]
+    fn infers_function_parameters() {
+        let (params, result) = infer("apply(f, x) = f(x) * 2", &[]).unwrap();
+        let function = Type::Function(vec![Type::Double], Box::new(Type::Double));
+        assert_eq!(params, vec![function.clone(), Type::Double]);
+        assert_eq!(result, Type::Double);
+        let (params, _) = infer("pick(f, x) = f(x > 0)", &[]).unwrap();
+        assert_eq!(
+            params[0],
+            Type::Function(vec![Type::Bool], Box::new(Type::Double))
+        );
+        let (_, result) = infer("adder(n) = x -> x + n", &[]).unwrap();
+        assert_eq!(result, function);
+        let error = infer("f(g) = g(1) + g(1, 2)", &[]).unwrap_err();
+        assert!(matches!(
+            error.downcast_ref(),
+            Some(CompileError::ArgumentCount(name, 1, 2)) if name == "g"
+        ));
+    }
+
+    #[test]
+    fn infers_lambdas_from_their_use() {
+        let Statement::Expression(expr) = parse("map(v, x -> 1) + map(v, x -> x * n)")
+            .statements
+            .remove(0)
+        else {
+            panic!("not an expression");
+        };
+        let variables = [
+            ("v".to_string(), Type::Vector),
+            ("n".to_string(), Type::Long),
+        ];
+        let types = lambdas(&expr, &[], &variables).unwrap();
+        let mapping = Type::Function(vec![Type::Double], Box::new(Type::Double));
+        assert_eq!(types, vec![mapping.clone(), mapping]);
+        let scaled = Type::Function(vec![Type::Double, Type::Long], Box::new(Type::Double));
+        let g = Function::new(
+            "g".to_string(),
+            vec![crate::ir::Temp::new("f_0".to_string(), scaled.clone())],
+            Type::Double,
+        );
+        let Statement::Expression(expr) = parse("g((a, b) -> a * b)").statements.remove(0) else {
+            panic!("not an expression");
+        };
+        assert_eq!(lambdas(&expr, &[g], &[]).unwrap(), vec![scaled]);
+        let Statement::Expression(expr) = parse("map(v, (a, b) -> a)").statements.remove(0) else {
+            panic!("not an expression");
+        };
+        assert!(matches!(
+            lambdas(&expr, &[], &variables).unwrap_err().downcast_ref(),
+            Some(CompileError::TypeMismatch(
+                Type::Function(..),
+                Type::Function(..)
+            ))
+        ));
+    }
+
+    #[test]
+    fn infers_bools() {
+        let (params, result) = infer("f(p, x) = not p and x > 1", &[]).unwrap();
+        assert_eq!(params, vec![Type::Bool, Type::Double]);
+        assert_eq!(result, Type::Bool);
+        let error = infer("f(p) = p + true", &[]).unwrap_err();
+        assert!(matches!(
+            error.downcast_ref(),
+            Some(CompileError::TypeMismatch(Type::Double, Type::Bool))
+        ));
+    }
+}
@@ -0,0 +1,980 @@
+use anyhow::{anyhow, bail, Result};
+use std::{
+    cmp::Ordering,
+    collections::{HashMap, VecDeque},
+    fmt,
+    io::{BufRead, Write},
+};
+
+use crate::{
+    ir::{
+        Comparison, Function, MathFunction, Module, Operation, Reduction, Terminator, Type, Value,
+    },
+    linalg,
+};
+
+/// How an error that stopped `run` is written to stderr, the same way the runtime of a compiled
+/// program writes one before exiting with 1.
+pub fn runtime_error(error: &anyhow::Error) -> String {
+    return format!("error: {error}");
+}
+
+/// Runs a module directly instead of building it, printing exactly what the compiled program
+/// would. `args` are the command line arguments after the program's name and `input` is read
+/// as its stdin.
+pub fn run(
+    module: &Module,
+    args: &[String],
+    input: &mut impl BufRead,
+    out: &mut impl Write,
+) -> Result<()> {
+    let main = module
+        .function("main")
+        .ok_or_else(|| anyhow!("module has no main function"))?;
+    let globals = module
+        .globals
+        .iter()
+        .map(|global| {
+            let zero = match global.ty {
+                Type::Long => Datum::Long(0),
+                Type::Bool => Datum::Bool(false),
+                // the empty vector made below
+                Type::Vector => Datum::Vector(0),
+                Type::Matrix => Datum::Matrix(0),
+                _ => Datum::Double(0.0),
+            };
+            (global.name.as_str(), zero)
+        })
+        .collect();
+    let mut interpreter = Interpreter {
+        module,
+        out,
+        globals,
+        vectors: vec![vec![]],
+        matrices: vec![Matrix {
+            rows: 0,
+            cols: 0,
+            data: vec![],
+        }],
+        closures: vec![],
+        args,
+        input,
+        words: VecDeque::new(),
+    };
+    // the command line is kept by the interpreter, `argv` can't point at it
+    let argc = Datum::Word(args.len() as i32 + 1);
+    interpreter.call(main, vec![argc, Datum::Long(0)])?;
+    return Ok(());
+}
+
+#[derive(PartialEq, Debug, Clone, Copy)]
+enum Datum {
+    Word(i32),
+    Long(i64),
+    Double(f64),
+    Bool(bool),
+    /// the index of the vector's elements in `Interpreter::vectors`
+    Vector(usize),
+    /// the index of the matrix in `Interpreter::matrices`
+    Matrix(usize),
+    /// the index of the closure in `Interpreter::closures`
+    Closure(usize),
+}
+
+impl Datum {
+    fn double(self) -> Result<f64> {
+        match self {
+            Datum::Double(x) => Ok(x),
+            other => bail!("expected a double but found {other:?}"),
+        }
+    }
+
+    fn long(self) -> Result<i64> {
+        match self {
+            Datum::Long(x) => Ok(x),
+            other => bail!("expected a long but found {other:?}"),
+        }
+    }
+
+    fn vector(self) -> Result<usize> {
+        match self {
+            Datum::Vector(x) => Ok(x),
+            other => bail!("expected a vector but found {other:?}"),
+        }
+    }
+
+    fn matrix(self) -> Result<usize> {
+        match self {
+            Datum::Matrix(x) => Ok(x),
+            other => bail!("expected a matrix but found {other:?}"),
+        }
+    }
+
+    fn closure(self) -> Result<usize> {
+        match self {
+            Datum::Closure(x) => Ok(x),
+            other => bail!("expected a closure but found {other:?}"),
+        }
+    }
+
+    fn truthy(self) -> bool {
+        match self {
+            Datum::Bool(x) => x,
+            Datum::Word(x) => x != 0,
+            Datum::Long(x) => x != 0,
+            Datum::Double(x) => x != 0.0,
+            Datum::Vector(_) | Datum::Matrix(_) | Datum::Closure(_) => true,
+        }
+    }
+}
+
+struct Interpreter<'a, W: Write> {
+    module: &'a Module,
+    out: &'a mut W,
+    globals: HashMap<&'a str, Datum>,
+    /// every vector made so far, like the compiled program's they are never freed
+    vectors: Vec<Vec<f64>>,
+    matrices: Vec<Matrix>,
+    /// the function of every closure made so far and the values it captured
+    closures: Vec<(&'a str, Vec<Datum>)>,
+    args: &'a [String],
+    input: &'a mut dyn BufRead,
+    /// the words of the last line read from `input` that `input()` hasn't taken yet
+    words: VecDeque<String>,
+}
+
+impl<'a, W: Write> Interpreter<'a, W> {
+    fn call(&mut self, function: &'a Function, args: Vec<Datum>) -> Result<Option<Datum>> {
+        let mut env: HashMap<&str, Datum> = function
+            .params
+            .iter()
+            .map(|param| param.name.as_str())
+            .zip(args)
+            .collect();
+        let mut block = &function.blocks[0];
+        // the block jumped from picks which value a phi takes
+        let mut previous: Option<&str> = None;
+        loop {
+            for statement in &block.statements {
+                let result = match &statement.operation {
+                    Operation::Phi(incoming) => {
+                        let (_, value) = incoming
+                            .iter()
+                            .find(|(label, _)| Some(label.as_str()) == previous)
+                            .ok_or_else(|| anyhow!("phi in @{} has no value", block.label))?;
+                        Some(eval(&env, value)?)
+                    }
+                    operation => self.execute(&env, operation)?,
+                };
+                if let (Some(dest), Some(result)) = (&statement.dest, result) {
+                    env.insert(&dest.name, result);
+                }
+            }
+            let next = match &block.terminator {
+                Terminator::Return(value) => {
+                    return value.as_ref().map(|v| eval(&env, v)).transpose();
+                }
+                Terminator::Jump(label) => label,
+                Terminator::Branch(cond, then, otherwise) => {
+                    if eval(&env, cond)?.truthy() {
+                        then
+                    } else {
+                        otherwise
+                    }
+                }
+            };
+            previous = Some(&block.label);
+            block = function
+                .blocks
+                .iter()
+                .find(|b| &b.label == next)
+                .ok_or_else(|| anyhow!("jump to unknown block @{next}"))?;
+        }
+    }
+
+    fn execute(
+        &mut self,
+        env: &HashMap<&str, Datum>,
+        operation: &'a Operation,
+    ) -> Result<Option<Datum>> {
+        // ints are exact, a result that doesn't fit in one is an error like in the compiled
+        // program
+        let arith = |x: &Value,
+                     y: &Value,
+                     long: fn(i64, i64) -> Result<i64>,
+                     double: fn(f64, f64) -> f64|
+         -> Result<Option<Datum>> {
+            let result = match (eval(env, x)?, eval(env, y)?) {
+                (Datum::Long(a), Datum::Long(b)) => Datum::Long(long(a, b)?),
+                (a, b) => Datum::Double(double(a.double()?, b.double()?)),
+            };
+            Ok(Some(result))
+        };
+        match operation {
+            Operation::Add(x, y)
+            | Operation::Sub(x, y)
+            | Operation::Mul(x, y)
+            | Operation::Div(x, y)
+            | Operation::Rem(x, y)
+            | Operation::Pow(x, y)
+                if x.ty() == Type::Vector || y.ty() == Type::Vector =>
+            {
+                let (x, y) = (eval(env, x)?, eval(env, y)?);
+                Ok(Some(self.elementwise(operation, x, y)?))
+            }
+            Operation::Copy(x) => Ok(Some(eval(env, x)?)),
+            Operation::Neg(x) => Ok(Some(match eval(env, x)? {
+                Datum::Long(x) => Datum::Long(x.checked_neg().ok_or_else(|| overflow("-"))?),
+                x => Datum::Double(-x.double()?),
+            })),
+            Operation::Add(x, y) => arith(
+                x,
+                y,
+                |a, b| a.checked_add(b).ok_or_else(|| overflow("+")),
+                |a, b| a + b,
+            ),
+            Operation::Sub(x, y) => arith(
+                x,
+                y,
+                |a, b| a.checked_sub(b).ok_or_else(|| overflow("-")),
+                |a, b| a - b,
+            ),
+            Operation::Mul(x, y) => arith(
+                x,
+                y,
+                |a, b| a.checked_mul(b).ok_or_else(|| overflow("*")),
+                |a, b| a * b,
+            ),
+            Operation::Div(x, y) => arith(
+                x,
+                y,
+                |a, b| a.checked_div(nonzero(b)?).ok_or_else(|| overflow("/")),
+                |a, b| a / b,
+            ),
+            Operation::Rem(x, y) => {
+                arith(x, y, |a, b| Ok(a.wrapping_rem(nonzero(b)?)), |a, b| a % b)
+            }
+            Operation::Pow(x, y) => arith(x, y, int_pow, f64::powf),
+            Operation::IntToFloat(x) => Ok(Some(Datum::Double(eval(env, x)?.long()? as f64))),
+            Operation::FloatToInt(x) => Ok(Some(Datum::Long(eval(env, x)?.double()? as i64))),
+            Operation::Compare(comparison, x, y) => {
+                let ordering = match (eval(env, x)?, eval(env, y)?) {
+                    (Datum::Long(a), Datum::Long(b)) => a.partial_cmp(&b),
+                    (Datum::Bool(a), Datum::Bool(b)) => a.partial_cmp(&b),
+                    (a, b) => a.double()?.partial_cmp(&b.double()?),
+                };
+                // every comparison but `!=` is false for nan
+                let result = match (comparison, ordering) {
+                    (Comparison::NotEqual, ordering) => ordering != Some(Ordering::Equal),
+                    (_, None) => false,
+                    (Comparison::Equal, Some(o)) => o.is_eq(),
+                    (Comparison::Less, Some(o)) => o.is_lt(),
+                    (Comparison::LessEqual, Some(o)) => o.is_le(),
+                    (Comparison::Greater, Some(o)) => o.is_gt(),
+                    (Comparison::GreaterEqual, Some(o)) => o.is_ge(),
+                };
+                Ok(Some(Datum::Bool(result)))
+            }
+            Operation::Not(x) => Ok(Some(Datum::Bool(!eval(env, x)?.truthy()))),
+            Operation::Phi(_) => bail!("phis are evaluated by the block running them"),
+            Operation::Vector(elements) => {
+                let elements = elements
+                    .iter()
+                    .map(|element| eval(env, element)?.double())
+                    .collect::<Result<_>>()?;
+                Ok(Some(self.vector(elements)))
+            }
+            Operation::Index(x, index) if x.ty() == Type::Matrix => {
+                let matrix = &self.matrices[eval(env, x)?.matrix()?];
+                let index = eval(env, index)?.long()?;
+                let row = usize::try_from(index)
+                    .ok()
+                    .filter(|i| *i < matrix.rows)
+                    .ok_or_else(|| {
+                        let rows = matrix.rows;
+                        anyhow!("index {index} is out of range for a matrix with {rows} rows")
+                    })?;
+                let elements = matrix.row(row).to_vec();
+                Ok(Some(self.vector(elements)))
+            }
+            Operation::Index(x, index) => {
+                let vector = &self.vectors[eval(env, x)?.vector()?];
+                let index = eval(env, index)?.long()?;
+                let element = usize::try_from(index)
+                    .ok()
+                    .and_then(|i| vector.get(i))
+                    .ok_or_else(|| {
+                        let length = vector.len();
+                        anyhow!("index {index} is out of range for a vector of length {length}")
+                    })?;
+                Ok(Some(Datum::Double(*element)))
+            }
+            Operation::Length(x) => {
+                let vector = &self.vectors[eval(env, x)?.vector()?];
+                Ok(Some(Datum::Long(vector.len() as i64)))
+            }
+            Operation::Reduce(reduction, x) => {
+                let vector = &self.vectors[eval(env, x)?.vector()?];
+                if vector.is_empty() && *reduction != Reduction::Sum {
+                    bail!("{reduction} of an empty vector");
+                }
+                // folded in order like the runtime so rounding matches
+                let result = match reduction {
+                    Reduction::Sum => vector.iter().fold(0.0, |a, b| a + b),
+                    Reduction::Mean => vector.iter().fold(0.0, |a, b| a + b) / vector.len() as f64,
+                    Reduction::Min => vector.iter().copied().reduce(f64::min).unwrap(),
+                    Reduction::Max => vector.iter().copied().reduce(f64::max).unwrap(),
+                };
+                Ok(Some(Datum::Double(result)))
+            }
+            Operation::Math(function, x) => {
+                let x = eval(env, x)?.double()?;
+                let result = match function {
+                    MathFunction::Sqrt => x.sqrt(),
+                    MathFunction::Exp => x.exp(),
+                    MathFunction::Ln => x.ln(),
+                    MathFunction::Sin => x.sin(),
+                    MathFunction::Cos => x.cos(),
+                };
+                Ok(Some(Datum::Double(result)))
+            }
+            Operation::Matrix(rows) => {
+                let rows = rows
+                    .iter()
+                    .map(|row| eval(env, row)?.vector())
+                    .collect::<Result<Vec<usize>>>()?;
+                let cols = rows.first().map_or(0, |row| self.vectors[*row].len());
+                let mut data = vec![];
+                for row in &rows {
+                    let row = &self.vectors[*row];
+                    if row.len() != cols {
+                        bail!(
+                            "the rows of a matrix have different lengths, {cols} and {}",
+                            row.len()
+                        );
+                    }
+                    data.extend(row);
+                }
+                let rows = rows.len();
+                Ok(Some(self.matrix(Matrix { rows, cols, data })))
+            }
+            Operation::MatMul(x, y) => {
+                let (x, y) = (eval(env, x)?, eval(env, y)?);
+                self.matmul(x, y).map(Some)
+            }
+            Operation::Transpose(x) => {
+                let x = &self.matrices[eval(env, x)?.matrix()?];
+                let data = linalg::transpose(&x.data, x.rows, x.cols);
+                let (rows, cols) = (x.cols, x.rows);
+                Ok(Some(self.matrix(Matrix { rows, cols, data })))
+            }
+            Operation::Det(x) => {
+                let x = &self.matrices[eval(env, x)?.matrix()?];
+                x.square("det")?;
+                Ok(Some(Datum::Double(linalg::determinant(&x.data, x.rows))))
+            }
+            Operation::Inverse(x) => {
+                let x = &self.matrices[eval(env, x)?.matrix()?];
+                x.square("inv")?;
+                let n = x.rows;
+                let data = linalg::solve(&x.data, &linalg::identity(n), n, n)
+                    .ok_or_else(|| anyhow!("`inv` of a singular matrix"))?;
+                Ok(Some(self.matrix(Matrix {
+                    rows: n,
+                    cols: n,
+                    data,
+                })))
+            }
+            Operation::Solve(a, b) => {
+                let a = &self.matrices[eval(env, a)?.matrix()?];
+                let b = &self.vectors[eval(env, b)?.vector()?];
+                a.square("solve")?;
+                if a.rows != b.len() {
+                    bail!(
+                        "`solve` can't combine {} with a vector of length {}",
+                        a,
+                        b.len()
+                    );
+                }
+                let x = linalg::solve(&a.data, b, a.rows, 1)
+                    .ok_or_else(|| anyhow!("`solve` of a singular matrix"))?;
+                Ok(Some(self.vector(x)))
+            }
+            Operation::Call(name, args) => {
+                let callee = self
+                    .module
+                    .function(name)
+                    .ok_or_else(|| anyhow!("call to unknown function ${name}"))?;
+                let args = args
+                    .iter()
+                    .map(|arg| eval(env, arg))
+                    .collect::<Result<_>>()?;
+                self.call(callee, args)
+            }
+            Operation::Closure(name, captures) => {
+                let captures = captures
+                    .iter()
+                    .map(|capture| eval(env, capture))
+                    .collect::<Result<_>>()?;
+                self.closures.push((name, captures));
+                Ok(Some(Datum::Closure(self.closures.len() - 1)))
+            }
+            Operation::Capture(closure, index) => {
+                let (_, captures) = &self.closures[eval(env, closure)?.closure()?];
+                let capture = captures
+                    .get(*index)
+                    .ok_or_else(|| anyhow!("closure has no capture {index}"))?;
+                Ok(Some(*capture))
+            }
+            Operation::CallClosure(closure, args) => {
+                let closure = eval(env, closure)?;
+                let args = args
+                    .iter()
+                    .map(|arg| eval(env, arg))
+                    .collect::<Result<_>>()?;
+                self.call_closure(closure, args)
+            }
+            Operation::Map(x, closure) => {
+                let closure = eval(env, closure)?;
+                let x = eval(env, x)?.vector()?;
+                let mut elements = vec![];
+                for index in 0..self.vectors[x].len() {
+                    let element = Datum::Double(self.vectors[x][index]);
+                    let result = self.call_closure(closure, vec![element])?;
+                    elements.push(
+                        result
+                            .ok_or_else(|| anyhow!("map needs a result"))?
+                            .double()?,
+                    );
+                }
+                Ok(Some(self.vector(elements)))
+            }
+            Operation::SetArgs(..) => Ok(None),
+            Operation::Arg(n) => {
+                let n = eval(env, n)?.long()?;
+                let given = self.args.len();
+                let Some(arg) = usize::try_from(n)
+                    .ok()
+                    .filter(|n| (1..=given).contains(n))
+                    .map(|n| &self.args[n - 1])
+                else {
+                    let plural = if given == 1 { "" } else { "s" };
+                    bail!("`arg({n})` is missing, the program was given {given} argument{plural}");
+                };
+                let x = number(arg)
+                    .ok_or_else(|| anyhow!("`arg({n})` is `{arg}`, which is not a number"))?;
+                Ok(Some(Datum::Double(x)))
+            }
+            Operation::Input => {
+                while self.words.is_empty() {
+                    let mut line = String::new();
+                    if self.input.read_line(&mut line)? == 0 {
+                        bail!("`input()` found no number left on stdin");
+                    }
+                    self.words
+                        .extend(line.split_whitespace().map(str::to_string));
+                }
+                let word = self.words.pop_front().expect("a word was just read");
+                let x = number(&word)
+                    .ok_or_else(|| anyhow!("`input()` read `{word}`, which is not a number"))?;
+                Ok(Some(Datum::Double(x)))
+            }
+            Operation::Print(x, format) => {
+                match eval(env, x)? {
+                    Datum::Long(x) => writeln!(self.out, "{x}")?,
+                    Datum::Bool(x) => writeln!(self.out, "{x}")?,
+                    Datum::Vector(x) => {
+                        let elements = self.vectors[x]
+                            .iter()
+                            .map(|element| format.format(*element))
+                            .collect::<Vec<String>>()
+                            .join(", ");
+                        writeln!(self.out, "[{elements}]")?
+                    }
+                    Datum::Matrix(x) => {
+                        let matrix = &self.matrices[x];
+                        let rows = (0..matrix.rows)
+                            .map(|i| {
+                                let elements = matrix
+                                    .row(i)
+                                    .iter()
+                                    .map(|element| format.format(*element))
+                                    .collect::<Vec<String>>()
+                                    .join(", ");
+                                format!("[{elements}]")
+                            })
+                            .collect::<Vec<String>>()
+                            .join(", ");
+                        writeln!(self.out, "[{rows}]")?
+                    }
+                    x => writeln!(self.out, "{}", format.format(x.double()?))?,
+                }
+                Ok(None)
+            }
+            Operation::Load(name) => {
+                let value = self
+                    .globals
+                    .get(name.as_str())
+                    .ok_or_else(|| anyhow!("load from unknown global ${name}"))?;
+                Ok(Some(*value))
+            }
+            Operation::Store(name, x) => {
+                let value = eval(env, x)?;
+                self.globals.insert(name, value);
+                Ok(None)
+            }
+        }
+    }
+
+    /// Calls the function of a closure with the closure before the arguments.
+    fn call_closure(&mut self, closure: Datum, mut args: Vec<Datum>) -> Result<Option<Datum>> {
+        let (name, _) = self.closures[closure.closure()?];
+        let callee = self
+            .module
+            .function(name)
+            .ok_or_else(|| anyhow!("closure of unknown function ${name}"))?;
+        args.insert(0, closure);
+        return self.call(callee, args);
+    }
+
+    fn vector(&mut self, elements: Vec<f64>) -> Datum {
+        self.vectors.push(elements);
+        return Datum::Vector(self.vectors.len() - 1);
+    }
+
+    fn matrix(&mut self, matrix: Matrix) -> Datum {
+        self.matrices.push(matrix);
+        return Datum::Matrix(self.matrices.len() - 1);
+    }
+
+    /// `x @ y`, a vector is a column on the right and a row on the left so the product of
+    /// two vectors is their dot product.
+    fn matmul(&mut self, x: Datum, y: Datum) -> Result<Datum> {
+        let (x_rows, inner, x_data) = match x {
+            Datum::Matrix(x) => {
+                let x = &self.matrices[x];
+                (Some(x.rows), x.cols, &x.data)
+            }
+            x => {
+                let x = &self.vectors[x.vector()?];
+                (None, x.len(), x)
+            }
+        };
+        let (y_cols, y_rows, y_data) = match y {
+            Datum::Matrix(y) => {
+                let y = &self.matrices[y];
+                (Some(y.cols), y.rows, &y.data)
+            }
+            y => {
+                let y = &self.vectors[y.vector()?];
+                (None, y.len(), y)
+            }
+        };
+        if inner != y_rows {
+            let left = describe(x_rows, inner);
+            let right = match y_cols {
+                Some(cols) => describe(Some(y_rows), cols),
+                None => describe(None, y_rows),
+            };
+            bail!("`@` can't combine {left} with {right}");
+        }
+        let rows = x_rows.unwrap_or(1);
+        let cols = y_cols.unwrap_or(1);
+        let data = linalg::multiply(x_data, y_data, rows, inner, cols);
+        let result = match (x_rows, y_cols) {
+            (Some(_), Some(_)) => self.matrix(Matrix { rows, cols, data }),
+            (None, None) => Datum::Double(data[0]),
+            _ => self.vector(data),
+        };
+        return Ok(result);
+    }
+
+    /// Arithmetic with a vector, computed element by element. A double is used with every
+    /// element and two vectors need the same length.
+    fn elementwise(&mut self, operation: &Operation, x: Datum, y: Datum) -> Result<Datum> {
+        let apply: fn(f64, f64) -> f64 = match operation {
+            Operation::Add(..) => |a, b| a + b,
+            Operation::Sub(..) => |a, b| a - b,
+            Operation::Mul(..) => |a, b| a * b,
+            Operation::Div(..) => |a, b| a / b,
+            Operation::Rem(..) => |a, b| a % b,
+            _ => f64::powf,
+        };
+        let elements = match (x, y) {
+            (Datum::Vector(x), Datum::Vector(y)) => {
+                let (x, y) = (&self.vectors[x], &self.vectors[y]);
+                if x.len() != y.len() {
+                    bail!(
+                        "vectors of length {} and {} can't be combined",
+                        x.len(),
+                        y.len()
+                    );
+                }
+                x.iter().zip(y).map(|(a, b)| apply(*a, *b)).collect()
+            }
+            (Datum::Vector(x), y) => {
+                let y = y.double()?;
+                self.vectors[x].iter().map(|a| apply(*a, y)).collect()
+            }
+            (x, Datum::Vector(y)) => {
+                let x = x.double()?;
+                self.vectors[y].iter().map(|b| apply(x, *b)).collect()
+            }
+            (x, y) => bail!("no vector in {operation} of {x:?} and {y:?}"),
+        };
+        return Ok(self.vector(elements));
+    }
+}
+
+/// A matrix stored row by row.
+struct Matrix {
+    rows: usize,
+    cols: usize,
+    data: Vec<f64>,
+}
+
+impl Matrix {
+    fn row(&self, i: usize) -> &[f64] {
+        &self.data[i * self.cols..(i + 1) * self.cols]
+    }
+
+    /// Fails unless the matrix is square, `name` is the builtin that needs it to be.
+    fn square(&self, name: &str) -> Result<()> {
+        if self.rows != self.cols {
+            bail!(
+                "`{name}` needs a square matrix but found a {}x{} one",
+                self.rows,
+                self.cols
+            );
+        }
+        return Ok(());
+    }
+}
+
+impl fmt::Display for Matrix {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", describe(Some(self.rows), self.cols))
+    }
+}
+
+/// Describes a matrix with `rows` or a vector of length `cols` the way the compiler does.
+fn describe(rows: Option<usize>, cols: usize) -> String {
+    match rows {
+        Some(rows) => format!("a {rows}x{cols} matrix"),
+        None => format!("a vector of length {cols}"),
+    }
+}
+
+fn eval(env: &HashMap<&str, Datum>, value: &Value) -> Result<Datum> {
+    match value {
+        Value::Temp(temp) => env
+            .get(temp.name.as_str())
+            .copied()
+            .ok_or_else(|| anyhow!("%{} is used before it is defined", temp.name)),
+        Value::Double(x) => Ok(Datum::Double(*x)),
+        Value::Long(x) => Ok(Datum::Long(*x)),
+        Value::Word(x) => Ok(Datum::Word(*x)),
+        Value::Bool(x) => Ok(Datum::Bool(*x)),
+    }
+}
+
+/// Reads a number the way `nm_number` does in the runtime, the whole word has to be used.
+fn number(word: &str) -> Option<f64> {
+    return word.parse().ok();
+}
+
+fn nonzero(divisor: i64) -> Result<i64> {
+    if divisor == 0 {
+        bail!("integer division by zero");
+    }
+    return Ok(divisor);
+}
+
+fn overflow(operator: &str) -> anyhow::Error {
+    return anyhow!("integer overflow in `{operator}`");
+}
+
+/// Raises an int to an int power, a negative exponent truncates `1 / x^-y` like integer
+/// division does.
+fn int_pow(x: i64, y: i64) -> Result<i64> {
+    if y < 0 {
+        return match x {
+            0 => bail!("integer division by zero"),
+            1 | -1 if y % 2 == 0 => Ok(1),
+            1 | -1 => Ok(x),
+            _ => Ok(0),
+        };
+    }
+    let exponent = match u32::try_from(y) {
+        Ok(exponent) => exponent,
+        // only the powers of -1, 0 and 1 fit in an int for exponents this large, and they only
+        // depend on whether it is odd
+        Err(_) if x.unsigned_abs() <= 1 => 2 - (y & 1) as u32,
+        Err(_) => return Err(overflow("^")),
+    };
+    return x.checked_pow(exponent).ok_or_else(|| overflow("^"));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{compiler::compile, format::PrintFormat, parser::parse};
+    use std::io;
+
+    fn run_source(source: &str) -> String {
+        let module = compile(parse(source), PrintFormat::default()).unwrap();
+        let mut out = vec![];
+        run(&module, &[], &mut io::empty(), &mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn prints_expressions() {
+        assert_eq!(run_source("1 + 2\n2.0 ^ 10 / 4"), "3\n256.0000\n");
+    }
+
+    #[test]
+    fn negates() {
+        assert_eq!(run_source("-2 ^ 2\n3 * -(1.5 - 2)"), "-4\n1.5000\n");
+    }
+
+    #[test]
+    fn calls_user_functions() {
+        assert_eq!(
+            run_source("x = 3\nf(a, b) = a * b\nf(x, 2) - 1"),
+            "5.0000\n"
+        );
+    }
+
+    #[test]
+    fn reassigns_variables() {
+        assert_eq!(
+            run_source("x = 1\nx = x + 1\nx\nx = x * 0.5; x"),
+            "2\n1.0000\n"
+        );
+    }
+
+    #[test]
+    fn reads_globals_when_called() {
+        let output = run_source(
+            "rate = 0.05\ninterest(p) = p * rate\ninterest(100)\nrate = 0.1\ninterest(100)",
+        );
+        assert_eq!(output, "5.0000\n10.0000\n");
+    }
+
+    #[test]
+    fn follows_format_directives() {
+        let output = run_source("#fmt sci 2\n0.000000001\n#fmt exact\n0.1\n#fmt hex-float\n3.0");
+        assert_eq!(output, "1.00e-09\n0.1\n0x1.8p+1\n");
+    }
+
+    #[test]
+    fn computes_exact_ints() {
+        let output = run_source("2 ^ 62 + 1\n7 / 2; -7 % 3; 7 / 2.0\n2 ^ -1; int(-2.7); float(3)");
+        assert_eq!(
+            output,
+            "4611686018427387905\n3\n-1\n3.5000\n0\n-2\n3.0000\n"
+        );
+    }
+
+    #[test]
+    fn fails_on_int_overflow() {
+        assert_eq!(
+            run_source("0x7FFF_FFFF_FFFF_FFFF - 1 + 1; -0x7FFF_FFFF_FFFF_FFFF - 1; (-2) ^ 63"),
+            "9223372036854775807\n-9223372036854775808\n-9223372036854775808\n"
+        );
+        for (source, operator) in [
+            ("0x7FFF_FFFF_FFFF_FFFF + 1", "+"),
+            ("-0x7FFF_FFFF_FFFF_FFFF - 2", "-"),
+            ("4_000_000_000 * 4_000_000_000", "*"),
+            ("n = -0x7FFF_FFFF_FFFF_FFFF - 1\nn / -1", "/"),
+            ("n = -0x7FFF_FFFF_FFFF_FFFF - 1\n-n", "-"),
+            ("2 ^ 63", "^"),
+            ("3 ^ 0x1_0000_0000", "^"),
+        ] {
+            let module = compile(parse(source), PrintFormat::default()).unwrap();
+            let error = run(&module, &[], &mut io::empty(), &mut vec![]).unwrap_err();
+            assert_eq!(
+                error.to_string(),
+                format!("integer overflow in `{operator}`")
+            );
+        }
+    }
+
+    #[test]
+    fn fails_on_int_division_by_zero() {
+        let module = compile(parse("1 / 0"), PrintFormat::default()).unwrap();
+        assert!(run(&module, &[], &mut io::empty(), &mut vec![]).is_err());
+    }
+
+    #[test]
+    fn short_circuits_logic() {
+        let output = run_source("1 < 2 and not false\nfalse and 1 / 0 == 1\n2 == 2.0 or 1 / 0 > 1");
+        assert_eq!(output, "true\nfalse\ntrue\n");
+    }
+
+    #[test]
+    fn returns_bools_from_functions() {
+        assert_eq!(
+            run_source("even(n) = n % 2 == 0\neven(4); even(7) or even(9)"),
+            "true\nfalse\n"
+        );
+    }
+
+    #[test]
+    fn computes_vectors() {
+        let output = run_source(
+            "v = [1, 2, 4]\nv * 2 - [1, 1, 1]; 2 ^ v; -v[2]\nlen(v); sum(v); mean(v); min(v); max(v)",
+        );
+        assert_eq!(
+            output,
+            "[1.0000, 3.0000, 7.0000]\n[2.0000, 4.0000, 16.0000]\n-4.0000\n\
+             3\n7.0000\n2.3333\n1.0000\n4.0000\n"
+        );
+    }
+
+    #[test]
+    fn fails_on_vector_misuse() {
+        for source in [
+            "[1, 2][2]",
+            "[1, 2][-1]",
+            "f(v: vector) = v + [1]\nf([1, 2])",
+            "max([])",
+        ] {
+            let module = compile(parse(source), PrintFormat::default()).unwrap();
+            let result = run(&module, &[], &mut io::empty(), &mut vec![]);
+            assert!(result.is_err(), "{source}");
+        }
+    }
+
+    #[test]
+    fn runs_loops() {
+        let output = run_source(
+            "n = 4\nsum(i, 1, n, i ^ 2); prod(i, 1, n, i); sum(i, 3, 1, 1.5)\n\
+             e(x) = sum(k, 0, 12, x ^ k / prod(j, 1, k, float(j)))\ne(1)",
+        );
+        assert_eq!(output, "30\n24\n0.0000\n2.7183\n");
+    }
+
+    #[test]
+    fn runs_lambdas() {
+        let output = run_source(
+            "apply(f, x) = f(x)\napply(x -> x ^ 2, 3)\nk = 10\nmap([1, 2], x -> x + k)\n\
+             integrate(f, a, b) = sum(i, 0, 999, f(a + (i + 0.5) * (b - a) / 1000)) * (b - a) / 1000\n\
+             integrate(x -> 3 * x ^ 2, 0, 1)\n\
+             compose(f, g) = x -> f(g(x))\nsq(x) = x * x\nh = compose(sq, x -> x + 1)\nh(2)",
+        );
+        assert_eq!(output, "9.0000\n[11.0000, 12.0000]\n1.0000\n9.0000\n");
+    }
+
+    #[test]
+    fn runs_let_bindings() {
+        let output = run_source(
+            "f(x) = let t = x ^ 2 + 1 in t * t\nx = 2\nlet x = x + 1 in (let x = x * 2 in x) + x\nx\n\
+             g(n) = let k = n * 2 in map([1, 2], v -> v * k)\ng(2)",
+        );
+        assert_eq!(output, "9\n2\n[4.0000, 8.0000]\n");
+    }
+
+    #[test]
+    fn computes_math_builtins() {
+        let output = run_source(
+            "f(x) = let t = x ^ 2 + 1 in t * sqrt(t)\nf(2)\n\
+             sqrt(16); exp(1); ln(exp(2)); sin(0); cos(0)\nsqrt(-1) != sqrt(-1)",
+        );
+        assert_eq!(
+            output,
+            "11.1803\n4.0000\n2.7183\n2.0000\n0.0000\n1.0000\ntrue\n"
+        );
+    }
+
+    #[test]
+    fn integrates_math_builtins_through_lambdas() {
+        let output = run_source(
+            "integrate(f, a, b) = sum(i, 0, 999, f(a + (i + 0.5) * (b - a) / 1000)) * (b - a) / 1000\n\
+             integrate(x -> sin(x), 0, 3.141592653589793)\napply(f: (float) -> float, x) = f(x)\n\
+             apply(x -> cos(x) ^ 2 + sin(x) ^ 2, 0.7); apply(x -> exp(ln(x)), 5)",
+        );
+        assert_eq!(output, "2.0000\n1.0000\n5.0000\n");
+    }
+
+    #[test]
+    fn runs_blocks() {
+        let output = run_source(
+            "a = 100\nf(x) = { a = x * 2; b = a + 1; a * b }\nf(3)\n\
+             g(v) = {\n    n = len(v)\n    m = sum(v) / n\n    map(v, x -> x - m)\n}\n\
+             g([1, 2, 3])\na",
+        );
+        assert_eq!(output, "42.0000\n[-1.0000, 0.0000, 1.0000]\n100\n");
+    }
+
+    #[test]
+    fn computes_matrices() {
+        let output = run_source(
+            "a = [[2, 1], [1, 3]]\nb = [1, 2]\na @ b; b @ a; b @ b; a[1]\n\
+             T([[1, 2, 3]]); det(a); inv(a); solve(a, [3, 5])",
+        );
+        assert_eq!(
+            output,
+            "[4.0000, 7.0000]\n[4.0000, 7.0000]\n5.0000\n[1.0000, 3.0000]\n\
+             [[1.0000], [2.0000], [3.0000]]\n5.0000\n[[0.6000, -0.2000], [-0.2000, 0.4000]]\n\
+             [0.8000, 1.4000]\n"
+        );
+    }
+
+    #[test]
+    fn fails_on_matrix_misuse() {
+        for source in [
+            "[[1, 2], [2, 4]][2]",
+            "f(m: matrix) = det(m)\nf([[1, 2]])",
+            "inv([[1, 2], [2, 4]])",
+            "f(m: matrix) = m @ [1, 2]\nf([[1, 2, 3]])",
+            "f(v: vector) = [[1, 2], v]\nf([1])",
+        ] {
+            let module = compile(parse(source), PrintFormat::default()).unwrap();
+            assert!(
+                run(&module, &[], &mut io::empty(), &mut vec![]).is_err(),
+                "{source}"
+            );
+        }
+    }
+
+    #[test]
+    fn reads_args_and_input() {
+        let module = compile(
+            parse(
+                "x = arg(1)
+x * arg(2)
+input() + input()
+input()",
+            ),
+            PrintFormat::default(),
+        )
+        .unwrap();
+        let args = ["3".to_string(), "0.5".to_string()];
+        let mut out = vec![];
+        run(&module, &args, &mut "1 2.5\n\n  -4\n".as_bytes(), &mut out).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), "1.5000\n3.5000\n-4.0000\n");
+        for (args, input, error) in [
+            (
+                &args[..1],
+                "1 2 3",
+                "`arg(2)` is missing, the program was given 1 argument",
+            ),
+            (
+                &["3".to_string(), "x".to_string()][..],
+                "",
+                "`arg(2)` is `x`, which is not a number",
+            ),
+            (&args[..], "1 2", "`input()` found no number left on stdin"),
+            (
+                &args[..],
+                "1 two 3",
+                "`input()` read `two`, which is not a number",
+            ),
+        ] {
+            let result = run(&module, args, &mut input.as_bytes(), &mut vec![]);
+            assert_eq!(result.unwrap_err().to_string(), error);
+        }
+    }
+}
@@ -0,0 +1,1379 @@
+use anyhow::{bail, Result};
+use std::{
+    collections::{HashMap, HashSet},
+    fmt,
+};
+
+use crate::{error::CompileError, format::PrintFormat};
+
+/// A whole program in numerus' own ssa form, every backend is lowered from this.
+#[derive(PartialEq, Debug, Clone, Default)]
+pub struct Module {
+    pub functions: Vec<Function>,
+    pub globals: Vec<Global>,
+}
+
+impl Module {
+    pub fn function(&self, name: &str) -> Option<&Function> {
+        self.functions.iter().find(|f| f.name == name)
+    }
+
+    pub fn global(&self, name: &str) -> Option<&Global> {
+        self.globals.iter().find(|g| g.name == name)
+    }
+}
+
+/// A variable kept in memory so every function can read it, starts out as zero.
+#[derive(PartialEq, Debug, Clone)]
+pub struct Global {
+    pub name: String,
+    pub ty: Type,
+}
+
+#[derive(PartialEq, Debug, Clone)]
+pub struct Function {
+    pub export: bool,
+    pub name: String,
+    pub params: Vec<Temp>,
+    pub return_type: Type,
+    pub blocks: Vec<Block>,
+}
+
+impl Function {
+    pub fn new(name: String, params: Vec<Temp>, return_type: Type) -> Self {
+        Self {
+            export: false,
+            name,
+            params,
+            return_type,
+            blocks: vec![],
+        }
+    }
+
+    /// The program entry point, expression statements outside of functions end up in here. It
+    /// takes the command line like c's `main`, `argv` is a pointer to the arguments.
+    pub fn new_main() -> Self {
+        let params = vec![
+            Temp::new("argc".to_string(), Type::Word),
+            Temp::new("argv".to_string(), Type::Long),
+        ];
+        Self {
+            export: true,
+            ..Self::new("main".to_string(), params, Type::Word)
+        }
+    }
+}
+
+#[derive(PartialEq, Debug, Clone)]
+pub struct Block {
+    pub label: String,
+    pub statements: Vec<Statement>,
+    pub terminator: Terminator,
+}
+
+impl Block {
+    pub fn new(label: String) -> Self {
+        Self {
+            label,
+            statements: vec![],
+            terminator: Terminator::Return(None),
+        }
+    }
+}
+
+/// A single instruction, `dest` is `None` for operations only run for their side effects.
+#[derive(PartialEq, Debug, Clone)]
+pub struct Statement {
+    pub dest: Option<Temp>,
+    pub operation: Operation,
+}
+
+impl Statement {
+    pub fn assign(dest: Temp, operation: Operation) -> Self {
+        Self {
+            dest: Some(dest),
+            operation,
+        }
+    }
+
+    pub fn effect(operation: Operation) -> Self {
+        Self {
+            dest: None,
+            operation,
+        }
+    }
+}
+
+#[derive(PartialEq, Debug, Clone)]
+pub enum Operation {
+    Copy(Value),
+    Neg(Value),
+    Add(Value, Value),
+    Sub(Value, Value),
+    Mul(Value, Value),
+    Div(Value, Value),
+    /// remainder with the sign of the dividend, like c's `%` and `fmod`
+    Rem(Value, Value),
+    Pow(Value, Value),
+    /// converts a long to the nearest double
+    IntToFloat(Value),
+    /// converts a double to a long, dropping the fraction and saturating at the long's limits,
+    /// nan becomes zero
+    FloatToInt(Value),
+    /// compares two values of the same type, giving a bool
+    Compare(Comparison, Value, Value),
+    Not(Value),
+    /// the value from whichever of these predecessor blocks ran last, has to come before any
+    /// other statement in its block
+    Phi(Vec<(String, Value)>),
+    /// a new vector holding these doubles
+    Vector(Vec<Value>),
+    /// a new matrix with these vectors as its rows, rows of different lengths stop the program
+    Matrix(Vec<Value>),
+    /// the element of a vector, or the row of a matrix, at a long index. An index out of range
+    /// stops the program
+    Index(Value, Value),
+    /// the number of elements in a vector, as a long
+    Length(Value),
+    /// combines every element of a vector into one double
+    Reduce(Reduction, Value),
+    /// an elementary function of a double, outside of its domain the result is nan
+    Math(MathFunction, Value),
+    /// the product of matrices and vectors, a vector on the left is a row and on the right a
+    /// column, two vectors give their dot product. Mismatched sizes stop the program
+    MatMul(Value, Value),
+    Transpose(Value),
+    /// the determinant of a square matrix
+    Det(Value),
+    /// the inverse of a square matrix, a singular one stops the program
+    Inverse(Value),
+    /// the vector `x` solving `a @ x = b` for a square matrix `a`
+    Solve(Value, Value),
+    Call(String, Vec<Value>),
+    /// a new closure of the function with these values captured, the function takes the
+    /// closure as its first parameter followed by its own arguments
+    Closure(String, Vec<Value>),
+    /// a value captured by the closure, read by the function it closes over
+    Capture(Value, usize),
+    /// calls the function of a closure, passing the closure along with the arguments
+    CallClosure(Value, Vec<Value>),
+    /// a new vector with a closure from double to double applied to every element
+    Map(Value, Value),
+    Print(Value, PrintFormat),
+    /// keeps the parameters of `main` for `Arg`, has to run before it
+    SetArgs(Value, Value),
+    /// the command line argument at a long index read as a double, counting from 1. A missing
+    /// argument or one that isn't a number stops the program
+    Arg(Value),
+    /// the next number read from stdin as a double, running out of input or reading something
+    /// else stops the program
+    Input,
+    /// reads the current value of a global
+    Load(String),
+    /// replaces the value of a global
+    Store(String, Value),
+}
+
+impl Operation {
+    /// Every value read by this operation, in order.
+    pub fn operands(&self) -> Vec<&Value> {
+        match self {
+            Operation::Copy(x)
+            | Operation::Neg(x)
+            | Operation::IntToFloat(x)
+            | Operation::FloatToInt(x)
+            | Operation::Length(x)
+            | Operation::Reduce(_, x)
+            | Operation::Math(_, x)
+            | Operation::Transpose(x)
+            | Operation::Det(x)
+            | Operation::Inverse(x)
+            | Operation::Print(x, _)
+            | Operation::Capture(x, _)
+            | Operation::Arg(x)
+            | Operation::Store(_, x) => vec![x],
+            Operation::Add(x, y)
+            | Operation::Sub(x, y)
+            | Operation::Mul(x, y)
+            | Operation::Div(x, y)
+            | Operation::Rem(x, y)
+            | Operation::Pow(x, y)
+            | Operation::Compare(_, x, y)
+            | Operation::Index(x, y)
+            | Operation::MatMul(x, y)
+            | Operation::Solve(x, y)
+            | Operation::SetArgs(x, y)
+            | Operation::Map(x, y) => vec![x, y],
+            Operation::Not(x) => vec![x],
+            Operation::Phi(incoming) => incoming.iter().map(|(_, value)| value).collect(),
+            Operation::Vector(elements)
+            | Operation::Matrix(elements)
+            | Operation::Call(_, elements)
+            | Operation::Closure(_, elements) => elements.iter().collect(),
+            Operation::CallClosure(closure, args) => std::iter::once(closure).chain(args).collect(),
+            Operation::Load(_) | Operation::Input => vec![],
+        }
+    }
+}
+
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum Comparison {
+    Equal,
+    NotEqual,
+    Less,
+    LessEqual,
+    Greater,
+    GreaterEqual,
+}
+
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum Reduction {
+    Sum,
+    /// fails on an empty vector
+    Mean,
+    /// fails on an empty vector
+    Min,
+    /// fails on an empty vector
+    Max,
+}
+
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum MathFunction {
+    Sqrt,
+    Exp,
+    /// the natural logarithm
+    Ln,
+    Sin,
+    Cos,
+}
+
+#[derive(PartialEq, Debug, Clone)]
+pub enum Terminator {
+    Return(Option<Value>),
+    Jump(String),
+    Branch(Value, String, String),
+}
+
+impl Terminator {
+    pub fn successors(&self) -> Vec<&str> {
+        match self {
+            Terminator::Return(_) => vec![],
+            Terminator::Jump(label) => vec![label],
+            Terminator::Branch(_, then, otherwise) => vec![then, otherwise],
+        }
+    }
+}
+
+#[derive(PartialEq, Debug, Clone)]
+pub struct Temp {
+    pub name: String,
+    pub ty: Type,
+}
+
+impl Temp {
+    pub fn new(name: String, ty: Type) -> Self {
+        Self { name, ty }
+    }
+}
+
+#[derive(PartialEq, Debug, Clone)]
+pub enum Value {
+    Temp(Temp),
+    Double(f64),
+    Long(i64),
+    Word(i32),
+    Bool(bool),
+}
+
+impl Value {
+    pub fn ty(&self) -> Type {
+        match self {
+            Value::Temp(temp) => temp.ty.clone(),
+            Value::Double(_) => Type::Double,
+            Value::Long(_) => Type::Long,
+            Value::Bool(_) => Type::Bool,
+            Value::Word(_) => Type::Word,
+        }
+    }
+}
+
+#[derive(PartialEq, Eq, Hash, Debug, Clone)]
+pub enum Type {
+    Word,
+    Long,
+    Double,
+    /// stored as a word holding 0 or 1
+    Bool,
+    /// a pointer to the length of the vector followed by its doubles
+    Vector,
+    /// a pointer to the number of rows and columns followed by the doubles of each row
+    Matrix,
+    /// a pointer to a closure, the function it calls followed by the values it captured, with
+    /// the parameter and result types of the function
+    Function(Vec<Type>, Box<Type>),
+}
+
+impl Type {
+    /// What the type is called in numerus programs and error messages.
+    pub fn name(&self) -> String {
+        let name = match self {
+            Type::Word => "word",
+            Type::Long => "int",
+            Type::Double => "float",
+            Type::Bool => "bool",
+            Type::Vector => "vector",
+            Type::Matrix => "matrix",
+            Type::Function(params, result) => {
+                let params = params
+                    .iter()
+                    .map(|param| param.name())
+                    .collect::<Vec<String>>()
+                    .join(", ");
+                return format!("({params}) -> {}", result.name());
+            }
+        };
+        return name.to_string();
+    }
+}
+
+/// Checks the structure of a module, any error here is a bug in the compiler rather than
+/// in the program being compiled.
+pub fn verify(module: &Module) -> Result<()> {
+    let mut names = HashSet::new();
+    for global in &module.globals {
+        if !names.insert(&global.name) {
+            bail!(CompileError::InvalidIr(
+                global.name.clone(),
+                "global is defined more than once".to_string()
+            ));
+        }
+    }
+    let mut names = HashSet::new();
+    for function in &module.functions {
+        if !names.insert(&function.name) {
+            return Err(invalid(function, "function is defined more than once"));
+        }
+        verify_function(module, function)?;
+    }
+    return Ok(());
+}
+
+fn verify_function(module: &Module, function: &Function) -> Result<()> {
+    if function.blocks.is_empty() {
+        return Err(invalid(function, "function has no blocks"));
+    }
+    let mut labels = HashSet::new();
+    for block in &function.blocks {
+        if !labels.insert(block.label.as_str()) {
+            return Err(invalid(
+                function,
+                &format!("duplicate block @{}", block.label),
+            ));
+        }
+    }
+
+    let mut assigned = HashSet::new();
+    for temp in function.params.iter().chain(
+        function
+            .blocks
+            .iter()
+            .flat_map(|block| &block.statements)
+            .filter_map(|statement| statement.dest.as_ref()),
+    ) {
+        if !assigned.insert(temp.name.as_str()) {
+            return Err(invalid(
+                function,
+                &format!("%{} is assigned more than once", temp.name),
+            ));
+        }
+    }
+
+    let predecessors = predecessors(function);
+    let dominators = dominators(function, &predecessors);
+    // everything defined once a block has run to its end
+    let defined_after = |label: &str| -> HashSet<&str> {
+        function
+            .blocks
+            .iter()
+            .filter(|b| dominators[label].contains(b.label.as_str()))
+            .flat_map(|b| &b.statements)
+            .filter_map(|s| s.dest.as_ref())
+            .map(|d| d.name.as_str())
+            .chain(function.params.iter().map(|p| p.name.as_str()))
+            .collect()
+    };
+    for block in &function.blocks {
+        // everything defined in a dominating block is guaranteed to have run before this one
+        let mut defined: HashSet<&str> = function.params.iter().map(|p| p.name.as_str()).collect();
+        for dominator in function.blocks.iter().filter(|b| {
+            b.label != block.label && dominators[block.label.as_str()].contains(b.label.as_str())
+        }) {
+            defined.extend(
+                dominator
+                    .statements
+                    .iter()
+                    .filter_map(|s| s.dest.as_ref())
+                    .map(|d| d.name.as_str()),
+            );
+        }
+
+        for (index, statement) in block.statements.iter().enumerate() {
+            if let Operation::Phi(incoming) = &statement.operation {
+                let after_phis = block.statements[..index]
+                    .iter()
+                    .any(|s| !matches!(s.operation, Operation::Phi(_)));
+                if after_phis {
+                    return Err(invalid(function, "phi after other statements"));
+                }
+                let mut from: Vec<&str> = incoming.iter().map(|(l, _)| l.as_str()).collect();
+                let mut expected = predecessors
+                    .get(block.label.as_str())
+                    .cloned()
+                    .unwrap_or_default();
+                from.sort();
+                expected.sort();
+                if from != expected {
+                    return Err(invalid(
+                        function,
+                        &format!("phi in @{} doesn't list each predecessor once", block.label),
+                    ));
+                }
+                for (label, value) in incoming {
+                    check_defined(function, &defined_after(label), value)?;
+                }
+            } else {
+                for operand in statement.operation.operands() {
+                    check_defined(function, &defined, operand)?;
+                }
+            }
+            let result = result_type(module, function, &statement.operation)?;
+            match (&statement.dest, result) {
+                (Some(dest), Some(ty)) if dest.ty != ty => {
+                    return Err(invalid(
+                        function,
+                        &format!("%{} is {} but assigned a {} value", dest.name, dest.ty, ty),
+                    ));
+                }
+                (Some(dest), None) => {
+                    return Err(invalid(
+                        function,
+                        &format!("%{} is assigned an operation without a result", dest.name),
+                    ));
+                }
+                _ => {}
+            }
+            if let Some(dest) = &statement.dest {
+                defined.insert(&dest.name);
+            }
+        }
+
+        match &block.terminator {
+            Terminator::Return(value) => {
+                if let Some(value) = value {
+                    check_defined(function, &defined, value)?;
+                }
+                let ty = value.as_ref().map(|v| v.ty());
+                if ty.as_ref() != Some(&function.return_type) {
+                    return Err(invalid(function, "return value does not match return type"));
+                }
+            }
+            Terminator::Jump(target) => check_label(function, &labels, target)?,
+            Terminator::Branch(cond, then, otherwise) => {
+                check_defined(function, &defined, cond)?;
+                check_label(function, &labels, then)?;
+                check_label(function, &labels, otherwise)?;
+            }
+        }
+    }
+    return Ok(());
+}
+
+/// Maps each block to the labels of the blocks that can jump to it.
+fn predecessors(function: &Function) -> HashMap<&str, Vec<&str>> {
+    let mut predecessors: HashMap<&str, Vec<&str>> = HashMap::new();
+    for block in &function.blocks {
+        for successor in block.terminator.successors() {
+            predecessors
+                .entry(successor)
+                .or_default()
+                .push(&block.label);
+        }
+    }
+    return predecessors;
+}
+
+/// Maps each block to the labels of every block that dominates it, including itself.
+fn dominators<'a>(
+    function: &'a Function,
+    predecessors: &HashMap<&str, Vec<&'a str>>,
+) -> HashMap<&'a str, HashSet<&'a str>> {
+    let all: HashSet<&str> = function.blocks.iter().map(|b| b.label.as_str()).collect();
+
+    let entry = function.blocks[0].label.as_str();
+    let mut dominators: HashMap<&str, HashSet<&str>> = function
+        .blocks
+        .iter()
+        .map(|b| (b.label.as_str(), all.clone()))
+        .collect();
+    dominators.insert(entry, HashSet::from([entry]));
+
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for block in function.blocks.iter().skip(1) {
+            let label = block.label.as_str();
+            let mut doms = predecessors
+                .get(label)
+                .into_iter()
+                .flatten()
+                .map(|pred| dominators[pred].clone())
+                .reduce(|acc, doms| acc.intersection(&doms).copied().collect())
+                .unwrap_or_default();
+            doms.insert(label);
+            if doms != dominators[label] {
+                dominators.insert(label, doms);
+                changed = true;
+            }
+        }
+    }
+    return dominators;
+}
+
+fn result_type(
+    module: &Module,
+    function: &Function,
+    operation: &Operation,
+) -> Result<Option<Type>> {
+    let ty = match operation {
+        Operation::Copy(x) => Some(x.ty()),
+        Operation::Neg(x) => {
+            if !matches!(x.ty(), Type::Long | Type::Double) {
+                return Err(invalid(
+                    function,
+                    &format!("can't negate a {} value in {operation}", x.ty().name()),
+                ));
+            }
+            Some(x.ty())
+        }
+        // a vector is combined with a double element by element
+        Operation::Add(x, y)
+        | Operation::Sub(x, y)
+        | Operation::Mul(x, y)
+        | Operation::Div(x, y)
+        | Operation::Rem(x, y)
+        | Operation::Pow(x, y)
+            if x.ty() == Type::Vector || y.ty() == Type::Vector =>
+        {
+            let scalar = |value: &Value| matches!(value.ty(), Type::Vector | Type::Double);
+            if !scalar(x) || !scalar(y) {
+                return Err(invalid(
+                    function,
+                    &format!("vector arithmetic with a non double in {operation}"),
+                ));
+            }
+            Some(Type::Vector)
+        }
+        Operation::Add(x, y)
+        | Operation::Sub(x, y)
+        | Operation::Mul(x, y)
+        | Operation::Div(x, y)
+        | Operation::Rem(x, y)
+        | Operation::Pow(x, y) => {
+            if x.ty() != y.ty() {
+                return Err(invalid(
+                    function,
+                    &format!("operand types differ in {operation}"),
+                ));
+            }
+            if !matches!(x.ty(), Type::Long | Type::Double) {
+                return Err(invalid(
+                    function,
+                    &format!("arithmetic on {}s in {operation}", x.ty().name()),
+                ));
+            }
+            Some(x.ty())
+        }
+        Operation::Compare(comparison, x, y) => {
+            if x.ty() != y.ty() {
+                return Err(invalid(
+                    function,
+                    &format!("operand types differ in {operation}"),
+                ));
+            }
+            if matches!(x.ty(), Type::Vector | Type::Matrix) {
+                return Err(invalid(
+                    function,
+                    &format!("{}s can't be compared in {operation}", x.ty().name()),
+                ));
+            }
+            let equality = matches!(comparison, Comparison::Equal | Comparison::NotEqual);
+            if x.ty() == Type::Bool && !equality {
+                return Err(invalid(
+                    function,
+                    &format!("bools have no order in {operation}"),
+                ));
+            }
+            Some(Type::Bool)
+        }
+        Operation::Not(x) => {
+            expect_type(function, operation, x, Type::Bool)?;
+            Some(Type::Bool)
+        }
+        Operation::Phi(incoming) => {
+            let mut types = incoming.iter().map(|(_, value)| value.ty());
+            let Some(ty) = types.next() else {
+                return Err(invalid(function, "phi without incoming values"));
+            };
+            if types.any(|other| other != ty) {
+                return Err(invalid(
+                    function,
+                    &format!("incoming types differ in {operation}"),
+                ));
+            }
+            Some(ty)
+        }
+        Operation::Call(name, args) => {
+            let callee = module
+                .function(name)
+                .ok_or_else(|| invalid(function, &format!("call to unknown function ${name}")))?;
+            if callee.params.len() != args.len() {
+                return Err(invalid(
+                    function,
+                    &format!(
+                        "${name} takes {} arguments but is called with {}",
+                        callee.params.len(),
+                        args.len()
+                    ),
+                ));
+            }
+            if let Some((param, arg)) = callee.params.iter().zip(args).find(|(p, a)| p.ty != a.ty())
+            {
+                return Err(invalid(
+                    function,
+                    &format!(
+                        "${name} expects %{} to be {} but got {arg}",
+                        param.name, param.ty
+                    ),
+                ));
+            }
+            Some(callee.return_type.clone())
+        }
+        Operation::Closure(name, _) => {
+            let callee = module.function(name).ok_or_else(|| {
+                invalid(function, &format!("closure of unknown function ${name}"))
+            })?;
+            match callee.params.first() {
+                Some(env) if matches!(env.ty, Type::Function(..)) => Some(env.ty.clone()),
+                _ => {
+                    return Err(invalid(
+                        function,
+                        &format!("${name} doesn't take a closure in {operation}"),
+                    ))
+                }
+            }
+        }
+        // the function can only be reading a closure made of itself
+        Operation::Capture(env, index) => {
+            let closure = module
+                .functions
+                .iter()
+                .flat_map(|f| f.blocks.iter().flat_map(|b| &b.statements))
+                .find_map(|statement| match &statement.operation {
+                    Operation::Closure(name, captures) if *name == function.name => Some(captures),
+                    _ => None,
+                });
+            let param = function.params.first().map(|p| Value::Temp(p.clone()));
+            match closure.and_then(|captures| captures.get(*index)) {
+                Some(capture) if param.as_ref() == Some(env) => Some(capture.ty()),
+                _ => {
+                    return Err(invalid(
+                        function,
+                        &format!("{operation} doesn't read a capture of the closure"),
+                    ))
+                }
+            }
+        }
+        Operation::CallClosure(closure, args) => {
+            let Type::Function(params, result) = closure.ty() else {
+                return Err(invalid(
+                    function,
+                    &format!("{operation} calls a {} value", closure.ty()),
+                ));
+            };
+            let types: Vec<Type> = args.iter().map(|arg| arg.ty()).collect();
+            if types != params {
+                return Err(invalid(
+                    function,
+                    &format!("arguments don't match the closure's parameters in {operation}"),
+                ));
+            }
+            Some(*result)
+        }
+        Operation::Map(v, closure) => {
+            expect_type(function, operation, v, Type::Vector)?;
+            let mapping = Type::Function(vec![Type::Double], Box::new(Type::Double));
+            expect_type(function, operation, closure, mapping)?;
+            Some(Type::Vector)
+        }
+        Operation::Vector(elements) => {
+            for element in elements {
+                expect_type(function, operation, element, Type::Double)?;
+            }
+            Some(Type::Vector)
+        }
+        Operation::Matrix(rows) => {
+            for row in rows {
+                expect_type(function, operation, row, Type::Vector)?;
+            }
+            Some(Type::Matrix)
+        }
+        Operation::Index(x, index) => {
+            expect_type(function, operation, index, Type::Long)?;
+            match x.ty() {
+                Type::Matrix => Some(Type::Vector),
+                _ => {
+                    expect_type(function, operation, x, Type::Vector)?;
+                    Some(Type::Double)
+                }
+            }
+        }
+        Operation::MatMul(x, y) => match (x.ty(), y.ty()) {
+            (Type::Matrix, Type::Matrix) => Some(Type::Matrix),
+            (Type::Matrix, Type::Vector) | (Type::Vector, Type::Matrix) => Some(Type::Vector),
+            (Type::Vector, Type::Vector) => Some(Type::Double),
+            _ => {
+                return Err(invalid(
+                    function,
+                    &format!("matmul of a non matrix or vector in {operation}"),
+                ))
+            }
+        },
+        Operation::Transpose(x) | Operation::Inverse(x) => {
+            expect_type(function, operation, x, Type::Matrix)?;
+            Some(Type::Matrix)
+        }
+        Operation::Det(x) => {
+            expect_type(function, operation, x, Type::Matrix)?;
+            Some(Type::Double)
+        }
+        Operation::Solve(a, b) => {
+            expect_type(function, operation, a, Type::Matrix)?;
+            expect_type(function, operation, b, Type::Vector)?;
+            Some(Type::Vector)
+        }
+        Operation::Length(x) => {
+            expect_type(function, operation, x, Type::Vector)?;
+            Some(Type::Long)
+        }
+        Operation::Reduce(_, x) => {
+            expect_type(function, operation, x, Type::Vector)?;
+            Some(Type::Double)
+        }
+        Operation::Math(_, x) => {
+            expect_type(function, operation, x, Type::Double)?;
+            Some(Type::Double)
+        }
+        Operation::IntToFloat(x) => {
+            expect_type(function, operation, x, Type::Long)?;
+            Some(Type::Double)
+        }
+        Operation::FloatToInt(x) => {
+            expect_type(function, operation, x, Type::Double)?;
+            Some(Type::Long)
+        }
+        Operation::Print(x, _) => {
+            if !matches!(
+                x.ty(),
+                Type::Long | Type::Double | Type::Bool | Type::Vector | Type::Matrix
+            ) {
+                return Err(invalid(
+                    function,
+                    &format!("can't print a {} value in {operation}", x.ty()),
+                ));
+            }
+            None
+        }
+        Operation::SetArgs(argc, argv) => {
+            if function.name != "main" {
+                return Err(invalid(function, &format!("{operation} outside of $main")));
+            }
+            expect_type(function, operation, argc, Type::Word)?;
+            expect_type(function, operation, argv, Type::Long)?;
+            None
+        }
+        Operation::Arg(n) => {
+            expect_type(function, operation, n, Type::Long)?;
+            Some(Type::Double)
+        }
+        Operation::Input => Some(Type::Double),
+        Operation::Load(name) => Some(global(module, function, name)?.ty.clone()),
+        Operation::Store(name, x) => {
+            let global = global(module, function, name)?;
+            if global.ty != x.ty() {
+                return Err(invalid(
+                    function,
+                    &format!("${name} is {} but stored a {} value", global.ty, x.ty()),
+                ));
+            }
+            None
+        }
+    };
+    return Ok(ty);
+}
+
+fn expect_type(function: &Function, operation: &Operation, x: &Value, ty: Type) -> Result<()> {
+    if x.ty() != ty {
+        return Err(invalid(
+            function,
+            &format!("{operation} expects a {ty} operand but got a {}", x.ty()),
+        ));
+    }
+    return Ok(());
+}
+
+fn global<'a>(module: &'a Module, function: &Function, name: &str) -> Result<&'a Global> {
+    module
+        .global(name)
+        .ok_or_else(|| invalid(function, &format!("unknown global ${name}")))
+}
+
+fn check_defined(function: &Function, defined: &HashSet<&str>, value: &Value) -> Result<()> {
+    match value {
+        Value::Temp(temp) if !defined.contains(temp.name.as_str()) => Err(invalid(
+            function,
+            &format!("%{} is used before it is defined", temp.name),
+        )),
+        _ => Ok(()),
+    }
+}
+
+fn check_label(function: &Function, labels: &HashSet<&str>, label: &str) -> Result<()> {
+    if labels.contains(label) {
+        Ok(())
+    } else {
+        Err(invalid(
+            function,
+            &format!("jump to unknown block @{label}"),
+        ))
+    }
+}
+
+fn invalid(function: &Function, reason: &str) -> anyhow::Error {
+    CompileError::InvalidIr(function.name.clone(), reason.to_string()).into()
+}
+
+impl fmt::Display for Module {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for global in &self.globals {
+            writeln!(f, "global ${}: {}", global.name, global.ty)?;
+        }
+        if !self.globals.is_empty() {
+            writeln!(f)?;
+        }
+        let functions = self
+            .functions
+            .iter()
+            .map(|func| func.to_string())
+            .collect::<Vec<String>>()
+            .join("\n");
+        write!(f, "{functions}")
+    }
+}
+
+impl fmt::Display for Function {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let export = if self.export { "export " } else { "" };
+        let params = self
+            .params
+            .iter()
+            .map(|param| format!("%{}: {}", param.name, param.ty))
+            .collect::<Vec<String>>()
+            .join(", ");
+        writeln!(
+            f,
+            "{export}fn ${}({params}) -> {} {{",
+            self.name, self.return_type
+        )?;
+        for block in &self.blocks {
+            write!(f, "{block}")?;
+        }
+        writeln!(f, "}}")
+    }
+}
+
+impl fmt::Display for Block {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "@{}", self.label)?;
+        for statement in &self.statements {
+            writeln!(f, "\t{statement}")?;
+        }
+        writeln!(f, "\t{}", self.terminator)
+    }
+}
+
+impl fmt::Display for Statement {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.dest {
+            Some(dest) => write!(f, "%{}: {} = {}", dest.name, dest.ty, self.operation),
+            None => write!(f, "{}", self.operation),
+        }
+    }
+}
+
+impl fmt::Display for Operation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Operation::Copy(x) => write!(f, "copy {x}"),
+            Operation::Neg(x) => write!(f, "neg {x}"),
+            Operation::Add(x, y) => write!(f, "add {x}, {y}"),
+            Operation::Sub(x, y) => write!(f, "sub {x}, {y}"),
+            Operation::Mul(x, y) => write!(f, "mul {x}, {y}"),
+            Operation::Div(x, y) => write!(f, "div {x}, {y}"),
+            Operation::Rem(x, y) => write!(f, "rem {x}, {y}"),
+            Operation::Pow(x, y) => write!(f, "pow {x}, {y}"),
+            Operation::IntToFloat(x) => write!(f, "itof {x}"),
+            Operation::FloatToInt(x) => write!(f, "ftoi {x}"),
+            Operation::Compare(comparison, x, y) => write!(f, "{comparison} {x}, {y}"),
+            Operation::Not(x) => write!(f, "not {x}"),
+            Operation::Phi(incoming) => {
+                let incoming = incoming
+                    .iter()
+                    .map(|(label, value)| format!("@{label} {value}"))
+                    .collect::<Vec<String>>()
+                    .join(", ");
+                write!(f, "phi {incoming}")
+            }
+            Operation::Vector(elements) => {
+                let elements = elements
+                    .iter()
+                    .map(|element| element.to_string())
+                    .collect::<Vec<String>>()
+                    .join(", ");
+                write!(f, "vector [{elements}]")
+            }
+            Operation::Matrix(rows) => {
+                let rows = rows
+                    .iter()
+                    .map(|row| row.to_string())
+                    .collect::<Vec<String>>()
+                    .join(", ");
+                write!(f, "matrix [{rows}]")
+            }
+            Operation::Index(x, index) => write!(f, "index {x}, {index}"),
+            Operation::MatMul(x, y) => write!(f, "matmul {x}, {y}"),
+            Operation::Transpose(x) => write!(f, "transpose {x}"),
+            Operation::Det(x) => write!(f, "det {x}"),
+            Operation::Inverse(x) => write!(f, "inv {x}"),
+            Operation::Solve(a, b) => write!(f, "solve {a}, {b}"),
+            Operation::Length(x) => write!(f, "len {x}"),
+            Operation::Reduce(reduction, x) => write!(f, "{reduction} {x}"),
+            Operation::Math(function, x) => write!(f, "{function} {x}"),
+            Operation::Call(name, args) => {
+                let args = args
+                    .iter()
+                    .map(|arg| arg.to_string())
+                    .collect::<Vec<String>>()
+                    .join(", ");
+                write!(f, "call ${name}({args})")
+            }
+            Operation::Closure(name, captures) => {
+                let captures = captures
+                    .iter()
+                    .map(|capture| capture.to_string())
+                    .collect::<Vec<String>>()
+                    .join(", ");
+                write!(f, "closure ${name}({captures})")
+            }
+            Operation::Capture(env, index) => write!(f, "capture {env}, {index}"),
+            Operation::CallClosure(closure, args) => {
+                let args = args
+                    .iter()
+                    .map(|arg| arg.to_string())
+                    .collect::<Vec<String>>()
+                    .join(", ");
+                write!(f, "call {closure}({args})")
+            }
+            Operation::Map(v, closure) => write!(f, "map {v}, {closure}"),
+            Operation::Print(x, format) => write!(f, "print {x}, {format}"),
+            Operation::SetArgs(argc, argv) => write!(f, "setargs {argc}, {argv}"),
+            Operation::Arg(n) => write!(f, "arg {n}"),
+            Operation::Input => write!(f, "input"),
+            Operation::Load(name) => write!(f, "load ${name}"),
+            Operation::Store(name, x) => write!(f, "store ${name}, {x}"),
+        }
+    }
+}
+
+impl fmt::Display for Terminator {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Terminator::Return(Some(value)) => write!(f, "ret {value}"),
+            Terminator::Return(None) => write!(f, "ret"),
+            Terminator::Jump(label) => write!(f, "jmp @{label}"),
+            Terminator::Branch(cond, then, otherwise) => {
+                write!(f, "br {cond}, @{then}, @{otherwise}")
+            }
+        }
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::Temp(temp) => write!(f, "%{}", temp.name),
+            Value::Double(x) => write!(f, "{x:?}"),
+            Value::Long(x) => write!(f, "{x}"),
+            Value::Word(x) => write!(f, "{x}"),
+            Value::Bool(x) => write!(f, "{x}"),
+        }
+    }
+}
+
+impl fmt::Display for Comparison {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}",
+            match self {
+                Comparison::Equal => "eq",
+                Comparison::NotEqual => "ne",
+                Comparison::Less => "lt",
+                Comparison::LessEqual => "le",
+                Comparison::Greater => "gt",
+                Comparison::GreaterEqual => "ge",
+            }
+        )
+    }
+}
+
+impl fmt::Display for MathFunction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}",
+            match self {
+                MathFunction::Sqrt => "sqrt",
+                MathFunction::Exp => "exp",
+                MathFunction::Ln => "ln",
+                MathFunction::Sin => "sin",
+                MathFunction::Cos => "cos",
+            }
+        )
+    }
+}
+
+impl fmt::Display for Reduction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}",
+            match self {
+                Reduction::Sum => "sum",
+                Reduction::Mean => "mean",
+                Reduction::Min => "min",
+                Reduction::Max => "max",
+            }
+        )
+    }
+}
+
+impl fmt::Display for Type {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}",
+            match self {
+                Type::Word => "w",
+                Type::Long => "l",
+                Type::Double => "d",
+                Type::Bool => "w",
+                Type::Vector | Type::Matrix | Type::Function(..) => "l",
+            }
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp(name: &str) -> Temp {
+        Temp::new(name.to_string(), Type::Double)
+    }
+
+    fn var(name: &str) -> Value {
+        Value::Temp(temp(name))
+    }
+
+    fn function(name: &str, params: Vec<Temp>, blocks: Vec<Block>) -> Function {
+        Function {
+            blocks,
+            ..Function::new(name.to_string(), params, Type::Double)
+        }
+    }
+
+    fn block(label: &str, statements: Vec<Statement>, terminator: Terminator) -> Block {
+        Block {
+            label: label.to_string(),
+            statements,
+            terminator,
+        }
+    }
+
+    fn verify_one(function: Function) -> Result<()> {
+        verify(&Module {
+            functions: vec![function],
+            ..Module::default()
+        })
+    }
+
+    #[test]
+    fn accepts_well_formed_function() {
+        let f = function(
+            "f",
+            vec![temp("x")],
+            vec![block(
+                "start",
+                vec![Statement::assign(
+                    temp("y"),
+                    Operation::Add(var("x"), Value::Double(1.0)),
+                )],
+                Terminator::Return(Some(var("y"))),
+            )],
+        );
+        assert!(verify_one(f).is_ok());
+    }
+
+    #[test]
+    fn rejects_use_before_definition() {
+        let f = function(
+            "f",
+            vec![],
+            vec![block(
+                "start",
+                vec![
+                    Statement::assign(temp("y"), Operation::Add(var("z"), Value::Double(1.0))),
+                    Statement::assign(temp("z"), Operation::Copy(Value::Double(2.0))),
+                ],
+                Terminator::Return(Some(var("y"))),
+            )],
+        );
+        assert!(verify_one(f).is_err());
+    }
+
+    #[test]
+    fn rejects_double_assignment() {
+        let f = function(
+            "f",
+            vec![temp("x")],
+            vec![block(
+                "start",
+                vec![Statement::assign(
+                    temp("x"),
+                    Operation::Copy(Value::Double(2.0)),
+                )],
+                Terminator::Return(Some(var("x"))),
+            )],
+        );
+        assert!(verify_one(f).is_err());
+    }
+
+    #[test]
+    fn rejects_undefined_return_value() {
+        let f = function(
+            "f",
+            vec![],
+            vec![block(
+                "start",
+                vec![],
+                Terminator::Return(Some(var("missing"))),
+            )],
+        );
+        assert!(verify_one(f).is_err());
+    }
+
+    #[test]
+    fn rejects_value_from_non_dominating_block() {
+        let cond = Value::Word(1);
+        let f = function(
+            "f",
+            vec![],
+            vec![
+                block(
+                    "start",
+                    vec![],
+                    Terminator::Branch(cond, "then".to_string(), "join".to_string()),
+                ),
+                block(
+                    "then",
+                    vec![Statement::assign(
+                        temp("y"),
+                        Operation::Copy(Value::Double(1.0)),
+                    )],
+                    Terminator::Jump("join".to_string()),
+                ),
+                block("join", vec![], Terminator::Return(Some(var("y")))),
+            ],
+        );
+        assert!(verify_one(f).is_err());
+    }
+
+    #[test]
+    fn rejects_call_arity_mismatch() {
+        let callee = function(
+            "g",
+            vec![temp("a"), temp("b")],
+            vec![block("start", vec![], Terminator::Return(Some(var("a"))))],
+        );
+        let caller = function(
+            "f",
+            vec![],
+            vec![block(
+                "start",
+                vec![Statement::assign(
+                    temp("y"),
+                    Operation::Call("g".to_string(), vec![Value::Double(1.0)]),
+                )],
+                Terminator::Return(Some(var("y"))),
+            )],
+        );
+        let module = Module {
+            functions: vec![callee, caller],
+            ..Module::default()
+        };
+        assert!(verify(&module).is_err());
+    }
+
+    #[test]
+    fn checks_globals() {
+        let f = function(
+            "f",
+            vec![],
+            vec![block(
+                "start",
+                vec![
+                    Statement::assign(temp("x"), Operation::Load("rate".to_string())),
+                    Statement::effect(Operation::Store("rate".to_string(), var("x"))),
+                ],
+                Terminator::Return(Some(var("x"))),
+            )],
+        );
+        let mut module = Module {
+            functions: vec![f],
+            globals: vec![Global {
+                name: "rate".to_string(),
+                ty: Type::Double,
+            }],
+        };
+        assert!(verify(&module).is_ok());
+        module.globals[0].ty = Type::Word;
+        assert!(verify(&module).is_err());
+        module.globals.clear();
+        assert!(verify(&module).is_err());
+    }
+
+    #[test]
+    fn checks_conversion_types() {
+        let convert = |operation: Operation| {
+            function(
+                "f",
+                vec![],
+                vec![block(
+                    "start",
+                    vec![Statement::assign(temp("x"), operation)],
+                    Terminator::Return(Some(var("x"))),
+                )],
+            )
+        };
+        assert!(verify_one(convert(Operation::IntToFloat(Value::Long(3)))).is_ok());
+        assert!(verify_one(convert(Operation::IntToFloat(Value::Double(3.0)))).is_err());
+        assert!(verify_one(convert(Operation::Add(Value::Long(1), Value::Double(2.0)))).is_err());
+    }
+
+    #[test]
+    fn checks_phi_predecessors() {
+        let join = |incoming: Vec<(&str, Value)>| {
+            let incoming = incoming
+                .into_iter()
+                .map(|(label, value)| (label.to_string(), value))
+                .collect();
+            function(
+                "f",
+                vec![],
+                vec![
+                    block(
+                        "start",
+                        vec![],
+                        Terminator::Branch(
+                            Value::Bool(true),
+                            "then".to_string(),
+                            "join".to_string(),
+                        ),
+                    ),
+                    block(
+                        "then",
+                        vec![Statement::assign(
+                            temp("y"),
+                            Operation::Copy(Value::Double(1.0)),
+                        )],
+                        Terminator::Jump("join".to_string()),
+                    ),
+                    block(
+                        "join",
+                        vec![Statement::assign(temp("x"), Operation::Phi(incoming))],
+                        Terminator::Return(Some(var("x"))),
+                    ),
+                ],
+            )
+        };
+        assert!(verify_one(join(vec![
+            ("start", Value::Double(0.0)),
+            ("then", var("y"))
+        ]))
+        .is_ok());
+        assert!(verify_one(join(vec![("then", var("y"))])).is_err());
+        assert!(verify_one(join(vec![("start", var("y")), ("then", var("y"))])).is_err());
+        assert!(verify_one(join(vec![
+            ("start", Value::Bool(false)),
+            ("then", var("y"))
+        ]))
+        .is_err());
+    }
+
+    #[test]
+    fn checks_vector_operations() {
+        let vector = Temp::new("v".to_string(), Type::Vector);
+        let build = Statement::assign(
+            vector.clone(),
+            Operation::Vector(vec![Value::Double(1.0), Value::Double(2.0)]),
+        );
+        let check = |operation: Operation, ty: Type| {
+            let f = Function {
+                blocks: vec![block(
+                    "start",
+                    vec![
+                        build.clone(),
+                        Statement::assign(Temp::new("x".to_string(), ty.clone()), operation),
+                    ],
+                    Terminator::Return(Some(Value::Temp(Temp::new("x".to_string(), ty.clone())))),
+                )],
+                ..Function::new("f".to_string(), vec![], ty)
+            };
+            verify_one(f)
+        };
+        let v = Value::Temp(vector.clone());
+        assert!(check(Operation::Mul(v.clone(), Value::Double(2.0)), Type::Vector).is_ok());
+        assert!(check(Operation::Add(v.clone(), v.clone()), Type::Vector).is_ok());
+        assert!(check(Operation::Add(v.clone(), Value::Long(2)), Type::Vector).is_err());
+        assert!(check(Operation::Index(v.clone(), Value::Long(0)), Type::Double).is_ok());
+        assert!(check(
+            Operation::Index(v.clone(), Value::Double(0.0)),
+            Type::Double
+        )
+        .is_err());
+        assert!(check(Operation::Length(v.clone()), Type::Long).is_ok());
+        assert!(check(Operation::Reduce(Reduction::Sum, v.clone()), Type::Double).is_ok());
+        assert!(check(Operation::Math(MathFunction::Sin, v.clone()), Type::Double).is_err());
+        assert!(check(Operation::Vector(vec![Value::Long(1)]), Type::Vector).is_err());
+    }
+}
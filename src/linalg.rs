@@ -0,0 +1,137 @@
+//! Dense linear algebra on row-major matrices, used by the interpreter. The runtime linked into
+//! compiled programs does the same steps in the same order so both give the same results.
+
+/// The product of a `rows` x `inner` matrix and an `inner` x `cols` matrix.
+pub fn multiply(x: &[f64], y: &[f64], rows: usize, inner: usize, cols: usize) -> Vec<f64> {
+    let mut product = vec![0.0; rows * cols];
+    for i in 0..rows {
+        for j in 0..cols {
+            let mut sum = 0.0;
+            for k in 0..inner {
+                sum += x[i * inner + k] * y[k * cols + j];
+            }
+            product[i * cols + j] = sum;
+        }
+    }
+    return product;
+}
+
+pub fn transpose(x: &[f64], rows: usize, cols: usize) -> Vec<f64> {
+    let mut transposed = vec![0.0; rows * cols];
+    for i in 0..rows {
+        for j in 0..cols {
+            transposed[j * rows + i] = x[i * cols + j];
+        }
+    }
+    return transposed;
+}
+
+/// The determinant of an `n` x `n` matrix by gaussian elimination with partial pivoting.
+pub fn determinant(x: &[f64], n: usize) -> f64 {
+    let mut a = x.to_vec();
+    let mut det = 1.0;
+    for k in 0..n {
+        let p = pivot(&a, n, k);
+        if a[p * n + k] == 0.0 {
+            return 0.0;
+        }
+        if p != k {
+            swap_rows(&mut a, n, p, k);
+            det = -det;
+        }
+        det *= a[k * n + k];
+        for i in k + 1..n {
+            let factor = a[i * n + k] / a[k * n + k];
+            for j in k..n {
+                a[i * n + j] -= factor * a[k * n + j];
+            }
+        }
+    }
+    return det;
+}
+
+/// Solves `a x = b` for every column of the `n` x `m` matrix `b`, giving the `n` x `m` matrix
+/// of solutions or `None` when `a` is singular.
+pub fn solve(a: &[f64], b: &[f64], n: usize, m: usize) -> Option<Vec<f64>> {
+    let (mut a, mut x) = (a.to_vec(), b.to_vec());
+    for k in 0..n {
+        let p = pivot(&a, n, k);
+        if a[p * n + k] == 0.0 {
+            return None;
+        }
+        swap_rows(&mut a, n, p, k);
+        swap_rows(&mut x, m, p, k);
+        for i in k + 1..n {
+            let factor = a[i * n + k] / a[k * n + k];
+            for j in k..n {
+                a[i * n + j] -= factor * a[k * n + j];
+            }
+            for j in 0..m {
+                x[i * m + j] -= factor * x[k * m + j];
+            }
+        }
+    }
+    for i in (0..n).rev() {
+        for j in 0..m {
+            let mut sum = x[i * m + j];
+            for k in i + 1..n {
+                sum -= a[i * n + k] * x[k * m + j];
+            }
+            x[i * m + j] = sum / a[i * n + i];
+        }
+    }
+    return Some(x);
+}
+
+pub fn identity(n: usize) -> Vec<f64> {
+    let mut identity = vec![0.0; n * n];
+    for i in 0..n {
+        identity[i * n + i] = 1.0;
+    }
+    return identity;
+}
+
+/// The row at or below `k` with the largest magnitude in column `k`.
+fn pivot(a: &[f64], n: usize, k: usize) -> usize {
+    let mut p = k;
+    for i in k + 1..n {
+        if a[i * n + k].abs() > a[p * n + k].abs() {
+            p = i;
+        }
+    }
+    return p;
+}
+
+fn swap_rows(a: &mut [f64], cols: usize, i: usize, j: usize) {
+    for c in 0..cols {
+        a.swap(i * cols + c, j * cols + c);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn multiplies_and_transposes() {
+        let x = [1.0, 2.0, 3.0, 4.0, 5.0, 6.0];
+        assert_eq!(transpose(&x, 2, 3), vec![1.0, 4.0, 2.0, 5.0, 3.0, 6.0]);
+        assert_eq!(
+            multiply(&x, &transpose(&x, 2, 3), 2, 3, 2),
+            vec![14.0, 32.0, 32.0, 77.0]
+        );
+    }
+
+    #[test]
+    fn solves_systems() {
+        let a = [0.0, 2.0, 1.0, 1.0];
+        assert_eq!(determinant(&a, 2), -2.0);
+        assert_eq!(solve(&a, &[4.0, 3.0], 2, 1), Some(vec![1.0, 2.0]));
+        assert_eq!(
+            solve(&a, &identity(2), 2, 2),
+            Some(vec![-0.5, 1.0, 0.5, 0.0])
+        );
+        assert_eq!(determinant(&[1.0, 2.0, 2.0, 4.0], 2), 0.0);
+        assert_eq!(solve(&[1.0, 2.0, 2.0, 4.0], &[1.0, 1.0], 2, 1), None);
+    }
+}
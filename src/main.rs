@@ -2,45 +2,161 @@
 #![feature(let_chains)]
 #![feature(slice_split_once)]
 
-use clap::{Arg, Command, Parser};
+use anyhow::{Context, Result};
+use clap::{Parser, Subcommand, ValueEnum};
+use std::{
+    env, fs,
+    io::{self, Write},
+    path::PathBuf,
+    process,
+};
 
+mod backend;
 mod compiler;
 mod error;
+mod explain;
+mod format;
+mod imports;
+mod infer;
+mod interp;
+mod ir;
+mod linalg;
 mod parser;
 
-fn main() {
-    let cmd = Command::new("numerus")
-        .arg(
-            Arg::new("output")
-                .short('o')
-                .help("path of the file to output program")
-                .num_args(1),
-        )
-        .arg(
-            Arg::new("ssa")
-                .help("")
-                .num_args(0),
-        )
-        .arg(
-            Arg::new("target")
-                .short('t')
-                .help("compile for a target among:\n\tamd64_sysv (default), amd64_apple, arm64, arm64_apple, rv64")
-                .num_args(1)
-        );
+use backend::Backend;
+use error::{Diagnostic, Severity};
+use format::PrintFormat;
+
+fn main() -> Result<()> {
+    let args = Args::parse();
+    if let Some(Command::Explain { code }) = &args.command {
+        let Some(explanation) = explain::explain(code) else {
+            eprintln!("error: {code} is not an error code");
+            process::exit(1);
+        };
+        println!("{explanation}");
+        return Ok(());
+    }
+    let path = args.source.as_deref().expect("clap requires a source without a subcommand");
+    let source = fs::read_to_string(path).context(format!("failed to read {path}"))?;
+    // directories given with `-I` are searched before the ones in NUMERUS_PATH
+    let mut include = args.include.clone();
+    if let Some(paths) = env::var_os("NUMERUS_PATH") {
+        include.extend(env::split_paths(&paths));
+    }
+    let parsed = imports::resolve(parser::parse(&source), path.as_ref(), &include);
+    let warnings = compiler::lint(&parsed);
+    let module = match compiler::compile(parsed, args.print_format) {
+        Ok(module) => {
+            report(path, warnings, args.error_format);
+            module
+        }
+        Err(errors) => {
+            let diagnostics = warnings.into_iter().chain(errors).collect();
+            report(path, diagnostics, args.error_format);
+            process::exit(1);
+        }
+    };
+    if args.verify_ir || cfg!(debug_assertions) {
+        ir::verify(&module)?;
+    }
+
+    if args.run {
+        let (input, out) = (&mut io::stdin().lock(), &mut io::stdout().lock());
+        if let Err(error) = interp::run(&module, &args.program_args, input, out) {
+            out.flush()?;
+            eprintln!("{}", interp::runtime_error(&error));
+            process::exit(1);
+        }
+    } else if args.ir {
+        fs::write(&args.output, module.to_string())?;
+    } else if args.ssa {
+        fs::write(&args.output, backend::qbe::emit(&module))?;
+    } else if args.c {
+        fs::write(&args.output, backend::c::emit(&module))?;
+    } else {
+        backend::build_executable(&module, args.backend, &args.target, &args.output)?;
+    }
+    return Ok(());
+}
+
+/// Prints every diagnostic in source order, the compiled file's first and then each imported
+/// file's, followed by how many errors there were when they are for a person to read.
+fn report(path: &str, mut diagnostics: Vec<Diagnostic>, format: ErrorFormat) {
+    diagnostics.sort_by_key(|d| (d.file.clone(), d.span.line, d.span.column));
+    for diagnostic in &diagnostics {
+        let file = diagnostic.file.as_deref().unwrap_or(path);
+        match format {
+            ErrorFormat::Human => eprintln!("{}", diagnostic.human(file)),
+            ErrorFormat::Json => eprintln!("{}", diagnostic.json(file)),
+        }
+    }
+    let count = diagnostics
+        .iter()
+        .filter(|d| d.severity == Severity::Error)
+        .count();
+    if format == ErrorFormat::Human && count > 0 {
+        let plural = if count == 1 { "" } else { "s" };
+        eprintln!("error: could not compile {path} due to {count} error{plural}");
+        eprintln!("for more information about an error, try `numerus explain <code>`");
+    }
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// print a longer description of an error code, with an example of it and its fix
+    Explain { code: String },
+}
+
+#[derive(ValueEnum, Clone, Copy, PartialEq, Debug)]
+enum ErrorFormat {
+    Human,
+    Json,
 }
 
 #[derive(Parser, Debug)]
-#[command(version, about, long_about = None)]
+#[command(name = "numerus", version, about, long_about = None)]
+#[command(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
 struct Args {
+    #[command(subcommand)]
+    command: Option<Command>,
+    /// path of the source code to compile
+    #[arg(required = true)]
+    source: Option<String>,
+    /// directory to look for imported files in, before the ones listed in NUMERUS_PATH
+    #[arg(short = 'I', long = "include")]
+    include: Vec<PathBuf>,
     /// path of the file to output to
     #[arg(short, long, default_value = "a.out")]
     output: String,
-    /// source code to compile
-    #[arg(short, long)]
+    /// run the program with the interpreter instead of building it
+    #[arg(long)]
+    run: bool,
+    /// how expression results are printed: fixed:N, sci:N, general, hex-float or exact
+    #[arg(long, default_value = "fixed:4")]
+    print_format: PrintFormat,
     /// output in qbe ssa (single static assignment)
     #[arg(long)]
     ssa: bool,
+    /// output numerus' own ir, the form every backend is lowered from
+    #[arg(long, conflicts_with = "ssa")]
+    ir: bool,
+    /// output c source code
+    #[arg(long, conflicts_with_all = ["ssa", "ir"])]
+    c: bool,
+    /// how errors and warnings are written to stderr, json prints one object per line
+    #[arg(long, value_enum, default_value = "human")]
+    error_format: ErrorFormat,
+    /// check the generated ir for malformed functions, always on in debug builds
+    #[arg(long)]
+    verify_ir: bool,
+    /// backend used to build the executable
+    #[arg(short, long, value_enum, default_value = "qbe")]
+    backend: Backend,
     /// compile for a target among:\n\tamd64_sysv (default), amd64_apple, arm64, arm64_apple, rv64
     #[arg(short, long, default_value = "amd64_sysv")]
     target: String,
+    /// arguments given to the program when it is run, read with `arg(n)`
+    #[arg(last = true, requires = "run")]
+    program_args: Vec<String>,
 }
@@ -1,59 +1,577 @@
-use crate::error::CompileError;
-use anyhow::{Context, Result};
+use crate::{
+    error::{CompileError, Diagnostic},
+    format::PrintFormat,
+    ir::Type,
+};
+use anyhow::Result;
+use std::{collections::HashMap, fmt};
 
 #[derive(PartialEq, Debug, Clone)]
 pub enum Statement {
     Declaration(Declaration),
     Expression(Vec<ParseToken>),
+    /// `#fmt kind [precision]`, changes how the expressions after it are printed
+    Format(PrintFormat),
+    /// `import "file.nms"` or `from "file.nms" import name, ...`, replaced by the declarations
+    /// of the file before compiling
+    Import(Import),
 }
 
 #[derive(PartialEq, Debug, Clone)]
+pub struct Import {
+    /// the file as written, relative to the importing one or the include path, or the module
+    /// like `std.stats` when `std` is set
+    pub path: String,
+    /// whether `path` is a module of the standard library bundled with the compiler
+    pub std: bool,
+    /// the names brought in without their namespace, `None` imports the whole file under it
+    pub names: Option<Vec<String>>,
+}
+
+#[derive(PartialEq, Debug, Clone, Default)]
 pub struct Declaration {
-    name: String,
-    args: Vec<String>,
-    body: Vec<ParseToken>,
+    pub name: String,
+    /// whether the name is followed by parameters, `f() = 1` declares a function without
+    /// parameters where `f = 1` declares a variable
+    pub function: bool,
+    pub args: Vec<String>,
+    /// the types written after parameters like `x: int`, by parameter name
+    pub annotations: HashMap<String, Type>,
+    /// the type written after the parameters like `f(x): float`
+    pub return_type: Option<Type>,
+    pub body: Vec<ParseToken>,
 }
 
 
 impl Declaration {}
 
-pub fn parse(source: &str) -> Result<Vec<Statement>> {
-    let mut statements: Vec<Statement> = vec![];
-    for (line_num, line) in source.split('\n').enumerate() {
-        let tokens = tokenize(line).context(format!("on line {line_num}"))?;
-        if tokens.contains(&ParseToken::Assign) {
-            let (id, expr) = tokens
-                .split_once(|t| t == &ParseToken::Assign)
-                .expect("there must be at least one ocurrance of '=' in tokens");
-            let (name, args) = split_declaration(id)?;
-            let body = infix_to_rpn(expr.to_vec()).context(format!("on line {line_num}"))?;
-            statements.push(Statement::Declaration(Declaration { name, args, body }));
-        } else {
-            let rpn = infix_to_rpn(tokens).context(format!("on line {line_num}"))?;
-            statements.push(Statement::Expression(rpn));
+/// The statements of a program that parsed, with everything that went wrong in the ones that
+/// didn't.
+#[derive(Debug, Default)]
+pub struct Parsed {
+    pub statements: Vec<Statement>,
+    /// where each of `statements` came from
+    pub spans: Vec<Span>,
+    pub diagnostics: Vec<Diagnostic>,
+    /// names assigned by statements that failed to parse
+    pub failed_declarations: Vec<String>,
+    /// names read by statements that failed to parse, they still count as used
+    pub failed_uses: Vec<String>,
+    /// the imported file each of `statements` came from, `None` for the file being compiled
+    pub files: Vec<Option<String>>,
+}
+
+impl Parsed {
+    fn finish_statement(&mut self, tokens: Vec<Token>) {
+        let declared = declared_name(&tokens);
+        let reads = read_names(&tokens);
+        match parse_statement(tokens) {
+            Ok(Some((statement, span))) => {
+                self.statements.push(statement);
+                self.spans.push(span);
+                self.files.push(None);
+            }
+            Ok(None) => {}
+            Err(diagnostic) => {
+                self.diagnostics.push(diagnostic);
+                self.failed_declarations.extend(declared);
+                self.failed_uses.extend(reads);
+            }
         }
     }
-    return Ok(statements);
+
+    /// Drops a statement after an error in one of its lines.
+    fn abandon_statement(&mut self, tokens: Vec<Token>, diagnostic: Diagnostic) {
+        self.diagnostics.push(diagnostic);
+        self.failed_declarations.extend(declared_name(&tokens));
+        self.failed_uses.extend(read_names(&tokens));
+    }
 }
 
-fn split_declaration(declaration: &[ParseToken]) -> Result<(String, Vec<String>)> {
-    let name = if let Some(ParseToken::Identifier(n)) = declaration.get(0) {
-        n
+/// The names a statement reads, the ones after its `=` when it is an assignment.
+fn read_names(tokens: &[Token]) -> Vec<String> {
+    let start = assignments(tokens).first().map_or(0, |assign| assign + 1);
+    return tokens[start..]
+        .iter()
+        .filter_map(|token| match &token.kind {
+            ParseToken::Identifier(name) => Some(name.clone()),
+            _ => None,
+        })
+        .collect();
+}
+
+/// The name a statement assigns to, if it is an assignment.
+fn declared_name(tokens: &[Token]) -> Option<String> {
+    match tokens.first() {
+        Some(Token {
+            kind: ParseToken::Identifier(name),
+            ..
+        }) if !assignments(tokens).is_empty() => Some(name.clone()),
+        _ => None,
+    }
+}
+
+/// Where the `=` of a statement's assignments are, leaving out the one of every `let`.
+fn assignments(tokens: &[Token]) -> Vec<usize> {
+    let mut lets = 0;
+    let mut positions = vec![];
+    for (index, token) in tokens.iter().enumerate() {
+        match token.kind {
+            ParseToken::Let => lets += 1,
+            ParseToken::Assign if lets > 0 => lets -= 1,
+            ParseToken::Assign => positions.push(index),
+            _ => {}
+        }
+    }
+    return positions;
+}
+
+/// Parses a whole program, skipping to the next statement whenever one has an error so every
+/// error in the source is reported at once. Statements end at a newline unless a parenthesis,
+/// bracket or brace is still open or the line ends with `\`, and `;` separates several statements on one line.
+/// Inside a block a newline separates its statements like `;` does.
+/// A blank line ends a statement so an unclosed parenthesis can't swallow the rest of the
+/// file, unless the innermost open group is a block, which can hold blank lines. Blank lines
+/// and lines holding only a `#` or `//` comment are skipped, line numbers start at 1.
+pub fn parse(source: &str) -> Parsed {
+    let mut parsed = Parsed::default();
+    let mut pending: Vec<Token> = vec![];
+    // the parenthesis, brackets and braces still open
+    let mut groups: Vec<ParseToken> = vec![];
+    for (index, line) in source.split('\n').enumerate() {
+        let line_num = index + 1;
+        if line.trim().is_empty() {
+            if groups.last() == Some(&ParseToken::OpenBrace) {
+                continue;
+            }
+            groups.clear();
+            parsed.finish_statement(std::mem::take(&mut pending));
+            continue;
+        }
+        if pending.is_empty()
+            && let Some(words) = format_directive(line)
+        {
+            match PrintFormat::from_directive(&words) {
+                Ok(format) => {
+                    parsed.statements.push(Statement::Format(format));
+                    parsed.spans.push(line_span(line, line_num));
+                    parsed.files.push(None);
+                }
+                Err(error) => parsed
+                    .diagnostics
+                    .push(Diagnostic::new(error, line_span(line, line_num))),
+            }
+            continue;
+        }
+
+        let mut tokens = match lex(line, line_num) {
+            Ok(tokens) => tokens,
+            Err(diagnostic) => {
+                // the rest of the statement can't be trusted, start again on the next line. What
+                // lexed before the error still tells whether it was an assignment.
+                let before: String = line.chars().take(diagnostic.span.column - 1).collect();
+                pending.extend(lex(&before, line_num).unwrap_or_default());
+                parsed.abandon_statement(std::mem::take(&mut pending), diagnostic);
+                groups.clear();
+                continue;
+            }
+        };
+        let continued = tokens.last().map(|t| &t.kind) == Some(&ParseToken::Continuation);
+        if continued {
+            tokens.pop();
+        }
+        if let Some(misplaced) = tokens.iter().find(|t| t.kind == ParseToken::Continuation) {
+            let diagnostic = Diagnostic::new(CompileError::InvalidContinuation, misplaced.span);
+            parsed.abandon_statement(std::mem::take(&mut pending), diagnostic);
+            groups.clear();
+            continue;
+        }
+        for token in tokens {
+            match token.kind {
+                ParseToken::OpenParen | ParseToken::OpenBracket | ParseToken::OpenBrace => {
+                    groups.push(token.kind.clone());
+                }
+                ParseToken::CloseParen | ParseToken::CloseBracket | ParseToken::CloseBrace => {
+                    groups.pop();
+                }
+                // a `;` in a block separates the block's statements
+                ParseToken::Semicolon if groups.contains(&ParseToken::OpenBrace) => {}
+                ParseToken::Semicolon => {
+                    groups.clear();
+                    parsed.finish_statement(std::mem::take(&mut pending));
+                    continue;
+                }
+                _ => {}
+            }
+            pending.push(token);
+        }
+        if groups.last() == Some(&ParseToken::OpenBrace) && !continued {
+            let last = pending.last().expect("an open brace was pushed");
+            if !matches!(last.kind, ParseToken::OpenBrace | ParseToken::Semicolon) {
+                let separator = Token {
+                    kind: ParseToken::Semicolon,
+                    span: last.span,
+                };
+                pending.push(separator);
+            }
+        }
+        if groups.is_empty() && !continued {
+            parsed.finish_statement(std::mem::take(&mut pending));
+        }
+    }
+    parsed.finish_statement(pending);
+    return parsed;
+}
+
+/// Parses the tokens of one logical statement, which may have come from several lines.
+fn parse_statement(tokens: Vec<Token>) -> Result<Option<(Statement, Span)>, Diagnostic> {
+    let (Some(first), Some(last)) = (tokens.first(), tokens.last()) else {
+        return Ok(None);
+    };
+    let span = first.span.to(last.span);
+    if matches!(first.kind, ParseToken::Import | ParseToken::From) {
+        return Ok(Some((Statement::Import(parse_import(&tokens)?), span)));
+    }
+    let tokens = lower_blocks(tokens)?;
+    let assignments = assignments(&tokens);
+    let statement = if let Some(&assign) = assignments.first() {
+        let (id, expr) = (&tokens[..assign], &tokens[assign + 1..]);
+        let mut declaration = split_declaration(id, tokens[assign].span)?;
+        if let Some(&second) = assignments.get(1) {
+            return Err(Diagnostic::new(
+                CompileError::MultipleAssignment,
+                tokens[second].span,
+            ));
+        }
+        if expr.is_empty() {
+            return Err(Diagnostic::new(
+                CompileError::EmptyExpression,
+                tokens[assign].span,
+            ));
+        }
+        declaration.body = infix_to_rpn(expr)?;
+        Statement::Declaration(declaration)
     } else {
-        return Err(CompileError::InvalidAssignment.into());
+        Statement::Expression(infix_to_rpn(&tokens)?)
     };
+    return Ok(Some((statement, span)));
+}
 
-    let args = declaration
-        .into_iter()
-        .skip(1)
-        .filter(|token| token.is_identifier())
-        .map(|token| match token {
-            ParseToken::Identifier(arg) => arg.to_owned(),
-            _ => panic!("impossible"),
-        })
-        .collect();
+/// Parses `import "file.nms"` or `from "file.nms" import name, ...`, a module of the standard
+/// library is named without quotes like `import std.stats`.
+fn parse_import(tokens: &[Token]) -> Result<Import, Diagnostic> {
+    let invalid = |token: &Token| Diagnostic::new(CompileError::InvalidImport, token.span);
+    let source = |token: &Token| match &token.kind {
+        ParseToken::Str(path) => Some((path.clone(), false)),
+        ParseToken::Identifier(module) if module.starts_with("std.") => Some((module.clone(), true)),
+        _ => None,
+    };
+    let ((path, std), names) = match tokens {
+        [first, path] if first.kind == ParseToken::Import => {
+            (source(path).ok_or_else(|| invalid(path))?, None)
+        }
+        [first, path, import, names @ ..]
+            if first.kind == ParseToken::From && import.kind == ParseToken::Import =>
+        {
+            (source(path).ok_or_else(|| invalid(path))?, Some(names))
+        }
+        [_, path, ..] if source(path).is_none() => return Err(invalid(path)),
+        [.., last] => return Err(invalid(last)),
+        [] => unreachable!("a statement has tokens"),
+    };
+    let names = match names {
+        None => None,
+        // the names are separated by commas
+        Some(names) => {
+            let mut imported = vec![];
+            for (index, token) in names.iter().enumerate() {
+                match &token.kind {
+                    ParseToken::Identifier(name) if index % 2 == 0 => imported.push(name.clone()),
+                    ParseToken::Comma if index % 2 == 1 && index + 1 < names.len() => {}
+                    _ => return Err(invalid(token)),
+                }
+            }
+            if imported.is_empty() {
+                return Err(invalid(&tokens[tokens.len() - 1]));
+            }
+            Some(imported)
+        }
+    };
+    return Ok(Import { path, std, names });
+}
+
+/// Rewrites every block like `{ a = x; b = a + 1; a * b }` into the `let`s it stands for,
+/// `(let a = x in let b = a + 1 in a * b)`, so the names it assigns end with it.
+fn lower_blocks(tokens: Vec<Token>) -> Result<Vec<Token>, Diagnostic> {
+    let mut output = vec![];
+    let mut tokens = tokens.into_iter();
+    while let Some(token) = tokens.next() {
+        match token.kind {
+            ParseToken::OpenBrace => {
+                let mut depth = 0;
+                let mut inner = vec![];
+                let close = loop {
+                    let Some(next) = tokens.next() else {
+                        return Err(Diagnostic::new(CompileError::UnclosedBrace, token.span));
+                    };
+                    match next.kind {
+                        ParseToken::OpenBrace => depth += 1,
+                        ParseToken::CloseBrace if depth == 0 => break next,
+                        ParseToken::CloseBrace => depth -= 1,
+                        _ => {}
+                    }
+                    inner.push(next);
+                };
+                output.extend(lower_block(token, inner, close)?);
+            }
+            ParseToken::CloseBrace => {
+                return Err(Diagnostic::new(CompileError::UnexpectedCloseBrace, token.span));
+            }
+            _ => output.push(token),
+        }
+    }
+    return Ok(output);
+}
+
+/// The `let`s standing for the statements of one block between its braces, see `lower_blocks`.
+fn lower_block(open: Token, inner: Vec<Token>, close: Token) -> Result<Vec<Token>, Diagnostic> {
+    // every statement with the span of the `;` or brace ending it
+    let mut statements: Vec<(Vec<Token>, Span)> = vec![];
+    let mut statement = vec![];
+    let mut depth = 0;
+    for token in inner {
+        match token.kind {
+            ParseToken::OpenParen | ParseToken::OpenBracket | ParseToken::OpenBrace => depth += 1,
+            ParseToken::CloseParen | ParseToken::CloseBracket | ParseToken::CloseBrace => {
+                depth -= 1
+            }
+            ParseToken::Semicolon if depth == 0 => {
+                if !statement.is_empty() {
+                    statements.push((std::mem::take(&mut statement), token.span));
+                }
+                continue;
+            }
+            _ => {}
+        }
+        statement.push(token);
+    }
+    if !statement.is_empty() {
+        statements.push((statement, close.span));
+    }
+    let is_assignment = |statement: &[Token]| {
+        matches!(
+            statement,
+            [Token { kind: ParseToken::Identifier(_), .. }, Token { kind: ParseToken::Assign, .. }, ..]
+        )
+    };
+    let Some((value, _)) = statements.pop().filter(|(last, _)| !is_assignment(last)) else {
+        return Err(Diagnostic::new(CompileError::MissingBlockValue, close.span));
+    };
+
+    let mut output = vec![Token {
+        kind: ParseToken::OpenParen,
+        span: open.span,
+    }];
+    for (statement, end) in statements {
+        if !is_assignment(&statement) {
+            let span = statement[0].span.to(statement[statement.len() - 1].span);
+            return Err(Diagnostic::new(CompileError::BlockExpression, span));
+        }
+        let mut statement = statement.into_iter();
+        let (name, assign) = (statement.next().unwrap(), statement.next().unwrap());
+        let value = lower_blocks(statement.collect())?;
+        if value.is_empty() {
+            return Err(Diagnostic::new(CompileError::EmptyExpression, assign.span));
+        }
+        output.push(Token {
+            kind: ParseToken::Let,
+            span: name.span,
+        });
+        output.extend([name, assign]);
+        output.extend(value);
+        output.push(Token {
+            kind: ParseToken::In,
+            span: end,
+        });
+    }
+    output.extend(lower_blocks(value)?);
+    output.push(Token {
+        kind: ParseToken::CloseParen,
+        span: close.span,
+    });
+    return Ok(output);
+}
+
+/// The span of a whole line without its surrounding whitespace.
+fn line_span(line: &str, line_num: usize) -> Span {
+    let start = line.chars().take_while(|c| c.is_whitespace()).count();
+    Span {
+        line: line_num,
+        column: start + 1,
+        end_line: line_num,
+        end_column: line.trim_end().chars().count() + 1,
+    }
+}
+
+/// The words after `#fmt` if the line is a format directive, a trailing comment is ignored.
+fn format_directive(line: &str) -> Option<Vec<&str>> {
+    let mut words = line.split_whitespace();
+    if words.next() != Some("#fmt") {
+        return None;
+    }
+    Some(
+        words
+            .take_while(|word| !word.starts_with('#') && !word.starts_with("//"))
+            .collect(),
+    )
+}
+
+/// Splits the left side of an assignment into the name being assigned and the parameters,
+/// it has to be either `name` or `name(param, ...)` with every parameter a different name.
+/// Parameters and the result of a function can be annotated with their type like
+/// `name(param: int): float`. The body of the returned declaration is left empty.
+fn split_declaration(declaration: &[Token], assign: Span) -> Result<Declaration, Diagnostic> {
+    let expression =
+        |token: &Token| Diagnostic::new(CompileError::ExpressionAssignment, token.span);
+    let Some((first, rest)) = declaration.split_first() else {
+        return Err(Diagnostic::new(CompileError::InvalidAssignment, assign));
+    };
+    let ParseToken::Identifier(name) = &first.kind else {
+        return Err(Diagnostic::new(CompileError::InvalidAssignment, first.span));
+    };
+    let mut declaration = Declaration {
+        name: name.clone(),
+        ..Declaration::default()
+    };
+    let Some((open, rest)) = rest.split_first() else {
+        return Ok(declaration);
+    };
+    if open.kind != ParseToken::OpenParen {
+        return Err(expression(open));
+    }
+    declaration.function = true;
+
+    let args = &mut declaration.args;
+    let mut rest = rest.iter().peekable();
+    loop {
+        let param = match rest.next() {
+            Some(token) if token.kind == ParseToken::CloseParen && args.is_empty() => break,
+            Some(token) => token,
+            None => return Err(Diagnostic::new(CompileError::UnclosedParen, open.span)),
+        };
+        let ParseToken::Identifier(arg) = &param.kind else {
+            return Err(expression(param));
+        };
+        if args.contains(arg) {
+            let error = CompileError::DuplicateParameter(arg.clone());
+            return Err(Diagnostic::new(error, param.span));
+        }
+        args.push(arg.clone());
+        if let Some(ty) = annotation(&mut rest, param.span)? {
+            declaration.annotations.insert(arg.clone(), ty);
+        }
+        match rest.next() {
+            Some(token) if token.kind == ParseToken::Comma => {}
+            Some(token) if token.kind == ParseToken::CloseParen => break,
+            Some(token) => return Err(expression(token)),
+            None => return Err(Diagnostic::new(CompileError::UnclosedParen, open.span)),
+        }
+    }
+    declaration.return_type = annotation(&mut rest, open.span)?;
+    if let Some(extra) = rest.next() {
+        return Err(expression(extra));
+    }
+    return Ok(declaration);
+}
 
-    return Ok((name.clone(), args));
+/// Reads a `: type` annotation if the tokens continue with one, `after` is where an error about
+/// a missing type name points.
+fn annotation<'a>(
+    tokens: &mut std::iter::Peekable<impl Iterator<Item = &'a Token>>,
+    after: Span,
+) -> Result<Option<Type>, Diagnostic> {
+    let Some(colon) = tokens.next_if(|t| t.kind == ParseToken::Colon) else {
+        return Ok(None);
+    };
+    return Ok(Some(type_name(tokens, after.to(colon.span))?));
+}
+
+/// Reads a type, either a name like `float` or a function type like `(float, int) -> float`.
+fn type_name<'a>(
+    tokens: &mut std::iter::Peekable<impl Iterator<Item = &'a Token>>,
+    after: Span,
+) -> Result<Type, Diagnostic> {
+    let (name, span) = match tokens.next() {
+        Some(Token {
+            kind: ParseToken::Identifier(name),
+            span,
+        }) => (name, span),
+        Some(open) if open.kind == ParseToken::OpenParen => {
+            let mut params = vec![];
+            if tokens.next_if(|t| t.kind == ParseToken::CloseParen).is_none() {
+                loop {
+                    params.push(type_name(tokens, open.span)?);
+                    match tokens.next() {
+                        Some(token) if token.kind == ParseToken::Comma => {}
+                        Some(token) if token.kind == ParseToken::CloseParen => break,
+                        Some(token) => {
+                            let error = CompileError::UnknownType(token.kind.to_string());
+                            return Err(Diagnostic::new(error, token.span));
+                        }
+                        None => {
+                            return Err(Diagnostic::new(CompileError::UnclosedParen, open.span))
+                        }
+                    }
+                }
+            }
+            let Some(arrow) = tokens.next_if(|t| t.kind == ParseToken::Arrow) else {
+                return Err(Diagnostic::new(CompileError::MissingType, after.to(open.span)));
+            };
+            let result = type_name(tokens, arrow.span)?;
+            return Ok(Type::Function(params, Box::new(result)));
+        }
+        Some(token) => {
+            let error = CompileError::UnknownType(token.kind.to_string());
+            return Err(Diagnostic::new(error, token.span));
+        }
+        None => return Err(Diagnostic::new(CompileError::MissingType, after)),
+    };
+    let ty = match name.as_str() {
+        "int" => Type::Long,
+        "float" => Type::Double,
+        "bool" => Type::Bool,
+        "vector" => Type::Vector,
+        "matrix" => Type::Matrix,
+        _ => return Err(Diagnostic::new(CompileError::UnknownType(name.clone()), *span)),
+    };
+    return Ok(ty);
+}
+
+/// Where a token came from in the source, lines and columns start at 1 and the end is
+/// exclusive.
+#[derive(PartialEq, Debug, Clone, Copy, Default)]
+pub struct Span {
+    pub line: usize,
+    pub column: usize,
+    pub end_line: usize,
+    pub end_column: usize,
+}
+
+impl Span {
+    /// From the start of this span to the end of `other`.
+    pub fn to(self, other: Span) -> Span {
+        Span {
+            end_line: other.end_line,
+            end_column: other.end_column,
+            ..self
+        }
+    }
+}
+
+#[derive(PartialEq, Debug, Clone)]
+pub struct Token {
+    pub kind: ParseToken,
+    pub span: Span,
 }
 
 #[derive(PartialEq, Debug, Clone)]
@@ -62,13 +580,88 @@ pub enum ParseToken {
     Subtract,
     Multiply,
     Divide,
+    Remainder,
     Exponent,
+    /// `@`, the product of matrices and vectors
+    MatrixMultiply,
+    /// unary minus, never produced by `lex` but by `infix_to_rpn` from a `-` without a left operand
+    Negate,
+    Equal,
+    NotEqual,
+    Less,
+    LessEqual,
+    Greater,
+    GreaterEqual,
+    And,
+    Or,
+    Not,
+    /// ends the left operand of an `and`, only produced by `infix_to_rpn` so the right operand
+    /// can be skipped when the left one is false
+    AndThen,
+    /// ends the left operand of an `or`, only produced by `infix_to_rpn` so the right operand
+    /// can be skipped when the left one is true
+    OrElse,
     Assign,
     OpenParen,
     CloseParen,
+    OpenBracket,
+    CloseBracket,
+    /// `{` opening a block, rewritten into `let`s before `infix_to_rpn`
+    OpenBrace,
+    CloseBrace,
     Comma,
+    /// separates a parameter or function from its type
+    Colon,
+    Semicolon,
+    /// `import` in `import "file.nms"` or `from "file.nms" import name`
+    Import,
+    From,
+    /// a string literal between double quotes, only used for the file of an import
+    Str(String),
+    /// a trailing `\` joining the next line onto this statement
+    Continuation,
     Identifier(String),
+    /// a literal with a decimal point or an exponent
     Number(f64),
+    /// a literal without a decimal point or exponent, including every `0x`/`0b` literal
+    Integer(i64),
+    /// `true` or `false`
+    Boolean(bool),
+    /// calling a function with this many arguments, only produced by `infix_to_rpn` after the
+    /// arguments
+    Call(String, usize),
+    /// a vector literal with this many elements, only produced by `infix_to_rpn` after the
+    /// elements
+    Vector(usize),
+    /// indexing the vector below the index, only produced by `infix_to_rpn` after both
+    Index,
+    /// starts the body of a `sum` or `prod` over a range, binding the variable to every int
+    /// from the first to the second value below it, only produced by `infix_to_rpn`
+    Loop(String),
+    /// ends the body of the innermost `Loop`, only produced by `infix_to_rpn`
+    EndLoop(Accumulator),
+    /// `->` between the parameters and the body of a lambda
+    Arrow,
+    /// starts the body of a lambda taking these parameters, only produced by `infix_to_rpn`
+    Lambda(Vec<String>),
+    /// ends the body of the innermost `Lambda`, only produced by `infix_to_rpn`
+    EndLambda,
+    /// `let` starting a local name, `let name = value in body`
+    Let,
+    /// `in` between the value of a `let` and the expression using it
+    In,
+    /// binds the value below it to the name until the next `EndBind`, only produced by
+    /// `infix_to_rpn`
+    Bind(String),
+    /// ends the innermost `Bind`, only produced by `infix_to_rpn`
+    EndBind,
+}
+
+/// How the values of a loop's body are combined.
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum Accumulator {
+    Sum,
+    Product,
 }
 
 impl ParseToken {
@@ -78,43 +671,120 @@ impl ParseToken {
             ParseToken::Subtract,
             ParseToken::Multiply,
             ParseToken::Divide,
+            ParseToken::Remainder,
             ParseToken::Exponent,
+            ParseToken::MatrixMultiply,
+            ParseToken::And,
+            ParseToken::Or,
+        ]
+        .contains(self)
+            || self.is_comparison()
+    }
+
+    pub fn is_comparison(&self) -> bool {
+        [
+            ParseToken::Equal,
+            ParseToken::NotEqual,
+            ParseToken::Less,
+            ParseToken::LessEqual,
+            ParseToken::Greater,
+            ParseToken::GreaterEqual,
         ]
         .contains(self)
     }
 
     fn presidence(&self) -> i32 {
         match self {
-            ParseToken::Add => 2,
-            ParseToken::Subtract => 2,
-            ParseToken::Multiply => 3,
-            ParseToken::Divide => 3,
-            ParseToken::Exponent => 4,
-            _ => 1,
+            ParseToken::Or => 1,
+            ParseToken::And => 2,
+            ParseToken::Not => 3,
+            _ if self.is_comparison() => 4,
+            ParseToken::Add => 5,
+            ParseToken::Subtract => 5,
+            ParseToken::Multiply => 6,
+            ParseToken::Divide => 6,
+            ParseToken::Remainder => 6,
+            ParseToken::MatrixMultiply => 6,
+            ParseToken::Negate => 7,
+            ParseToken::Exponent => 8,
+            _ => 0,
         }
     }
 
     fn is_left_associative(&self) -> bool {
         match self {
-            ParseToken::Add | ParseToken::Subtract | ParseToken::Multiply | ParseToken::Divide => {
-                true
-            }
+            ParseToken::Add
+            | ParseToken::Subtract
+            | ParseToken::Multiply
+            | ParseToken::Divide
+            | ParseToken::Remainder
+            | ParseToken::MatrixMultiply
+            | ParseToken::And
+            | ParseToken::Or => true,
+            _ if self.is_comparison() => true,
             ParseToken::Exponent => false,
             _ => false,
         }
     }
 
     pub fn is_number(&self) -> bool {
-        match self {
-            ParseToken::Number(_) => true,
-            _ => false,
-        }
+        matches!(self, ParseToken::Number(_) | ParseToken::Integer(_))
     }
 
     pub fn is_identifier(&self) -> bool {
+        matches!(self, ParseToken::Identifier(_))
+    }
+}
+
+impl fmt::Display for ParseToken {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
-            ParseToken::Identifier(_) => true,
-            _ => false,
+            ParseToken::Add => write!(f, "+"),
+            ParseToken::Subtract | ParseToken::Negate => write!(f, "-"),
+            ParseToken::Multiply => write!(f, "*"),
+            ParseToken::Divide => write!(f, "/"),
+            ParseToken::Remainder => write!(f, "%"),
+            ParseToken::Exponent => write!(f, "^"),
+            ParseToken::MatrixMultiply => write!(f, "@"),
+            ParseToken::Equal => write!(f, "=="),
+            ParseToken::NotEqual => write!(f, "!="),
+            ParseToken::Less => write!(f, "<"),
+            ParseToken::LessEqual => write!(f, "<="),
+            ParseToken::Greater => write!(f, ">"),
+            ParseToken::GreaterEqual => write!(f, ">="),
+            ParseToken::And | ParseToken::AndThen => write!(f, "and"),
+            ParseToken::Or | ParseToken::OrElse => write!(f, "or"),
+            ParseToken::Not => write!(f, "not"),
+            ParseToken::Assign => write!(f, "="),
+            ParseToken::OpenParen => write!(f, "("),
+            ParseToken::CloseParen => write!(f, ")"),
+            ParseToken::OpenBracket => write!(f, "["),
+            ParseToken::CloseBracket => write!(f, "]"),
+            ParseToken::OpenBrace => write!(f, "{{"),
+            ParseToken::CloseBrace => write!(f, "}}"),
+            ParseToken::Comma => write!(f, ","),
+            ParseToken::Colon => write!(f, ":"),
+            ParseToken::Semicolon => write!(f, ";"),
+            ParseToken::Import => write!(f, "import"),
+            ParseToken::From => write!(f, "from"),
+            ParseToken::Str(string) => write!(f, "\"{string}\""),
+            ParseToken::Continuation => write!(f, "\\"),
+            ParseToken::Identifier(name) => write!(f, "{name}"),
+            ParseToken::Number(n) => write!(f, "{n}"),
+            ParseToken::Integer(n) => write!(f, "{n}"),
+            ParseToken::Boolean(b) => write!(f, "{b}"),
+            ParseToken::Call(name, _) => write!(f, "{name}()"),
+            ParseToken::Vector(_) => write!(f, "[...]"),
+            ParseToken::Index => write!(f, "[]"),
+            ParseToken::Loop(variable) => write!(f, "{variable}"),
+            ParseToken::EndLoop(Accumulator::Sum) => write!(f, "sum()"),
+            ParseToken::EndLoop(Accumulator::Product) => write!(f, "prod()"),
+            ParseToken::Arrow => write!(f, "->"),
+            ParseToken::Lambda(params) => write!(f, "({}) ->", params.join(", ")),
+            ParseToken::EndLambda => write!(f, "lambda"),
+            ParseToken::Let => write!(f, "let"),
+            ParseToken::In | ParseToken::EndBind => write!(f, "in"),
+            ParseToken::Bind(name) => write!(f, "let {name} ="),
         }
     }
 }
@@ -125,62 +795,254 @@ pub enum EvalUnit {
     Operation(String, i32),
 }
 
-const DIGITS: &str = ".0123456789";
 const ALPHABET: &str = "_abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ";
-fn tokenize(source: &str) -> Result<Vec<ParseToken>> {
-    let mut tokens: Vec<ParseToken> = vec![];
-    let mut chars = source.chars().peekable();
-    while let Some(ch) = chars.next() {
-        match ch {
-            '0'..'9' | '.' => {
-                let mut number = ch.to_string();
-                while let Some(next_digit) = chars.peek() {
-                    if DIGITS.contains(*next_digit) {
-                        number.push(*next_digit);
+/// Splits one physical line into tokens, remembering the column each one started at.
+fn lex(source: &str, line_num: usize) -> Result<Vec<Token>, Diagnostic> {
+    let mut tokens: Vec<Token> = vec![];
+    let mut chars = source.chars().enumerate().peekable();
+    let span = |start: usize, end: usize| Span {
+        line: line_num,
+        column: start + 1,
+        end_line: line_num,
+        end_column: end + 1,
+    };
+    while let Some((index, ch)) = chars.next() {
+        let kind = match ch {
+            '0'..='9' | '.' => {
+                // take everything that could belong to the literal so typos like `0xG` or
+                // `1..2` are reported whole instead of being split into several tokens
+                let mut literal = ch.to_string();
+                while let Some(&(_, next)) = chars.peek() {
+                    let exponent_sign = matches!(next, '+' | '-')
+                        && radix_literal(&literal).is_none()
+                        && literal.ends_with(['e', 'E']);
+                    if next.is_ascii_alphanumeric() || matches!(next, '_' | '.') || exponent_sign {
+                        literal.push(next);
                         chars.next();
                     } else {
                         break;
                     }
                 }
-                let parsed = number
-                    .parse::<f64>()
-                    .context(format!("failed to parse float literal: {}", number))?;
-                tokens.push(ParseToken::Number(parsed));
+                parse_number(&literal)
+                    .map_err(|error| Diagnostic::new(error, span(index, index + literal.len())))?
             }
             'a'..='z' | 'A'..='Z' | '_' => {
                 let mut identifier = ch.to_string();
-                while let Some(next_letter) = chars.peek() {
-                    if ALPHABET.contains(*next_letter) {
-                        identifier.push(*next_letter);
+                while let Some(&(next_index, next_letter)) = chars.peek() {
+                    // a name from an imported file is qualified with its namespace like `geo.dist`
+                    let qualified = next_letter == '.'
+                        && source
+                            .chars()
+                            .nth(next_index + 1)
+                            .is_some_and(|c| c.is_ascii_alphabetic() || c == '_');
+                    if ALPHABET.contains(next_letter) || qualified {
+                        identifier.push(next_letter);
                         chars.next();
                     } else {
                         break;
                     }
                 }
-                tokens.push(ParseToken::Identifier(identifier));
+                match identifier.as_str() {
+                    "and" => ParseToken::And,
+                    "or" => ParseToken::Or,
+                    "not" => ParseToken::Not,
+                    "let" => ParseToken::Let,
+                    "in" => ParseToken::In,
+                    "import" => ParseToken::Import,
+                    "from" => ParseToken::From,
+                    "true" => ParseToken::Boolean(true),
+                    "false" => ParseToken::Boolean(false),
+                    _ => ParseToken::Identifier(identifier),
+                }
             }
-            '+' => tokens.push(ParseToken::Add),
-            '-' => tokens.push(ParseToken::Subtract),
-            '*' => tokens.push(ParseToken::Multiply),
-            '/' => tokens.push(ParseToken::Divide),
-            '^' => tokens.push(ParseToken::Exponent),
-            '=' => tokens.push(ParseToken::Assign),
-            ',' => tokens.push(ParseToken::Comma),
-            '(' => tokens.push(ParseToken::OpenParen),
-            ')' => tokens.push(ParseToken::CloseParen),
-            ' ' | '\t' => {}
-            _ => return Err(CompileError::InvalidCharacter(ch).into()),
-        }
+            '"' => {
+                let string: String = chars.by_ref().map(|(_, c)| c).take_while(|&c| c != '"').collect();
+                if !source[..].chars().skip(index + 1).any(|c| c == '"') {
+                    let error = CompileError::UnterminatedString;
+                    return Err(Diagnostic::new(error, span(index, source.chars().count())));
+                }
+                ParseToken::Str(string)
+            }
+            '+' => ParseToken::Add,
+            '-' if chars.next_if(|&(_, c)| c == '>').is_some() => ParseToken::Arrow,
+            '-' => ParseToken::Subtract,
+            '*' => ParseToken::Multiply,
+            // the rest of the line is a comment
+            '#' => break,
+            '/' if chars.peek().map(|&(_, c)| c) == Some('/') => break,
+            '/' => ParseToken::Divide,
+            '%' => ParseToken::Remainder,
+            '^' => ParseToken::Exponent,
+            '@' => ParseToken::MatrixMultiply,
+            '=' if chars.next_if(|&(_, c)| c == '=').is_some() => ParseToken::Equal,
+            '=' => ParseToken::Assign,
+            '!' if chars.next_if(|&(_, c)| c == '=').is_some() => ParseToken::NotEqual,
+            '<' if chars.next_if(|&(_, c)| c == '=').is_some() => ParseToken::LessEqual,
+            '<' => ParseToken::Less,
+            '>' if chars.next_if(|&(_, c)| c == '=').is_some() => ParseToken::GreaterEqual,
+            '>' => ParseToken::Greater,
+            ',' => ParseToken::Comma,
+            ':' => ParseToken::Colon,
+            ';' => ParseToken::Semicolon,
+            '\\' => ParseToken::Continuation,
+            '(' => ParseToken::OpenParen,
+            ')' => ParseToken::CloseParen,
+            '[' => ParseToken::OpenBracket,
+            ']' => ParseToken::CloseBracket,
+            '{' => ParseToken::OpenBrace,
+            '}' => ParseToken::CloseBrace,
+            ' ' | '\t' | '\r' => continue,
+            _ => {
+                let error = CompileError::InvalidCharacter(ch);
+                return Err(Diagnostic::new(error, span(index, index + 1)));
+            }
+        };
+        let end = chars.peek().map_or(source.chars().count(), |&(i, _)| i);
+        tokens.push(Token {
+            kind,
+            span: span(index, end),
+        });
     }
     return Ok(tokens);
 }
 
+/// Parses decimal literals with an optional exponent like `6.022e23`, and `0x`/`0b` integer
+/// literals. Any literal can use `_` between digits as a separator. Literals without a decimal
+/// point or exponent are integers.
+fn parse_number(literal: &str) -> Result<ParseToken, CompileError> {
+    let invalid =
+        |reason: &str| CompileError::InvalidNumber(literal.to_string(), reason.to_string());
+
+    if let Some((radix, digits)) = radix_literal(literal) {
+        if digits.is_empty() {
+            return Err(invalid("no digits after the prefix"));
+        }
+        if let Some(bad) = digits.chars().find(|c| *c != '_' && !c.is_digit(radix)) {
+            let kind = if radix == 16 { "hexadecimal" } else { "binary" };
+            return Err(invalid(&format!("'{bad}' is not a {kind} digit")));
+        }
+        if !valid_separators(digits) {
+            return Err(invalid("digit separators must be between digits"));
+        }
+        let value = i64::from_str_radix(&digits.replace('_', ""), radix)
+            .map_err(|_| invalid("too large for an integer literal"))?;
+        return Ok(ParseToken::Integer(value));
+    }
+
+    let (mantissa, exponent) = match literal.split_once(['e', 'E']) {
+        Some((mantissa, exponent)) => (mantissa, Some(exponent)),
+        None => (literal, None),
+    };
+    if mantissa.matches('.').count() > 1 {
+        return Err(invalid("more than one decimal point"));
+    }
+    if let Some(bad) = mantissa.chars().find(|c| !c.is_ascii_digit() && *c != '.' && *c != '_') {
+        return Err(invalid(&format!("unexpected '{bad}' in number")));
+    }
+    if !mantissa.chars().any(|c| c.is_ascii_digit()) {
+        return Err(invalid("no digits"));
+    }
+    if !valid_separators(mantissa) {
+        return Err(invalid("digit separators must be between digits"));
+    }
+    if let Some(exponent) = exponent {
+        let digits = exponent.strip_prefix(['+', '-']).unwrap_or(exponent);
+        if digits.is_empty() {
+            return Err(invalid("exponent has no digits"));
+        }
+        if let Some(bad) = digits.chars().find(|c| !c.is_ascii_digit() && *c != '_') {
+            return Err(invalid(&format!("unexpected '{bad}' in exponent")));
+        }
+        if !valid_separators(digits) {
+            return Err(invalid("digit separators must be between digits"));
+        }
+    }
+    let digits = literal.replace('_', "");
+    if exponent.is_none() && !mantissa.contains('.') {
+        return digits.parse::<i64>().map(ParseToken::Integer).map_err(|_| {
+            invalid("too large for an integer literal, add `.0` to make it a float")
+        });
+    }
+    return digits
+        .parse::<f64>()
+        .map(ParseToken::Number)
+        .map_err(|_| invalid("not a number"));
+}
+
+/// Splits `0x`/`0b` literals into their radix and digits.
+fn radix_literal(literal: &str) -> Option<(u32, &str)> {
+    let prefix = literal.get(..2)?;
+    match prefix {
+        "0x" | "0X" => Some((16, &literal[2..])),
+        "0b" | "0B" => Some((2, &literal[2..])),
+        _ => None,
+    }
+}
+
+fn valid_separators(digits: &str) -> bool {
+    let chars: Vec<char> = digits.chars().collect();
+    chars.iter().enumerate().all(|(i, c)| {
+        *c != '_'
+            || (i > 0
+                && i + 1 < chars.len()
+                && chars[i - 1].is_ascii_alphanumeric()
+                && chars[i + 1].is_ascii_alphanumeric())
+    })
+}
+
+/// What an open parenthesis or bracket in an expression holds.
+#[derive(PartialEq, Clone, Copy)]
+enum Group {
+    Paren,
+    /// the arguments of a call
+    Call,
+    /// the bounds and body of `sum(i, start, end, body)` or `prod(i, start, end, body)`, the
+    /// variable is left out
+    Loop,
+    /// the elements of a vector literal
+    Vector,
+    /// the index after the vector it indexes
+    Index,
+    /// the value of a `let`, up to its `in`
+    Let,
+}
+
+/// The parameters of a lambda if the tokens after an opening parenthesis are a list of names,
+/// a closing parenthesis and `->`.
+fn lambda_params<'a>(mut tokens: impl Iterator<Item = &'a Token>) -> Option<Vec<String>> {
+    let mut params = vec![];
+    loop {
+        match tokens.next().map(|t| &t.kind) {
+            Some(ParseToken::CloseParen) if params.is_empty() => break,
+            Some(ParseToken::Identifier(param)) => params.push(param.clone()),
+            _ => return None,
+        }
+        match tokens.next().map(|t| &t.kind) {
+            Some(ParseToken::Comma) => {}
+            Some(ParseToken::CloseParen) => break,
+            _ => return None,
+        }
+    }
+    if tokens.next().map(|t| &t.kind) != Some(&ParseToken::Arrow) {
+        return None;
+    }
+    return Some(params);
+}
+
 /// Converts an infix expression to reverse polish notation to make evaluation simpler.
 /// This function is an implementation of the shunting yard algorithm.
 /// https://en.wikipedia.org/wiki/Shunting_yard_algorithm#The_algorithm_in_detail
-fn infix_to_rpn(expr: Vec<ParseToken>) -> Result<Vec<ParseToken>> {
+fn infix_to_rpn(expr: &[Token]) -> Result<Vec<ParseToken>, Diagnostic> {
     let mut output: Vec<ParseToken> = vec![];
     let mut stack: Vec<ParseToken> = vec![];
+    // one entry per open parenthesis or bracket, with the number of commas seen in it
+    let mut groups: Vec<(Group, Span, usize)> = vec![];
+    // the variable of every open `Loop` group
+    let mut loop_variables: Vec<String> = vec![];
+    // the name of every open `Let` group
+    let mut let_names: Vec<String> = vec![];
+    let mut expect_operand = true;
+    let mut previous: Option<&Token> = None;
     let mut tokens = expr.iter().peekable();
 
     let should_pop = |t: &ParseToken, stack: &Vec<ParseToken>| {
@@ -189,62 +1051,276 @@ fn infix_to_rpn(expr: Vec<ParseToken>) -> Result<Vec<ParseToken>> {
         }
         let last = stack[stack.len() - 1].clone();
         last != ParseToken::OpenParen
+            && last != ParseToken::OpenBracket
             && (last.presidence() > t.presidence()
                 || last.presidence() >= t.presidence() && t.is_left_associative())
     };
+    // an operand is missing before a closing `)`, `,` or the end of the expression
+    let missing = |previous: Option<&Token>, token: Option<&Token>| -> Diagnostic {
+        let at = |token: Option<&Token>| token.or(previous).map_or(Span::default(), |t| t.span);
+        match (previous.map(|t| &t.kind), token.map(|t| &t.kind)) {
+            (Some(ParseToken::Comma), _) => Diagnostic::new(CompileError::EmptyArgument, at(None)),
+            (Some(ParseToken::OpenParen | ParseToken::OpenBracket), Some(ParseToken::Comma)) => {
+                Diagnostic::new(CompileError::EmptyArgument, at(token))
+            }
+            (Some(op), _) if op.is_operator() || *op == ParseToken::Not => {
+                Diagnostic::new(CompileError::TrailingOperator(op.clone()), at(None))
+            }
+            (_, Some(kind)) => Diagnostic::new(CompileError::MissingOperand(kind.clone()), at(token)),
+            (_, None) => Diagnostic::new(CompileError::EmptyExpression, at(None)),
+        }
+    };
 
     while let Some(token) = tokens.next() {
         let next_is_opening = if let Some(next) = tokens.peek() {
-            next == &&ParseToken::OpenParen
+            next.kind == ParseToken::OpenParen
         } else {
             false
         };
-        match token {
-            ParseToken::OpenParen => stack.push(token.clone()),
+        let error = |error: CompileError| Diagnostic::new(error, token.span);
+        match &token.kind {
+            ParseToken::OpenParen
+            | ParseToken::Identifier(_)
+            | ParseToken::Number(_)
+            | ParseToken::Integer(_)
+            | ParseToken::Boolean(_)
+            | ParseToken::Not
+            | ParseToken::Let
+                if !expect_operand =>
+            {
+                return Err(error(CompileError::MissingOperator(token.kind.clone())));
+            }
+            // `(a, b) -> body` starts a lambda taking several parameters, or none with `()`
+            ParseToken::OpenParen if expect_operand && lambda_params(tokens.clone()).is_some() => {
+                let params = lambda_params(tokens.clone()).unwrap();
+                // skip the parameters, commas, closing parenthesis and arrow
+                let skipped = (params.len() * 2).max(1) + 1;
+                previous = tokens.nth(skipped - 1);
+                output.push(ParseToken::Lambda(params));
+                stack.push(ParseToken::EndLambda);
+                continue;
+            }
+            ParseToken::OpenParen => {
+                let group = if previous.is_some_and(|p| p.kind.is_identifier()) {
+                    Group::Call
+                } else {
+                    Group::Paren
+                };
+                // `sum` and `prod` starting with a variable and a comma loop over a range
+                let mut ahead = tokens.clone().map(|t| &t.kind);
+                let is_loop = group == Group::Call
+                    && matches!(
+                        previous.map(|p| &p.kind),
+                        Some(ParseToken::Identifier(name)) if name == "sum" || name == "prod"
+                    )
+                    && matches!(ahead.next(), Some(ParseToken::Identifier(_)))
+                    && ahead.next() == Some(&ParseToken::Comma);
+                if is_loop {
+                    let Some(ParseToken::Identifier(variable)) = tokens.next().map(|t| &t.kind)
+                    else {
+                        unreachable!("the variable was just looked at");
+                    };
+                    loop_variables.push(variable.clone());
+                    groups.push((Group::Loop, token.span, 0));
+                    stack.push(token.kind.clone());
+                    previous = tokens.next();
+                    continue;
+                }
+                groups.push((group, token.span, 0));
+                stack.push(token.kind.clone());
+            }
+            // a bracket after an operand indexes it, anywhere else it starts a vector
+            ParseToken::OpenBracket => {
+                let group = if expect_operand {
+                    Group::Vector
+                } else {
+                    Group::Index
+                };
+                groups.push((group, token.span, 0));
+                stack.push(token.kind.clone());
+                expect_operand = true;
+            }
+            // `let name = value in body` binds the value for the body, which reaches as far as
+            // it can like a lambda's
+            ParseToken::Let => {
+                let name = match (tokens.next(), tokens.peek()) {
+                    (Some(name), Some(assign)) if assign.kind == ParseToken::Assign => {
+                        match &name.kind {
+                            ParseToken::Identifier(name) => name.clone(),
+                            _ => return Err(Diagnostic::new(CompileError::InvalidAssignment, name.span)),
+                        }
+                    }
+                    (Some(name), _) if !name.kind.is_identifier() => {
+                        return Err(Diagnostic::new(CompileError::InvalidAssignment, name.span));
+                    }
+                    _ => return Err(error(CompileError::LetWithoutValue)),
+                };
+                let_names.push(name);
+                groups.push((Group::Let, token.span, 0));
+                stack.push(ParseToken::Let);
+                previous = tokens.next();
+                continue;
+            }
+            ParseToken::In => {
+                let Some((Group::Let, _, _)) = groups.last() else {
+                    return Err(error(CompileError::UnexpectedToken(ParseToken::In)));
+                };
+                groups.pop();
+                if expect_operand {
+                    return Err(missing(previous, Some(token)));
+                }
+                while let Some(top) = stack.pop()
+                    && top != ParseToken::Let
+                {
+                    output.push(top);
+                }
+                output.push(ParseToken::Bind(let_names.pop().expect("every let has a name")));
+                stack.push(ParseToken::EndBind);
+                expect_operand = true;
+            }
+            // a `let` has to reach its `in` before the group around it ends
+            ParseToken::CloseParen | ParseToken::CloseBracket | ParseToken::Comma
+                if matches!(groups.last(), Some((Group::Let, _, _))) =>
+            {
+                let (_, open, _) = groups.pop().expect("the group was just looked at");
+                return Err(Diagnostic::new(CompileError::LetWithoutIn, open));
+            }
             ParseToken::CloseParen => {
-                /* TOMORROW refactor this so this function returns eval units, 
-                and function args are counted, perhaps store values in a buf 
-                so if a function is reached you can push the args and count them */
-                while !stack.is_empty()
-                    && let Some(top) = stack.pop()
+                let Some((group @ (Group::Paren | Group::Call | Group::Loop), _, commas)) =
+                    groups.pop()
+                else {
+                    return Err(error(CompileError::UnexpectedCloseParen));
+                };
+                let is_call = group == Group::Call;
+                let empty_call =
+                    is_call && previous.map(|p| &p.kind) == Some(&ParseToken::OpenParen);
+                if expect_operand && !empty_call {
+                    return Err(missing(previous, Some(token)));
+                }
+                while let Some(top) = stack.pop()
+                    && top != ParseToken::OpenParen
                 {
-                    if top == ParseToken::OpenParen {
-                        if !stack.is_empty()
-                            && let Some(next_top) = stack.last()
-                            && next_top.is_identifier()
-                        {
-                            output.push(stack.pop().unwrap());
-                        }
-                        break;
-                    } else {
-                        output.push(top.clone());
+                    output.push(top);
+                }
+                if group == Group::Loop {
+                    let Some(ParseToken::Identifier(name)) = stack.pop() else {
+                        panic!("a loop's name is below its parenthesis");
+                    };
+                    loop_variables.pop();
+                    // the variable was taken off as the first argument
+                    if commas != 2 {
+                        return Err(error(CompileError::ArgumentCount(name, 4, commas + 2)));
                     }
+                    let accumulator = match name.as_str() {
+                        "sum" => Accumulator::Sum,
+                        _ => Accumulator::Product,
+                    };
+                    output.push(ParseToken::EndLoop(accumulator));
+                }
+                if is_call {
+                    let Some(ParseToken::Identifier(name)) = stack.pop() else {
+                        panic!("a call's name is below its parenthesis");
+                    };
+                    let args = if empty_call { 0 } else { commas + 1 };
+                    output.push(ParseToken::Call(name, args));
+                }
+                expect_operand = false;
+            }
+            ParseToken::CloseBracket => {
+                let Some((group @ (Group::Vector | Group::Index), _, commas)) = groups.pop() else {
+                    return Err(error(CompileError::UnexpectedCloseBracket));
+                };
+                let empty_vector = group == Group::Vector
+                    && previous.map(|p| &p.kind) == Some(&ParseToken::OpenBracket);
+                if expect_operand && !empty_vector {
+                    return Err(missing(previous, Some(token)));
+                }
+                while let Some(top) = stack.pop()
+                    && top != ParseToken::OpenBracket
+                {
+                    output.push(top);
                 }
+                output.push(match group {
+                    Group::Index => ParseToken::Index,
+                    _ if empty_vector => ParseToken::Vector(0),
+                    _ => ParseToken::Vector(commas + 1),
+                });
+                expect_operand = false;
             }
             ParseToken::Comma => {
-                while !stack.is_empty()
-                    && let Some(top) = stack.pop()
+                let Some((group @ (Group::Call | Group::Vector | Group::Loop), _, commas)) =
+                    groups.last_mut()
+                else {
+                    return Err(error(CompileError::UnexpectedToken(token.kind.clone())));
+                };
+                *commas += 1;
+                if expect_operand {
+                    return Err(missing(previous, Some(token)));
+                }
+                // the opening token stays so the call or vector is emitted when it closes
+                while let Some(top) = stack.last()
+                    && top != &ParseToken::OpenParen
+                    && top != &ParseToken::OpenBracket
                 {
-                    if top == ParseToken::OpenParen {
-                        break;
-                    } else {
-                        output.push(top.clone());
-                    }
+                    output.push(stack.pop().unwrap());
+                }
+                // the bounds of a loop are complete, its body follows
+                if *group == Group::Loop && *commas == 2 {
+                    let variable = loop_variables.last().expect("every loop has a variable");
+                    output.push(ParseToken::Loop(variable.clone()));
                 }
+                expect_operand = true;
             }
-            ParseToken::Identifier(_) if next_is_opening => {
-                stack.push(ParseToken::OpenParen);
-                stack.push(token.clone());
+            // a lambda's body reaches as far as it can, until its group or the expression ends
+            ParseToken::Identifier(param)
+                if tokens.peek().map(|t| &t.kind) == Some(&ParseToken::Arrow) =>
+            {
+                output.push(ParseToken::Lambda(vec![param.clone()]));
+                stack.push(ParseToken::EndLambda);
+                previous = tokens.next();
+                continue;
             }
-            ParseToken::Identifier(_) | ParseToken::Number(_) => output.push(token.clone()),
-            _ => {
-                // any operator
-                while should_pop(token, &stack) {
+            ParseToken::Identifier(_) if next_is_opening => stack.push(token.kind.clone()),
+            ParseToken::Identifier(_)
+            | ParseToken::Number(_)
+            | ParseToken::Integer(_)
+            | ParseToken::Boolean(_) => {
+                output.push(token.kind.clone());
+                expect_operand = false;
+            }
+            // a minus where an operand belongs negates it
+            ParseToken::Subtract if expect_operand => stack.push(ParseToken::Negate),
+            ParseToken::Not => stack.push(ParseToken::Not),
+            kind if kind.is_operator() => {
+                if expect_operand {
+                    return Err(error(CompileError::MissingOperand(kind.clone())));
+                }
+                while should_pop(kind, &stack) {
                     output.push(stack.pop().unwrap());
                 }
-                stack.push(token.clone());
+                // the left operand is complete, mark it so its right side can be skipped
+                match kind {
+                    ParseToken::And => output.push(ParseToken::AndThen),
+                    ParseToken::Or => output.push(ParseToken::OrElse),
+                    _ => {}
+                }
+                stack.push(kind.clone());
+                expect_operand = true;
             }
+            kind => return Err(error(CompileError::UnexpectedToken(kind.clone()))),
         }
+        previous = Some(token);
+    }
+    if let Some((group, open, _)) = groups.pop() {
+        let error = match group {
+            Group::Paren | Group::Call | Group::Loop => CompileError::UnclosedParen,
+            Group::Vector | Group::Index => CompileError::UnclosedBracket,
+            Group::Let => CompileError::LetWithoutIn,
+        };
+        return Err(Diagnostic::new(error, open));
+    }
+    if expect_operand {
+        return Err(missing(previous, None));
     }
     stack.iter().rev().for_each(|op| output.push(op.clone()));
     return Ok(output);
@@ -254,12 +1330,36 @@ fn infix_to_rpn(expr: Vec<ParseToken>) -> Result<Vec<ParseToken>> {
 mod tests {
     use super::*;
 
+    fn parse(source: &str) -> Result<Vec<Statement>> {
+        let parsed = super::parse(source);
+        if let Some(diagnostic) = parsed.diagnostics.into_iter().next() {
+            let line = diagnostic.span.line;
+            return Err(diagnostic.error.context(format!("on line {line}")));
+        }
+        Ok(parsed.statements)
+    }
+
+    fn tokenize(source: &str) -> Result<Vec<ParseToken>> {
+        Ok(lex(source, 1)?.into_iter().map(|t| t.kind).collect())
+    }
+
+    fn infix_to_rpn(expr: Vec<ParseToken>) -> Result<Vec<ParseToken>> {
+        let tokens: Vec<Token> = expr
+            .into_iter()
+            .map(|kind| Token {
+                kind,
+                span: Span::default(),
+            })
+            .collect();
+        super::infix_to_rpn(&tokens).map_err(|diagnostic| diagnostic.error)
+    }
+
     #[test]
     fn parses_single_line() {
         let input = "1+2";
         let expected = vec![Statement::Expression(vec![
-            ParseToken::Number(1.0),
-            ParseToken::Number(2.0),
+            ParseToken::Integer(1),
+            ParseToken::Integer(2),
             ParseToken::Add,
         ])];
         let parsed = parse(&input);
@@ -275,13 +1375,13 @@ mod tests {
         let input = "1+2\n3-4";
         let expected = vec![
             Statement::Expression(vec![
-                ParseToken::Number(1.0),
-                ParseToken::Number(2.0),
+                ParseToken::Integer(1),
+                ParseToken::Integer(2),
                 ParseToken::Add,
             ]),
             Statement::Expression(vec![
-                ParseToken::Number(3.0),
-                ParseToken::Number(4.0),
+                ParseToken::Integer(3),
+                ParseToken::Integer(4),
                 ParseToken::Subtract,
             ]),
         ];
@@ -299,7 +1399,8 @@ mod tests {
         let expected = vec![Statement::Declaration(Declaration {
             name: "var".to_string(),
             args: vec![],
-            body: vec![ParseToken::Number(3.0)],
+            body: vec![ParseToken::Integer(3)],
+            ..Declaration::default()
         })];
         let parsed = parse(&input);
         if let Ok(statements) = parsed {
@@ -314,8 +1415,10 @@ mod tests {
         let input = "f(x) = x";
         let expected = vec![Statement::Declaration(Declaration {
             name: "f".to_string(),
+            function: true,
             args: vec!["x".to_string()],
             body: vec![ParseToken::Identifier("x".to_string())],
+            ..Declaration::default()
         })];
         let parsed = parse(&input);
         if let Ok(statements) = parsed {
@@ -330,6 +1433,7 @@ mod tests {
         let input = "f(x, y, z) = x + y + z";
         let expected = vec![Statement::Declaration(Declaration {
             name: "f".to_string(),
+            function: true,
             args: vec!["x".to_string(), "y".to_string(), "z".to_string()],
             body: vec![
                 ParseToken::Identifier("x".to_string()),
@@ -338,6 +1442,7 @@ mod tests {
                 ParseToken::Identifier("z".to_string()),
                 ParseToken::Add,
             ],
+            ..Declaration::default()
         })];
         let parsed = parse(&input);
         if let Ok(statements) = parsed {
@@ -349,44 +1454,547 @@ mod tests {
     }
 
     #[test]
-    fn parses_function_call() {
-        let input = "func(x, 3)";
-        let expected = vec![Statement::Expression(vec![
-            ParseToken::Identifier("x".to_string()),
-            ParseToken::Number(3.0),
-            ParseToken::Identifier("func".to_string()),
-        ])];
-        let parsed = parse(&input);
-        if let Ok(statements) = parsed {
-            assert_eq!(statements, expected)
+    fn parses_function_call() {
+        let input = "func(x, 3)";
+        let expected = vec![Statement::Expression(vec![
+            ParseToken::Identifier("x".to_string()),
+            ParseToken::Integer(3),
+            ParseToken::Call("func".to_string(), 2),
+        ])];
+        let parsed = parse(&input);
+        if let Ok(statements) = parsed {
+            assert_eq!(statements, expected)
+        } else {
+            assert!(false)
+        }
+    }
+    #[test]
+    fn parses_function_call_with_arg_expression() {
+        let input = "func(x, 3 + 4 * 2)";
+        let expected = vec![Statement::Expression(vec![
+            // [ x 3 4 2 * + func() ]
+            ParseToken::Identifier("x".to_string()),
+            ParseToken::Integer(3),
+            ParseToken::Integer(4),
+            ParseToken::Integer(2),
+            ParseToken::Multiply,
+            ParseToken::Add,
+            ParseToken::Call("func".to_string(), 2),
+        ])];
+        let parsed = parse(&input);
+        if let Ok(statements) = parsed {
+            assert_eq!(statements, expected)
+        } else {
+            assert!(false)
+        }
+    }
+    #[test]
+    fn parses_single_argument_call() {
+        let input = "f(x) + 1";
+        let expected = vec![Statement::Expression(vec![
+            ParseToken::Identifier("x".to_string()),
+            ParseToken::Call("f".to_string(), 1),
+            ParseToken::Integer(1),
+            ParseToken::Add,
+        ])];
+        let parsed = parse(&input);
+        if let Ok(statements) = parsed {
+            assert_eq!(statements, expected)
+        } else {
+            assert!(false)
+        }
+    }
+    #[test]
+    fn parses_format_directive() {
+        let input = "#fmt sci 6\n1";
+        let expected = vec![
+            Statement::Format(PrintFormat::Sci(6)),
+            Statement::Expression(vec![ParseToken::Integer(1)]),
+        ];
+        let parsed = parse(&input);
+        if let Ok(statements) = parsed {
+            assert_eq!(statements, expected)
+        } else {
+            assert!(false)
+        }
+    }
+
+    #[test]
+    fn parses_comments_and_blank_lines() {
+        let input = "# constants\n\nvar = 3 // three\n  \n// done\nvar # print it\n";
+        let expected = vec![
+            Statement::Declaration(Declaration {
+                name: "var".to_string(),
+                args: vec![],
+                body: vec![ParseToken::Integer(3)],
+                ..Declaration::default()
+            }),
+            Statement::Expression(vec![ParseToken::Identifier("var".to_string())]),
+        ];
+        let parsed = parse(&input);
+        if let Ok(statements) = parsed {
+            assert_eq!(statements, expected)
+        } else {
+            assert!(false)
+        }
+    }
+
+    #[test]
+    fn reports_physical_line_numbers() {
+        let input = "# header\n\n1 + }";
+        let parsed = parse(&input);
+        if let Err(e) = parsed {
+            assert_eq!(e.to_string(), "on line 3")
+        } else {
+            assert!(false)
+        }
+    }
+
+    #[test]
+    fn parses_statement_across_open_parenthesis() {
+        let input = "f(1,\n  2) + (3\n  - 4)\n5";
+        let expected = vec![
+            Statement::Expression(vec![
+                ParseToken::Integer(1),
+                ParseToken::Integer(2),
+                ParseToken::Call("f".to_string(), 2),
+                ParseToken::Integer(3),
+                ParseToken::Integer(4),
+                ParseToken::Subtract,
+                ParseToken::Add,
+            ]),
+            Statement::Expression(vec![ParseToken::Integer(5)]),
+        ];
+        let parsed = parse(&input);
+        if let Ok(statements) = parsed {
+            assert_eq!(statements, expected)
+        } else {
+            assert!(false)
+        }
+    }
+
+    #[test]
+    fn parses_line_continuation() {
+        let input = "x = 1 + \\ // more below\n    2\nx";
+        let expected = vec![
+            Statement::Declaration(Declaration {
+                name: "x".to_string(),
+                args: vec![],
+                body: vec![ParseToken::Integer(1), ParseToken::Integer(2), ParseToken::Add],
+                ..Declaration::default()
+            }),
+            Statement::Expression(vec![ParseToken::Identifier("x".to_string())]),
+        ];
+        let parsed = parse(&input);
+        if let Ok(statements) = parsed {
+            assert_eq!(statements, expected)
+        } else {
+            assert!(false)
+        }
+    }
+
+    #[test]
+    fn parses_semicolon_separated_statements() {
+        let input = "x = 1; x;";
+        let expected = vec![
+            Statement::Declaration(Declaration {
+                name: "x".to_string(),
+                args: vec![],
+                body: vec![ParseToken::Integer(1)],
+                ..Declaration::default()
+            }),
+            Statement::Expression(vec![ParseToken::Identifier("x".to_string())]),
+        ];
+        let parsed = parse(&input);
+        if let Ok(statements) = parsed {
+            assert_eq!(statements, expected)
+        } else {
+            assert!(false)
+        }
+    }
+
+    #[test]
+    fn reports_line_inside_multiline_statement() {
+        let input = "f(1,\n  2 }";
+        let parsed = parse(&input);
+        if let Err(e) = parsed {
+            assert_eq!(e.to_string(), "on line 2")
+        } else {
+            assert!(false)
+        }
+    }
+
+    #[test]
+    fn errors_on_continuation_mid_line() {
+        assert!(parse("1 \\ + 2").is_err());
+    }
+
+    fn declaration_error(source: &str) -> CompileError {
+        let mut parsed = super::parse(source);
+        assert!(parsed.statements.is_empty(), "{source} should not parse");
+        parsed.diagnostics.remove(0).error.downcast().unwrap()
+    }
+
+    #[test]
+    fn parses_zero_parameter_function() {
+        let parsed = parse("f() = 2");
+        if let Ok(statements) = parsed {
+            assert_eq!(
+                statements,
+                vec![Statement::Declaration(Declaration {
+                    name: "f".to_string(),
+                    function: true,
+                    args: vec![],
+                    body: vec![ParseToken::Integer(2)],
+                    ..Declaration::default()
+                })]
+            )
+        } else {
+            assert!(false)
+        }
+    }
+
+    #[test]
+    fn parses_type_annotations() {
+        let parsed = parse("f(n: int, x): float = n * x");
+        let expected = Declaration {
+            name: "f".to_string(),
+            function: true,
+            args: vec!["n".to_string(), "x".to_string()],
+            annotations: HashMap::from([("n".to_string(), Type::Long)]),
+            return_type: Some(Type::Double),
+            body: vec![
+                ParseToken::Identifier("n".to_string()),
+                ParseToken::Identifier("x".to_string()),
+                ParseToken::Multiply,
+            ],
+        };
+        if let Ok(statements) = parsed {
+            assert_eq!(statements, vec![Statement::Declaration(expected)])
+        } else {
+            assert!(false)
+        }
+        assert!(matches!(
+            declaration_error("f(x: double) = x"),
+            CompileError::UnknownType(name) if name == "double"
+        ));
+        assert!(matches!(
+            declaration_error("f(x): = x"),
+            CompileError::MissingType
+        ));
+    }
+
+    #[test]
+    fn parses_function_annotations() {
+        let parsed = parse("g(f: (float, int) -> bool) = f(1, 2)");
+        let function = Type::Function(vec![Type::Double, Type::Long], Box::new(Type::Bool));
+        if let Ok(statements) = parsed {
+            let Statement::Declaration(declaration) = &statements[0] else {
+                panic!("expected a declaration");
+            };
+            assert_eq!(declaration.annotations["f"], function)
+        } else {
+            assert!(false)
+        }
+        assert!(matches!(
+            declaration_error("g(f: (float)) = f(1)"),
+            CompileError::MissingType
+        ));
+    }
+
+    #[test]
+    fn rejects_expressions_on_left_side() {
+        for source in ["f(x+1) = 2", "f x y = 1", "f(g(x)) = x", "f(x) y = 1", "f(x,) = x"] {
+            assert!(
+                matches!(declaration_error(source), CompileError::ExpressionAssignment),
+                "{source}"
+            );
+        }
+        assert!(matches!(
+            declaration_error("3 = x"),
+            CompileError::InvalidAssignment
+        ));
+        assert!(matches!(
+            declaration_error("= x"),
+            CompileError::InvalidAssignment
+        ));
+        assert!(matches!(
+            declaration_error("f(x = x"),
+            CompileError::UnclosedParen
+        ));
+    }
+
+    #[test]
+    fn rejects_duplicate_parameters() {
+        assert!(matches!(
+            declaration_error("f(x, y, x) = x"),
+            CompileError::DuplicateParameter(name) if name == "x"
+        ));
+    }
+
+    #[test]
+    fn rejects_multiple_assignments() {
+        let mut parsed = super::parse("x = y = 1");
+        assert_eq!(parsed.diagnostics[0].span.column, 7);
+        assert!(matches!(
+            parsed.diagnostics.remove(0).error.downcast().unwrap(),
+            CompileError::MultipleAssignment
+        ));
+    }
+
+    #[test]
+    fn parses_let_inside_assignments() {
+        let parsed = super::parse("f(x) = let t = x in t");
+        assert!(parsed.diagnostics.is_empty());
+        assert!(matches!(
+            &parsed.statements[0],
+            Statement::Declaration(Declaration { name, .. }) if name == "f"
+        ));
+    }
+
+    #[test]
+    fn parses_blocks_as_lets() {
+        let one_line = super::parse("f(x) = { a = x * 2; b = { c = a; c }; a * b }");
+        let lines =
+            super::parse("f(x) = {\n    a = x * 2;  b = { c = a\n\n c }\n\n    a * b\n}");
+        let expected = vec![Statement::Declaration(Declaration {
+            name: "f".to_string(),
+            function: true,
+            args: vec!["x".to_string()],
+            body: vec![
+                ParseToken::Identifier("x".to_string()),
+                ParseToken::Integer(2),
+                ParseToken::Multiply,
+                ParseToken::Bind("a".to_string()),
+                ParseToken::Identifier("a".to_string()),
+                ParseToken::Bind("c".to_string()),
+                ParseToken::Identifier("c".to_string()),
+                ParseToken::EndBind,
+                ParseToken::Bind("b".to_string()),
+                ParseToken::Identifier("a".to_string()),
+                ParseToken::Identifier("b".to_string()),
+                ParseToken::Multiply,
+                ParseToken::EndBind,
+                ParseToken::EndBind,
+            ],
+            ..Declaration::default()
+        })];
+        assert_eq!(one_line.statements, expected);
+        assert!(lines.diagnostics.is_empty());
+        assert_eq!(lines.statements, expected);
+        // a blank line still ends a parenthesis left open inside a block
+        let unclosed = super::parse("f(x) = { a = (x\n\nf(1)");
+        assert_eq!(unclosed.diagnostics.len(), 1);
+        assert!(matches!(
+            unclosed.diagnostics[0].error.downcast_ref(),
+            Some(CompileError::UnclosedBrace)
+        ));
+        assert_eq!(unclosed.statements.len(), 1);
+    }
+
+    #[test]
+    fn rejects_malformed_blocks() {
+        let errors: Vec<CompileError> = super::parse("{ a = 1 }\n{ 1; 2 }\n1 }\n{ }\n{ a = ; 1 }")
+            .diagnostics
+            .into_iter()
+            .map(|diagnostic| diagnostic.error.downcast().unwrap())
+            .collect();
+        assert!(matches!(
+            errors.as_slice(),
+            [
+                CompileError::MissingBlockValue,
+                CompileError::BlockExpression,
+                CompileError::UnexpectedCloseBrace,
+                CompileError::MissingBlockValue,
+                CompileError::EmptyExpression,
+            ]
+        ));
+    }
+
+    #[test]
+    fn parses_imports() {
+        let parsed = super::parse(
+            "import \"lib/geo.nms\"\nfrom \"geo.nms\" import dist, area\nfrom std.stats import mean\ngeo.dist(1, 2)",
+        );
+        let expected = vec![
+            Statement::Import(Import {
+                path: "lib/geo.nms".to_string(),
+                std: false,
+                names: None,
+            }),
+            Statement::Import(Import {
+                path: "geo.nms".to_string(),
+                std: false,
+                names: Some(vec!["dist".to_string(), "area".to_string()]),
+            }),
+            Statement::Import(Import {
+                path: "std.stats".to_string(),
+                std: true,
+                names: Some(vec!["mean".to_string()]),
+            }),
+            Statement::Expression(vec![
+                ParseToken::Integer(1),
+                ParseToken::Integer(2),
+                ParseToken::Call("geo.dist".to_string(), 2),
+            ]),
+        ];
+        assert!(parsed.diagnostics.is_empty());
+        assert_eq!(parsed.statements, expected);
+        let errors: Vec<CompileError> = super::parse(
+            "import geo\nfrom \"geo.nms\" import\nfrom \"geo.nms\" import a,\nimport \"geo.nms\ngeo.",
+        )
+        .diagnostics
+        .into_iter()
+        .map(|diagnostic| diagnostic.error.downcast().unwrap())
+        .collect();
+        assert!(matches!(
+            errors.as_slice(),
+            [
+                CompileError::InvalidImport,
+                CompileError::InvalidImport,
+                CompileError::InvalidImport,
+                CompileError::UnterminatedString,
+                CompileError::InvalidNumber(..),
+            ]
+        ));
+    }
+
+    #[test]
+    fn recovers_at_next_statement() {
+        let parsed = super::parse("1 +\nx = 2\ny = 3 $ 4\nx; (2 * ; x");
+        let expected = vec![
+            Statement::Declaration(Declaration {
+                name: "x".to_string(),
+                args: vec![],
+                body: vec![ParseToken::Integer(2)],
+                ..Declaration::default()
+            }),
+            Statement::Expression(vec![ParseToken::Identifier("x".to_string())]),
+            Statement::Expression(vec![ParseToken::Identifier("x".to_string())]),
+        ];
+        assert_eq!(parsed.statements, expected);
+        let spans: Vec<(usize, usize)> = parsed
+            .diagnostics
+            .iter()
+            .map(|d| (d.span.line, d.span.column))
+            .collect();
+        assert_eq!(spans, vec![(1, 3), (3, 7), (4, 4)]);
+        assert_eq!(parsed.failed_declarations, vec!["y".to_string()]);
+    }
+
+    #[test]
+    fn blank_line_ends_unclosed_statement() {
+        let parsed = super::parse("f(1,\n\n2");
+        assert_eq!(
+            parsed.statements,
+            vec![Statement::Expression(vec![ParseToken::Integer(2)])]
+        );
+        assert!(matches!(
+            parsed.diagnostics[0].error.downcast_ref(),
+            Some(CompileError::UnclosedParen)
+        ));
+        assert_eq!(parsed.diagnostics[0].span.column, 2);
+    }
+
+    #[test]
+    fn lex_records_spans() {
+        let tokens = lex("ab + 12", 3).unwrap();
+        let spans: Vec<(usize, usize, usize)> = tokens
+            .iter()
+            .map(|t| (t.span.line, t.span.column, t.span.end_column))
+            .collect();
+        assert_eq!(spans, vec![(3, 1, 3), (3, 4, 5), (3, 6, 8)]);
+    }
+
+    #[test]
+    fn tokenize_trailing_comment() {
+        let source = "1 / 2 // half";
+        let expected = vec![
+            ParseToken::Integer(1),
+            ParseToken::Divide,
+            ParseToken::Integer(2),
+        ];
+
+        let tokenized = tokenize(&source);
+        if let Ok(tokens) = tokenized {
+            assert_eq!(expected, tokens)
+        } else {
+            assert!(false);
+        }
+    }
+
+    #[test]
+    fn tokenize_single_number() {
+        let source = "1";
+        let expected = vec![ParseToken::Integer(1)];
+
+        let tokenized = tokenize(&source);
+        if let Ok(tokens) = tokenized {
+            assert_eq!(expected, tokens)
+        } else {
+            assert!(false);
+        }
+    }
+
+    #[test]
+    fn tokenize_decimal_number() {
+        let source = "1.0";
+        let expected = vec![ParseToken::Number(1.0)];
+
+        let tokenized = tokenize(&source);
+        if let Ok(tokens) = tokenized {
+            assert_eq!(expected, tokens)
+        } else {
+            assert!(false);
+        }
+    }
+
+    #[test]
+    fn tokenize_scientific_notation() {
+        let source = "6.022e23 1e-9 1E+5 2.5e3";
+        let expected = vec![
+            ParseToken::Number(6.022e23),
+            ParseToken::Number(1e-9),
+            ParseToken::Number(1e5),
+            ParseToken::Number(2500.0),
+        ];
+
+        let tokenized = tokenize(&source);
+        if let Ok(tokens) = tokenized {
+            assert_eq!(expected, tokens)
         } else {
-            assert!(false)
+            assert!(false);
         }
     }
+
     #[test]
-    fn parses_function_call_with_arg_expression() {
-        let input = "func(x, 3 + 4 * 2)";
-        let expected = vec![Statement::Expression(vec![
-            // [ x 3 4 2 * + func() ]
-            ParseToken::Identifier("x".to_string()),
-            ParseToken::Number(3.0),
-            ParseToken::Number(4.0),
-            ParseToken::Number(2.0),
-            ParseToken::Multiply,
+    fn tokenize_digit_separators() {
+        let source = "1_000_000 + 0.000_1 + 1e1_0";
+        let expected = vec![
+            ParseToken::Integer(1000000),
             ParseToken::Add,
-            ParseToken::Identifier("func".to_string()),
-        ])];
-        let parsed = parse(&input);
-        if let Ok(statements) = parsed {
-            assert_eq!(statements, expected)
+            ParseToken::Number(0.0001),
+            ParseToken::Add,
+            ParseToken::Number(1e10),
+        ];
+
+        let tokenized = tokenize(&source);
+        if let Ok(tokens) = tokenized {
+            assert_eq!(expected, tokens)
         } else {
-            assert!(false)
+            assert!(false);
         }
     }
+
     #[test]
-    fn tokenize_single_number() {
-        let source = "1";
-        let expected = vec![ParseToken::Number(1.0)];
+    fn tokenize_hex_and_binary() {
+        let source = "0xFF 0Xff_ff 0b1010 0B1_0";
+        let expected = vec![
+            ParseToken::Integer(255),
+            ParseToken::Integer(65535),
+            ParseToken::Integer(10),
+            ParseToken::Integer(2),
+        ];
 
         let tokenized = tokenize(&source);
         if let Ok(tokens) = tokenized {
@@ -397,9 +2005,34 @@ mod tests {
     }
 
     #[test]
-    fn tokenize_decimal_number() {
-        let source = "1.0";
-        let expected = vec![ParseToken::Number(1.0)];
+    fn tokenize_integers_and_remainder() {
+        let source = "7 % 2.5 + 9_223_372_036_854_775_807";
+        let expected = vec![
+            ParseToken::Integer(7),
+            ParseToken::Remainder,
+            ParseToken::Number(2.5),
+            ParseToken::Add,
+            ParseToken::Integer(i64::MAX),
+        ];
+
+        let tokenized = tokenize(&source);
+        if let Ok(tokens) = tokenized {
+            assert_eq!(expected, tokens)
+        } else {
+            assert!(false);
+        }
+        assert!(tokenize("9223372036854775808").is_err());
+        assert!(tokenize("0x1_0000_0000_0000_0000").is_err());
+    }
+
+    #[test]
+    fn tokenize_numbers_starting_with_nine() {
+        let source = "9 + 99.5";
+        let expected = vec![
+            ParseToken::Integer(9),
+            ParseToken::Add,
+            ParseToken::Number(99.5),
+        ];
 
         let tokenized = tokenize(&source);
         if let Ok(tokens) = tokenized {
@@ -409,6 +2042,17 @@ mod tests {
         }
     }
 
+    #[test]
+    fn tokenize_errors_malformed_literals() {
+        let sources = [
+            "1e", "1e+", "1..2", "0xG", "0x", "0b102", "1_", "1__0", "1._5", "12abc", ".",
+        ];
+        for source in sources {
+            let tokenized = tokenize(source);
+            assert!(tokenized.is_err(), "{source} should not tokenize");
+        }
+    }
+
     #[test]
     fn tokenize_single_identifier() {
         let source = "var";
@@ -426,9 +2070,9 @@ mod tests {
     fn tokenize_1_plus_2() {
         let source = "1+2";
         let expected = vec![
-            ParseToken::Number(1.0),
+            ParseToken::Integer(1),
             ParseToken::Add,
-            ParseToken::Number(2.0),
+            ParseToken::Integer(2),
         ];
 
         let tokenized = tokenize(&source);
@@ -443,11 +2087,11 @@ mod tests {
     fn tokenize_with_whitespace() {
         let source = "1 +  2 -\t3";
         let expected = vec![
-            ParseToken::Number(1.0),
+            ParseToken::Integer(1),
             ParseToken::Add,
-            ParseToken::Number(2.0),
+            ParseToken::Integer(2),
             ParseToken::Subtract,
-            ParseToken::Number(3.0),
+            ParseToken::Integer(3),
         ];
 
         let tokenized = tokenize(&source);
@@ -462,17 +2106,43 @@ mod tests {
     fn tokenize_all_operators() {
         let source = "1+2-3*4/5^6";
         let expected = vec![
-            ParseToken::Number(1.0),
+            ParseToken::Integer(1),
             ParseToken::Add,
-            ParseToken::Number(2.0),
+            ParseToken::Integer(2),
             ParseToken::Subtract,
-            ParseToken::Number(3.0),
+            ParseToken::Integer(3),
             ParseToken::Multiply,
-            ParseToken::Number(4.0),
+            ParseToken::Integer(4),
             ParseToken::Divide,
-            ParseToken::Number(5.0),
+            ParseToken::Integer(5),
             ParseToken::Exponent,
-            ParseToken::Number(6.0),
+            ParseToken::Integer(6),
+        ];
+
+        let tokenized = tokenize(&source);
+        if let Ok(tokens) = tokenized {
+            assert_eq!(expected, tokens)
+        } else {
+            assert!(false);
+        }
+    }
+
+    #[test]
+    fn tokenize_comparisons_and_logic() {
+        let source = "a<=1 and not b!=c or d==e";
+        let expected = vec![
+            ParseToken::Identifier("a".to_string()),
+            ParseToken::LessEqual,
+            ParseToken::Integer(1),
+            ParseToken::And,
+            ParseToken::Not,
+            ParseToken::Identifier("b".to_string()),
+            ParseToken::NotEqual,
+            ParseToken::Identifier("c".to_string()),
+            ParseToken::Or,
+            ParseToken::Identifier("d".to_string()),
+            ParseToken::Equal,
+            ParseToken::Identifier("e".to_string()),
         ];
 
         let tokenized = tokenize(&source);
@@ -500,12 +2170,12 @@ mod tests {
     fn tokenize_parenthesis_operation() {
         let source = "1+(2-3)";
         let expected = vec![
-            ParseToken::Number(1.0),
+            ParseToken::Integer(1),
             ParseToken::Add,
             ParseToken::OpenParen,
-            ParseToken::Number(2.0),
+            ParseToken::Integer(2),
             ParseToken::Subtract,
-            ParseToken::Number(3.0),
+            ParseToken::Integer(3),
             ParseToken::CloseParen,
         ];
 
@@ -558,9 +2228,9 @@ mod tests {
         let expected = vec![
             ParseToken::Identifier("f".to_string()),
             ParseToken::OpenParen,
-            ParseToken::Number(1.0),
+            ParseToken::Integer(1),
             ParseToken::Add,
-            ParseToken::Number(2.0),
+            ParseToken::Integer(2),
             ParseToken::Comma,
             ParseToken::Identifier("g".to_string()),
             ParseToken::OpenParen,
@@ -588,7 +2258,7 @@ mod tests {
 
     #[test]
     fn tokenize_errors_invalid_character() {
-        let source = "1+}3";
+        let source = "1+$3";
         let tokenized = tokenize(&source);
         assert!(tokenized.is_err())
     }
@@ -703,7 +2373,7 @@ mod tests {
             ParseToken::Number(1.0),
             ParseToken::Identifier("x".to_string()),
             ParseToken::Identifier("y".to_string()),
-            ParseToken::Identifier("f".to_string()),
+            ParseToken::Call("f".to_string(), 2),
             ParseToken::Number(3.0),
             ParseToken::Subtract,
             ParseToken::Add,
@@ -722,32 +2392,286 @@ mod tests {
             // [f ( 1 + 2 , 3 - 4 / 5 ) + 6]
             ParseToken::Identifier("f".to_string()),
             ParseToken::OpenParen,
-            ParseToken::Number(1.0),
+            ParseToken::Integer(1),
             ParseToken::Add,
-            ParseToken::Number(2.0),
+            ParseToken::Integer(2),
             ParseToken::Comma,
-            ParseToken::Number(3.0),
+            ParseToken::Integer(3),
             ParseToken::Subtract,
-            ParseToken::Number(4.0),
+            ParseToken::Integer(4),
             ParseToken::Divide,
-            ParseToken::Number(5.0),
+            ParseToken::Integer(5),
             ParseToken::CloseParen,
             ParseToken::Add,
-            ParseToken::Number(6.0),
+            ParseToken::Integer(6),
         ];
         let expected = vec![
             // [1 2 + , 3 4 5 / - f() 6 +]
-            ParseToken::Number(1.0),
-            ParseToken::Number(2.0),
+            ParseToken::Integer(1),
+            ParseToken::Integer(2),
             ParseToken::Add,
-            ParseToken::Number(3.0),
-            ParseToken::Number(4.0),
-            ParseToken::Number(5.0),
+            ParseToken::Integer(3),
+            ParseToken::Integer(4),
+            ParseToken::Integer(5),
             ParseToken::Divide,
             ParseToken::Subtract,
-            ParseToken::Identifier("f".to_string()),
-            ParseToken::Number(6.0),
+            ParseToken::Call("f".to_string(), 2),
+            ParseToken::Integer(6),
+            ParseToken::Add,
+        ];
+        let result = infix_to_rpn(input);
+        if let Ok(output) = result {
+            assert_eq!(output, expected)
+        } else {
+            assert!(false)
+        }
+    }
+
+    fn rpn_error(source: &str) -> CompileError {
+        let tokens = tokenize(source).unwrap();
+        let error = infix_to_rpn(tokens).expect_err(source);
+        error.downcast::<CompileError>().unwrap()
+    }
+
+    #[test]
+    fn rpn_conversion_unary_minus() {
+        let input = tokenize("-2 ^ 2 * -x").unwrap();
+        let expected = vec![
+            ParseToken::Integer(2),
+            ParseToken::Integer(2),
+            ParseToken::Exponent,
+            ParseToken::Negate,
+            ParseToken::Identifier("x".to_string()),
+            ParseToken::Negate,
+            ParseToken::Multiply,
+        ];
+        let result = infix_to_rpn(input);
+        if let Ok(output) = result {
+            assert_eq!(output, expected)
+        } else {
+            assert!(false)
+        }
+    }
+
+    #[test]
+    fn rpn_conversion_short_circuits() {
+        let input = tokenize("x > 1 or not y and true").unwrap();
+        let expected = vec![
+            ParseToken::Identifier("x".to_string()),
+            ParseToken::Integer(1),
+            ParseToken::Greater,
+            ParseToken::OrElse,
+            ParseToken::Identifier("y".to_string()),
+            ParseToken::Not,
+            ParseToken::AndThen,
+            ParseToken::Boolean(true),
+            ParseToken::And,
+            ParseToken::Or,
+        ];
+        let result = infix_to_rpn(input);
+        if let Ok(output) = result {
+            assert_eq!(output, expected)
+        } else {
+            assert!(false)
+        }
+    }
+
+    #[test]
+    fn rpn_errors_unclosed_paren() {
+        assert!(matches!(rpn_error("(1 + 2"), CompileError::UnclosedParen));
+        assert!(matches!(rpn_error("f(1, (2)"), CompileError::UnclosedParen));
+    }
+
+    #[test]
+    fn rpn_errors_unexpected_close_paren() {
+        assert!(matches!(rpn_error("1 + 2)"), CompileError::UnexpectedCloseParen));
+        assert!(matches!(rpn_error(")"), CompileError::UnexpectedCloseParen));
+    }
+
+    #[test]
+    fn rpn_errors_missing_operand() {
+        assert!(matches!(
+            rpn_error("1 + * 2"),
+            CompileError::MissingOperand(ParseToken::Multiply)
+        ));
+        assert!(matches!(
+            rpn_error("()"),
+            CompileError::MissingOperand(ParseToken::CloseParen)
+        ));
+    }
+
+    #[test]
+    fn rpn_errors_trailing_operator() {
+        assert!(matches!(
+            rpn_error("1 +"),
+            CompileError::TrailingOperator(ParseToken::Add)
+        ));
+        assert!(matches!(
+            rpn_error("(2 ^)"),
+            CompileError::TrailingOperator(ParseToken::Exponent)
+        ));
+    }
+
+    #[test]
+    fn rpn_errors_empty_argument() {
+        assert!(matches!(rpn_error("f(,)"), CompileError::EmptyArgument));
+        assert!(matches!(rpn_error("f(1,)"), CompileError::EmptyArgument));
+        assert!(matches!(rpn_error("f(1,,2)"), CompileError::EmptyArgument));
+    }
+
+    #[test]
+    fn rpn_errors_missing_operator() {
+        assert!(matches!(rpn_error("1 2"), CompileError::MissingOperator(_)));
+        assert!(matches!(rpn_error("2(3)"), CompileError::MissingOperator(_)));
+    }
+
+    #[test]
+    fn rpn_allows_zero_argument_call() {
+        let input = tokenize("f() + 1").unwrap();
+        let expected = vec![
+            ParseToken::Call("f".to_string(), 0),
+            ParseToken::Integer(1),
+            ParseToken::Add,
+        ];
+        let result = infix_to_rpn(input);
+        if let Ok(output) = result {
+            assert_eq!(output, expected)
+        } else {
+            assert!(false)
+        }
+    }
+
+    #[test]
+    fn rpn_conversion_vectors_and_indexing() {
+        let input = tokenize("[1, x * 2][i] + f([])[0]").unwrap();
+        let expected = vec![
+            ParseToken::Integer(1),
+            ParseToken::Identifier("x".to_string()),
+            ParseToken::Integer(2),
+            ParseToken::Multiply,
+            ParseToken::Vector(2),
+            ParseToken::Identifier("i".to_string()),
+            ParseToken::Index,
+            ParseToken::Vector(0),
+            ParseToken::Call("f".to_string(), 1),
+            ParseToken::Integer(0),
+            ParseToken::Index,
+            ParseToken::Add,
+        ];
+        let result = infix_to_rpn(input);
+        if let Ok(output) = result {
+            assert_eq!(output, expected)
+        } else {
+            assert!(false)
+        }
+    }
+
+    #[test]
+    fn rpn_conversion_matrix_product() {
+        let input = tokenize("a + [[1], [2]] @ b * 2").unwrap();
+        let expected = vec![
+            ParseToken::Identifier("a".to_string()),
+            ParseToken::Integer(1),
+            ParseToken::Vector(1),
+            ParseToken::Integer(2),
+            ParseToken::Vector(1),
+            ParseToken::Vector(2),
+            ParseToken::Identifier("b".to_string()),
+            ParseToken::MatrixMultiply,
+            ParseToken::Integer(2),
+            ParseToken::Multiply,
+            ParseToken::Add,
+        ];
+        let result = infix_to_rpn(input);
+        if let Ok(output) = result {
+            assert_eq!(output, expected)
+        } else {
+            assert!(false)
+        }
+    }
+
+    #[test]
+    fn rpn_conversion_loops() {
+        let input = tokenize("sum(i, 1, n, i * 2) + sum(v)").unwrap();
+        let expected = vec![
+            ParseToken::Integer(1),
+            ParseToken::Identifier("n".to_string()),
+            ParseToken::Loop("i".to_string()),
+            ParseToken::Identifier("i".to_string()),
+            ParseToken::Integer(2),
+            ParseToken::Multiply,
+            ParseToken::EndLoop(Accumulator::Sum),
+            ParseToken::Identifier("v".to_string()),
+            ParseToken::Call("sum".to_string(), 1),
+            ParseToken::Add,
+        ];
+        let result = infix_to_rpn(input);
+        if let Ok(output) = result {
+            assert_eq!(output, expected)
+        } else {
+            assert!(false)
+        }
+        assert!(matches!(
+            rpn_error("prod(k, 1, 5)"),
+            CompileError::ArgumentCount(name, 4, 3) if name == "prod"
+        ));
+    }
+
+    #[test]
+    fn rpn_conversion_lambdas() {
+        let input = tokenize("map(v, x -> x ^ 2) + f((a, b) -> a * b, () -> 1)").unwrap();
+        let expected = vec![
+            ParseToken::Identifier("v".to_string()),
+            ParseToken::Lambda(vec!["x".to_string()]),
+            ParseToken::Identifier("x".to_string()),
+            ParseToken::Integer(2),
+            ParseToken::Exponent,
+            ParseToken::EndLambda,
+            ParseToken::Call("map".to_string(), 2),
+            ParseToken::Lambda(vec!["a".to_string(), "b".to_string()]),
+            ParseToken::Identifier("a".to_string()),
+            ParseToken::Identifier("b".to_string()),
+            ParseToken::Multiply,
+            ParseToken::EndLambda,
+            ParseToken::Lambda(vec![]),
+            ParseToken::Integer(1),
+            ParseToken::EndLambda,
+            ParseToken::Call("f".to_string(), 2),
+            ParseToken::Add,
+        ];
+        let result = infix_to_rpn(input);
+        if let Ok(output) = result {
+            assert_eq!(output, expected)
+        } else {
+            assert!(false)
+        }
+        assert!(matches!(rpn_error("x ->"), CompileError::EmptyExpression));
+    }
+
+    #[test]
+    fn rpn_conversion_let() {
+        let input = tokenize("f(let t = x + 1 in let t = t * 2 in t ^ 2, (let a = 1 in a) - 1)").unwrap();
+        let expected = vec![
+            ParseToken::Identifier("x".to_string()),
+            ParseToken::Integer(1),
             ParseToken::Add,
+            ParseToken::Bind("t".to_string()),
+            ParseToken::Identifier("t".to_string()),
+            ParseToken::Integer(2),
+            ParseToken::Multiply,
+            ParseToken::Bind("t".to_string()),
+            ParseToken::Identifier("t".to_string()),
+            ParseToken::Integer(2),
+            ParseToken::Exponent,
+            ParseToken::EndBind,
+            ParseToken::EndBind,
+            ParseToken::Integer(1),
+            ParseToken::Bind("a".to_string()),
+            ParseToken::Identifier("a".to_string()),
+            ParseToken::EndBind,
+            ParseToken::Integer(1),
+            ParseToken::Subtract,
+            ParseToken::Call("f".to_string(), 2),
         ];
         let result = infix_to_rpn(input);
         if let Ok(output) = result {
@@ -755,5 +2679,29 @@ mod tests {
         } else {
             assert!(false)
         }
+        assert!(matches!(rpn_error("(let t = 1) * t"), CompileError::LetWithoutIn));
+        assert!(matches!(rpn_error("let t = 1"), CompileError::LetWithoutIn));
+        assert!(matches!(rpn_error("let t + 1 in t"), CompileError::LetWithoutValue));
+        assert!(matches!(rpn_error("let 2 = 1 in 2"), CompileError::InvalidAssignment));
+        assert!(matches!(
+            rpn_error("1 in 2"),
+            CompileError::UnexpectedToken(ParseToken::In)
+        ));
+    }
+
+    #[test]
+    fn rpn_errors_brackets() {
+        assert!(matches!(rpn_error("[1, 2"), CompileError::UnclosedBracket));
+        assert!(matches!(rpn_error("(1]"), CompileError::UnexpectedCloseBracket));
+        assert!(matches!(rpn_error("[1)"), CompileError::UnexpectedCloseParen));
+        assert!(matches!(rpn_error("[1,]"), CompileError::EmptyArgument));
+        assert!(matches!(
+            rpn_error("v[]"),
+            CompileError::MissingOperand(ParseToken::CloseBracket)
+        ));
+        assert!(matches!(
+            rpn_error("v[1, 2]"),
+            CompileError::UnexpectedToken(ParseToken::Comma)
+        ));
     }
 }